@@ -0,0 +1,269 @@
+use bevy::prelude::*;
+
+use crate::{
+    block::{BlockId, Tint},
+    noise::fractal_noise,
+};
+
+/// The grass color at each corner of the climate space, as (cold, hot) x (dry, wet)
+const GRASS_COLORS: [[[u8; 3]; 2]; 2] = [
+    [[0x80, 0xB4, 0x97], [0x6A, 0xA8, 0x5E]],
+    [[0xBF, 0xB7, 0x55], [0x59, 0xC9, 0x3C]],
+];
+/// The foliage color at each corner of the climate space, as (cold, hot) x (dry, wet)
+const FOLIAGE_COLORS: [[[u8; 3]; 2]; 2] = [
+    [[0x60, 0xA1, 0x7B], [0x48, 0x8A, 0x45]],
+    [[0xAE, 0xA4, 0x2A], [0x30, 0xBB, 0x0B]],
+];
+
+/// The index of a biome in a [`BiomeMap`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub struct BiomeId(pub u16);
+
+/// A kind of region of the world, found wherever the climate is closest to its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    pub name: String,
+    /// The temperature and humidity, each in `[0, 1]`, at which this biome is found
+    pub climate: Vec2,
+    /// The block at the top of each column of terrain
+    pub surface: BlockId,
+    /// The blocks just below the surface, above the stone
+    pub subsurface: BlockId,
+    /// The average height of the surface
+    pub base_height: f32,
+    /// How far the surface rises and falls from its average height
+    pub amplitude: f32,
+    /// The chance of each surface block growing a tree
+    pub tree_density: f32,
+    /// The sRGB color grass is tinted with
+    pub grass_color: [u8; 3],
+}
+
+/// The climate of the world, from which its biomes and the tint of grass and foliage are sampled
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct BiomeMap {
+    pub seed: u32,
+    /// The approximate size, in blocks, of regions of similar climate
+    pub climate_scale: f32,
+    /// How far apart in climate biomes blend into each other, as a distance in temperature and
+    /// humidity
+    pub blend_width: f32,
+    biomes: Vec<Biome>,
+}
+
+impl Default for BiomeMap {
+    #[inline]
+    fn default() -> Self {
+        BiomeMap {
+            seed: 0,
+            climate_scale: 128.0,
+            blend_width: 0.08,
+            biomes: Vec::new(),
+        }
+    }
+}
+
+impl BiomeMap {
+    /// Add a biome to the world
+    #[allow(clippy::result_unit_err)]
+    pub fn add_biome(&mut self, biome: Biome) -> Result<BiomeId, ()> {
+        let id = BiomeId(self.biomes.len().try_into().map_err(|_| ())?);
+        self.biomes.push(biome);
+        Ok(id)
+    }
+
+    /// Get a biome
+    #[inline]
+    pub fn get(&self, id: BiomeId) -> &Biome {
+        &self.biomes[id.0 as usize]
+    }
+
+    /// Iterate over the biomes of the world
+    #[inline]
+    pub fn biomes(&self) -> impl Iterator<Item = (BiomeId, &Biome)> {
+        self.biomes
+            .iter()
+            .enumerate()
+            .map(|(ix, biome)| (BiomeId(ix as u16), biome))
+    }
+
+    /// Get the biome at a block, or `None` if the world has no biomes
+    pub fn biome(&self, position: IVec3) -> Option<BiomeId> {
+        let climate = self.climate(position);
+        self.biomes()
+            .min_by(|(_, a), (_, b)| {
+                let a = a.climate.distance_squared(climate);
+                let b = b.climate.distance_squared(climate);
+                a.total_cmp(&b)
+            })
+            .map(|(id, _)| id)
+    }
+
+    /// Get how much each biome contributes to the blended properties of a block, summing to `1`,
+    /// with biomes whose climates are about as close as the nearest blending together
+    fn weights(&self, position: IVec3) -> Vec<f32> {
+        let climate = self.climate(position);
+        let mut weights: Vec<f32> = self
+            .biomes
+            .iter()
+            .map(|biome| biome.climate.distance_squared(climate))
+            .collect();
+        let nearest = weights.iter().copied().fold(f32::INFINITY, f32::min);
+        let width = self.blend_width * self.blend_width;
+        for weight in weights.iter_mut() {
+            *weight = (-(*weight - nearest) / width).exp();
+        }
+        let norm: f32 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= norm;
+        }
+        weights
+    }
+
+    /// Get a property of the biomes at a block, blended between neighbouring biomes, or `None` if
+    /// the world has no biomes.
+    ///
+    /// As the climate varies smoothly from block to block, so do blended properties, even across
+    /// chunk borders.
+    pub fn blend(&self, position: IVec3, property: impl Fn(&Biome) -> f32) -> Option<f32> {
+        if self.biomes.is_empty() {
+            return None;
+        }
+        let weights = self.weights(position);
+        Some(
+            self.biomes
+                .iter()
+                .zip(weights)
+                .map(|(biome, weight)| weight * property(biome))
+                .sum(),
+        )
+    }
+
+    /// Get the temperature and humidity at a block, each in `[0, 1]`
+    pub fn climate(&self, position: IVec3) -> Vec2 {
+        let point = Vec2::new(position.x as f32, position.z as f32) / self.climate_scale;
+        Vec2::new(
+            fractal_noise(self.seed, point, 3),
+            fractal_noise(self.seed ^ 0x5EED_0001, point, 3),
+        )
+    }
+
+    /// Get the color of grass at a block, blended from its biomes, or sampled from the climate
+    /// if the world has no biomes
+    pub fn grass_color(&self, position: IVec3) -> Color {
+        if self.biomes.is_empty() {
+            return sample_colormap(&GRASS_COLORS, self.climate(position));
+        }
+        let color = |[r, g, b]: [u8; 3]| Vec3::new(r as f32, g as f32, b as f32) / 255.0;
+        let mixed: Vec3 = self
+            .biomes
+            .iter()
+            .zip(self.weights(position))
+            .map(|(biome, weight)| weight * color(biome.grass_color))
+            .sum();
+        Color::rgb(mixed.x, mixed.y, mixed.z)
+    }
+
+    #[inline]
+    pub fn foliage_color(&self, position: IVec3) -> Color {
+        sample_colormap(&FOLIAGE_COLORS, self.climate(position))
+    }
+
+    /// Get the color a tinted face of the block at `position` is multiplied by
+    pub fn tint_color(&self, tint: Tint, position: IVec3) -> Color {
+        match tint {
+            Tint::None => Color::WHITE,
+            Tint::Grass => self.grass_color(position),
+            Tint::Foliage => self.foliage_color(position),
+            Tint::Constant([r, g, b]) => Color::rgb_u8(r, g, b),
+        }
+    }
+}
+
+/// Bilinearly interpolate a colormap by temperature and humidity
+fn sample_colormap(colors: &[[[u8; 3]; 2]; 2], climate: Vec2) -> Color {
+    let color = |[r, g, b]: [u8; 3]| Vec3::new(r as f32, g as f32, b as f32) / 255.0;
+    let cold = color(colors[0][0]).lerp(color(colors[0][1]), climate.y);
+    let hot = color(colors[1][0]).lerp(color(colors[1][1]), climate.y);
+    let mixed = cold.lerp(hot, climate.x);
+    Color::rgb(mixed.x, mixed.y, mixed.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn biome(name: &str, climate: Vec2, base_height: f32) -> Biome {
+        Biome {
+            name: name.to_string(),
+            climate,
+            surface: BlockId::default(),
+            subsurface: BlockId::default(),
+            base_height,
+            amplitude: 4.0,
+            tree_density: 0.0,
+            grass_color: [0x40; 3],
+        }
+    }
+
+    fn map() -> BiomeMap {
+        let mut map = BiomeMap {
+            seed: 3,
+            climate_scale: 32.0,
+            ..default()
+        };
+        for (ix, climate) in [(0.2, 0.2), (0.8, 0.3), (0.4, 0.9), (0.5, 0.5)]
+            .into_iter()
+            .enumerate()
+        {
+            let climate = Vec2::from(climate);
+            map.add_biome(biome(&ix.to_string(), climate, ix as f32 * 10.0))
+                .unwrap();
+        }
+        map
+    }
+
+    fn positions() -> impl Iterator<Item = IVec3> {
+        (-200..200)
+            .step_by(7)
+            .flat_map(|x| (-200..200).step_by(11).map(move |z| IVec3::new(x, 0, z)))
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        let map = map();
+        for position in positions() {
+            let weights = map.weights(position);
+            assert_eq!(weights.len(), 4);
+            assert!(weights.iter().all(|w| (0.0..=1.0).contains(w)));
+            assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            // The biome at the block always contributes the most
+            let nearest = map.biome(position).unwrap().0 as usize;
+            assert!(weights.iter().all(|&w| w <= weights[nearest]));
+        }
+    }
+
+    #[test]
+    fn blend_stays_within_biome_values() {
+        let map = map();
+        for position in positions() {
+            let height = map.blend(position, |biome| biome.base_height).unwrap();
+            assert!((0.0..=30.0 + 1e-3).contains(&height));
+            let constant = map.blend(position, |_| 5.0).unwrap();
+            assert!((constant - 5.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn a_single_biome_takes_all_the_weight() {
+        let mut map = BiomeMap::default();
+        assert_eq!(map.blend(IVec3::ZERO, |biome| biome.base_height), None);
+        map.add_biome(biome("only", Vec2::splat(0.5), 12.0))
+            .unwrap();
+        for position in positions() {
+            assert_eq!(map.weights(position), vec![1.0]);
+            assert_eq!(map.blend(position, |biome| biome.base_height), Some(12.0));
+        }
+    }
+}
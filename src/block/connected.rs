@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use super::texture::BlockTextureId;
+
+/// Textures for a face which joins seamlessly with the faces of neighbouring blocks of the same
+/// type, such as a pane of glass.
+///
+/// Tile `i` is shown when the face connects to a neighbour on each side whose bit is set in `i`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ConnectedTextures {
+    pub tiles: [BlockTextureId; 16],
+}
+
+impl ConnectedTextures {
+    /// Connected to the neighbour above the texture
+    pub const UP: u8 = 1;
+    /// Connected to the neighbour right of the texture
+    pub const RIGHT: u8 = 2;
+    /// Connected to the neighbour below the texture
+    pub const DOWN: u8 = 4;
+    /// Connected to the neighbour left of the texture
+    pub const LEFT: u8 = 8;
+
+    #[inline]
+    pub fn new(tiles: [BlockTextureId; 16]) -> ConnectedTextures {
+        ConnectedTextures { tiles }
+    }
+
+    #[inline]
+    pub fn tile(&self, connections: u8) -> BlockTextureId {
+        self.tiles[(connections & 15) as usize]
+    }
+
+    /// Find which sides of a face connect to their neighbours.
+    ///
+    /// `directions` are the offsets to the neighbours above, right of, below and left of the
+    /// texture, and `same(offset)` tells whether the block at `offset` is of the same type. A side
+    /// only connects if the neighbour's own face is exposed, so inner corners keep their border.
+    pub fn connections(directions: [IVec3; 4], normal: IVec3, same: impl Fn(IVec3) -> bool) -> u8 {
+        directions
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, offset)| same(offset) && !same(offset + normal))
+            .fold(0, |mask, (bit, _)| mask | 1 << bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::face::{Face, FaceTransform};
+
+    /// Find the connections of the top face of the block at the origin, given the positions of
+    /// the blocks of the same type
+    fn top_connections(same: &[IVec3]) -> u8 {
+        let directions = Face::Top.texture_directions(FaceTransform::IDENTITY);
+        ConnectedTextures::connections(directions, IVec3::Y, |offset| same.contains(&offset))
+    }
+
+    #[test]
+    fn bits_follow_texture_directions() {
+        let directions = Face::Top.texture_directions(FaceTransform::IDENTITY);
+        assert_eq!(top_connections(&[]), 0);
+        for (bit, offset) in directions.into_iter().enumerate() {
+            assert_eq!(top_connections(&[offset]), 1 << bit);
+        }
+        assert_eq!(top_connections(&directions), 15);
+        assert_eq!(
+            ConnectedTextures::UP
+                | ConnectedTextures::RIGHT
+                | ConnectedTextures::DOWN
+                | ConnectedTextures::LEFT,
+            15
+        );
+    }
+
+    #[test]
+    fn covered_neighbours_do_not_connect() {
+        let directions = Face::Top.texture_directions(FaceTransform::IDENTITY);
+        let [up, right, ..] = directions;
+        // The block above `up` hides its top face, so the two faces meet at an inner corner
+        assert_eq!(
+            top_connections(&[up, right, up + IVec3::Y]),
+            ConnectedTextures::RIGHT
+        );
+    }
+
+    #[test]
+    fn unrelated_neighbours_do_not_connect() {
+        assert_eq!(top_connections(&[IVec3::Y, IVec3::NEG_Y]), 0);
+    }
+}
@@ -0,0 +1,498 @@
+use bevy::prelude::*;
+
+use super::texture::SheetCoords;
+
+/// A face of a block, in the order used to index per-face block data
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[repr(u8)]
+pub enum Face {
+    /// The `+y` face
+    #[default]
+    Top = 0,
+    /// The `-y` face
+    Bottom = 1,
+    /// The `+x` face
+    Right = 2,
+    /// The `-x` face
+    Left = 3,
+    /// The `+z` face
+    Back = 4,
+    /// The `-z` face
+    Front = 5,
+}
+
+impl Face {
+    /// All faces, in index order
+    pub const ALL: [Face; 6] = [
+        Face::Top,
+        Face::Bottom,
+        Face::Right,
+        Face::Left,
+        Face::Back,
+        Face::Front,
+    ];
+
+    /// Get the face with a given index
+    #[inline]
+    pub fn from_index(ix: usize) -> Option<Face> {
+        Self::ALL.get(ix).copied()
+    }
+
+    /// Get this face's index
+    #[inline]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Get the face on the opposite side of a block
+    #[inline]
+    pub fn opposite(self) -> Face {
+        Face::ALL[self.index() ^ 1]
+    }
+
+    /// Get the axis this face is perpendicular to
+    #[inline]
+    pub fn axis(self) -> Axis {
+        match self {
+            Face::Top | Face::Bottom => Axis::Y,
+            Face::Right | Face::Left => Axis::X,
+            Face::Back | Face::Front => Axis::Z,
+        }
+    }
+
+    /// Get the offset to the neighbouring block on this side
+    #[inline]
+    pub fn offset(self) -> IVec3 {
+        match self {
+            Face::Top => IVec3::Y,
+            Face::Bottom => IVec3::NEG_Y,
+            Face::Right => IVec3::X,
+            Face::Left => IVec3::NEG_X,
+            Face::Back => IVec3::Z,
+            Face::Front => IVec3::NEG_Z,
+        }
+    }
+
+    /// Get the outward normal of this face
+    #[inline]
+    pub fn normal(self) -> Vec3 {
+        self.offset().as_vec3()
+    }
+
+    /// Get the face whose normal is closest to a direction
+    #[inline]
+    pub fn from_direction(direction: Vec3) -> Face {
+        let abs = direction.abs();
+        if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x >= 0.0 {
+                Face::Right
+            } else {
+                Face::Left
+            }
+        } else if abs.y >= abs.z {
+            if direction.y >= 0.0 {
+                Face::Top
+            } else {
+                Face::Bottom
+            }
+        } else if direction.z >= 0.0 {
+            Face::Back
+        } else {
+            Face::Front
+        }
+    }
+
+    /// Get the corners of this face, relative to the center of a unit block
+    #[inline]
+    pub fn vertices(self) -> [[f32; 3]; 4] {
+        FACE_VERTICES[self.index()]
+    }
+
+    /// Get the texture corner mapped to each vertex of this face
+    #[inline]
+    pub fn uv_corners(self) -> [Corner; 4] {
+        FACE_UV_CORNERS[self.index()]
+    }
+
+    /// Get the triangles of this face, as indices into [`Face::vertices`]
+    #[inline]
+    pub fn triangles(self) -> [u16; 6] {
+        FACE_TRIANGLES[self.index()]
+    }
+
+    /// Get the texture coordinates, in `[0, 1]`, of a point on this face of a unit block centered
+    /// at the origin, with the face's texture transformed by `transform`
+    pub fn texture_coords(self, transform: FaceTransform, point: Vec3) -> Vec2 {
+        let mut normal_axis = Vec3::ZERO;
+        normal_axis[self.axis() as usize] = 1.0;
+        let mut uv = Vec2::ZERO;
+        for (vertex, corner) in self.vertices().into_iter().zip(self.uv_corners()) {
+            // Bilinearly interpolate between the corners within the plane of the face
+            let weights = (0.5 + 2.0 * Vec3::from(vertex) * point).max(normal_axis);
+            uv += weights.x * weights.y * weights.z * corner.transform(transform).offset();
+        }
+        uv
+    }
+
+    /// Get the offsets to the neighbouring blocks above, right of, below and left of this face's
+    /// texture, when transformed by `transform`
+    pub fn texture_directions(self, transform: FaceTransform) -> [IVec3; 4] {
+        let mut directions = [IVec3::ZERO; 4];
+        for face in Face::ALL {
+            if face.axis() == self.axis() {
+                continue;
+            }
+            // The midpoint of the edge of this face towards `face`
+            let edge = 0.5 * (self.normal() + face.normal());
+            let uv = self.texture_coords(transform, edge);
+            let side = if uv.y < 0.25 {
+                0
+            } else if uv.x > 0.75 {
+                1
+            } else if uv.y > 0.75 {
+                2
+            } else {
+                3
+            };
+            directions[side] = face.offset();
+        }
+        directions
+    }
+
+    /// Get a quarter-turn rotation taking the normal of `self` to the normal of `to`
+    pub fn rotation_to(self, to: Face) -> Mat3 {
+        let from = self.normal();
+        let target = to.normal();
+        if self == to {
+            Mat3::IDENTITY
+        } else if self == to.opposite() {
+            // Prefer flipping about the vertical axis, so horizontal faces stay upright
+            let axis = if self.axis() == Axis::Y {
+                Vec3::X
+            } else {
+                Vec3::Y
+            };
+            Mat3::from_axis_angle(axis, std::f32::consts::PI)
+        } else {
+            Mat3::from_axis_angle(from.cross(target), std::f32::consts::FRAC_PI_2)
+        }
+    }
+}
+
+/// A coordinate axis
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[repr(u8)]
+pub enum Axis {
+    X = 0,
+    #[default]
+    Y = 1,
+    Z = 2,
+}
+
+impl Axis {
+    /// All axes, in index order
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    /// Get the axis with a given index
+    #[inline]
+    pub fn from_index(ix: usize) -> Option<Axis> {
+        Self::ALL.get(ix).copied()
+    }
+
+    /// Get the face pointing along the positive direction of this axis
+    #[inline]
+    pub fn positive_face(self) -> Face {
+        match self {
+            Axis::X => Face::Right,
+            Axis::Y => Face::Top,
+            Axis::Z => Face::Back,
+        }
+    }
+}
+
+/// A corner of a texture, in clockwise order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[repr(u8)]
+pub enum Corner {
+    TopLeft = 0,
+    TopRight = 1,
+    BottomRight = 2,
+    BottomLeft = 3,
+}
+
+impl Corner {
+    /// All corners, in clockwise order
+    pub const ALL: [Corner; 4] = [
+        Corner::TopLeft,
+        Corner::TopRight,
+        Corner::BottomRight,
+        Corner::BottomLeft,
+    ];
+
+    /// Get the UV coordinates of this corner of a texture in a sheet of the given resolution
+    #[inline]
+    pub fn uv(self, coords: SheetCoords, resolution: u32) -> [f32; 2] {
+        match self {
+            Corner::TopLeft => coords.top_left(resolution),
+            Corner::TopRight => coords.top_right(resolution),
+            Corner::BottomRight => coords.bottom_right(resolution),
+            Corner::BottomLeft => coords.bottom_left(resolution),
+        }
+    }
+
+    /// Get the position of this corner within a unit texture
+    #[inline]
+    pub fn offset(self) -> Vec2 {
+        match self {
+            Corner::TopLeft => Vec2::new(0.0, 0.0),
+            Corner::TopRight => Vec2::new(1.0, 0.0),
+            Corner::BottomRight => Vec2::new(1.0, 1.0),
+            Corner::BottomLeft => Vec2::new(0.0, 1.0),
+        }
+    }
+
+    /// Get the texture corner shown at this corner after rotating the texture by `rotation`
+    #[inline]
+    pub fn rotate(self, rotation: FaceRotation) -> Corner {
+        Corner::ALL[(self as usize + rotation as usize) % 4]
+    }
+
+    /// Get the corner on the other side of a texture mirrored left-to-right
+    #[inline]
+    pub fn mirror(self) -> Corner {
+        Corner::ALL[self as usize ^ 1]
+    }
+
+    /// Get the texture corner shown at this corner after transforming the texture by `transform`
+    #[inline]
+    pub fn transform(self, transform: FaceTransform) -> Corner {
+        if transform.mirrored {
+            self.mirror().rotate(transform.rotation)
+        } else {
+            self.rotate(transform.rotation)
+        }
+    }
+}
+
+/// A rotation of a face's texture, in counter-clockwise quarter turns
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+#[repr(u8)]
+pub enum FaceRotation {
+    #[default]
+    R0 = 0,
+    R90 = 1,
+    R180 = 2,
+    R270 = 3,
+}
+
+impl FaceRotation {
+    /// All rotations, in counter-clockwise order
+    pub const ALL: [FaceRotation; 4] = [
+        FaceRotation::R0,
+        FaceRotation::R90,
+        FaceRotation::R180,
+        FaceRotation::R270,
+    ];
+
+    /// Get the rotation by a number of counter-clockwise quarter turns
+    #[inline]
+    pub fn from_quarter_turns(turns: usize) -> FaceRotation {
+        Self::ALL[turns % 4]
+    }
+
+    /// Compose this rotation with another
+    #[inline]
+    pub fn then(self, other: FaceRotation) -> FaceRotation {
+        Self::from_quarter_turns(self as usize + other as usize)
+    }
+
+    /// Get the inverse of this rotation
+    #[inline]
+    pub fn inverse(self) -> FaceRotation {
+        Self::from_quarter_turns(4 - self as usize)
+    }
+}
+
+/// A transformation of a face's texture: an optional left-to-right mirroring followed by a
+/// rotation
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub struct FaceTransform {
+    pub rotation: FaceRotation,
+    pub mirrored: bool,
+}
+
+impl FaceTransform {
+    /// The identity transformation
+    pub const IDENTITY: FaceTransform = FaceTransform {
+        rotation: FaceRotation::R0,
+        mirrored: false,
+    };
+
+    /// All transformations
+    pub const ALL: [FaceTransform; 8] = {
+        use FaceRotation::*;
+        [
+            FaceTransform::rotated(R0),
+            FaceTransform::rotated(R90),
+            FaceTransform::rotated(R180),
+            FaceTransform::rotated(R270),
+            FaceTransform::mirrored(R0),
+            FaceTransform::mirrored(R90),
+            FaceTransform::mirrored(R180),
+            FaceTransform::mirrored(R270),
+        ]
+    };
+
+    /// A rotation without mirroring
+    #[inline]
+    pub const fn rotated(rotation: FaceRotation) -> FaceTransform {
+        FaceTransform {
+            rotation,
+            mirrored: false,
+        }
+    }
+
+    /// A mirroring followed by a rotation
+    #[inline]
+    pub const fn mirrored(rotation: FaceRotation) -> FaceTransform {
+        FaceTransform {
+            rotation,
+            mirrored: true,
+        }
+    }
+
+    /// Compose this transformation with another, applying `self` first
+    #[inline]
+    pub fn then(self, other: FaceTransform) -> FaceTransform {
+        let rotation = if other.mirrored {
+            self.rotation.inverse()
+        } else {
+            self.rotation
+        };
+        FaceTransform {
+            rotation: rotation.then(other.rotation),
+            mirrored: self.mirrored != other.mirrored,
+        }
+    }
+}
+
+const FACE_VERTICES: [[[f32; 3]; 4]; 6] = [
+    [
+        [-0.5, 0.5, -0.5],
+        [0.5, 0.5, -0.5],
+        [0.5, 0.5, 0.5],
+        [-0.5, 0.5, 0.5],
+    ],
+    [
+        [0.5, -0.5, 0.5],
+        [0.5, -0.5, -0.5],
+        [-0.5, -0.5, 0.5],
+        [-0.5, -0.5, -0.5],
+    ],
+    [
+        [0.5, -0.5, -0.5],
+        [0.5, -0.5, 0.5],
+        [0.5, 0.5, 0.5],
+        [0.5, 0.5, -0.5],
+    ],
+    [
+        [-0.5, -0.5, 0.5],
+        [-0.5, -0.5, -0.5],
+        [-0.5, 0.5, 0.5],
+        [-0.5, 0.5, -0.5],
+    ],
+    [
+        [-0.5, -0.5, 0.5],
+        [-0.5, 0.5, 0.5],
+        [0.5, 0.5, 0.5],
+        [0.5, -0.5, 0.5],
+    ],
+    [
+        [-0.5, -0.5, -0.5],
+        [-0.5, 0.5, -0.5],
+        [0.5, 0.5, -0.5],
+        [0.5, -0.5, -0.5],
+    ],
+];
+
+const FACE_UV_CORNERS: [[Corner; 4]; 6] = {
+    use Corner::*;
+    [
+        [TopLeft, TopRight, BottomRight, BottomLeft],
+        [BottomLeft, TopLeft, BottomRight, TopRight],
+        [BottomRight, BottomLeft, TopLeft, TopRight],
+        [BottomRight, BottomLeft, TopRight, TopLeft],
+        [BottomLeft, TopLeft, TopRight, BottomRight],
+        [TopLeft, BottomLeft, BottomRight, TopRight],
+    ]
+};
+
+const FACE_TRIANGLES: [[u16; 6]; 6] = [
+    [0, 3, 1, 1, 3, 2],
+    [0, 2, 1, 2, 3, 1],
+    [0, 3, 1, 1, 3, 2],
+    [0, 2, 1, 2, 3, 1],
+    [0, 3, 1, 1, 3, 2],
+    [0, 1, 3, 1, 2, 3],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_are_distinct() {
+        for (i, a) in FaceTransform::ALL.into_iter().enumerate() {
+            for b in &FaceTransform::ALL[i + 1..] {
+                let corners = |t: FaceTransform| Corner::ALL.map(|c| c.transform(t));
+                assert_ne!(corners(a), corners(*b), "{a:?} and {b:?}");
+            }
+        }
+    }
+
+    /// The corner shown through a composed transformation is found by looking up the corner
+    /// through each transformation in turn
+    #[test]
+    fn then_composes_corner_lookups() {
+        for a in FaceTransform::ALL {
+            for b in FaceTransform::ALL {
+                for corner in Corner::ALL {
+                    assert_eq!(
+                        corner.transform(a.then(b)),
+                        corner.transform(a).transform(b),
+                        "{a:?} then {b:?} at {corner:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn then_is_associative_with_identity_and_inverses() {
+        for a in FaceTransform::ALL {
+            assert_eq!(a.then(FaceTransform::IDENTITY), a);
+            assert_eq!(FaceTransform::IDENTITY.then(a), a);
+            assert!(FaceTransform::ALL
+                .iter()
+                .any(|&b| a.then(b) == FaceTransform::IDENTITY));
+            for b in FaceTransform::ALL {
+                for c in FaceTransform::ALL {
+                    assert_eq!(a.then(b).then(c), a.then(b.then(c)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_compose_and_invert() {
+        for a in FaceRotation::ALL {
+            assert_eq!(a.then(a.inverse()), FaceRotation::R0);
+            for b in FaceRotation::ALL {
+                assert_eq!(
+                    FaceTransform::rotated(a).then(FaceTransform::rotated(b)),
+                    FaceTransform::rotated(a.then(b))
+                );
+            }
+        }
+    }
+}
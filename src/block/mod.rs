@@ -1,40 +1,132 @@
-use bevy::prelude::*;
+use std::collections::BTreeSet;
+
+use bevy::{asset::HandleId, prelude::*};
 use bytemuck::{Pod, Zeroable};
 use slab::Slab;
 
-use self::texture::BlockTextureId;
+use crate::chunk::{mesher::ChunkNeedsMeshing, Chunk};
+
+use self::{
+    connected::ConnectedTextures,
+    face::{Axis, Face, FaceRotation, FaceTransform},
+    model::BlockModel,
+    state::{state_count, BlockProperty, BlockState, StateValues},
+    texture::BlockTextureId,
+    variant::{TextureVariant, TextureVariants},
+};
 
+pub mod connected;
+pub mod face;
+pub mod model;
+pub mod state;
 pub mod texture;
+pub mod variant;
 
 #[derive(Resource, Default)]
 pub struct Blocks {
-    blocks: Slab<()>,
+    blocks: Slab<BlockData>,
     meshing_data: Vec<MeshingData>,
+    types: Slab<BlockTypeData>,
+    models: Vec<ModelData>,
+    model_assets: BTreeSet<(HandleId, BlockModelId)>,
+    texture_variants: Vec<TextureVariants>,
+    connected_textures: Vec<ConnectedTextures>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ModelData {
+    model: BlockModel,
+    asset: Option<Handle<BlockModel>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct BlockData {
+    block_type: BlockTypeId,
+    state: BlockState,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct BlockTypeData {
+    properties: Vec<BlockProperty>,
+    states: Vec<BlockId>,
 }
 
 impl Blocks {
+    /// Register a block type without any properties, returning its only state
     #[inline]
+    #[allow(clippy::result_unit_err)]
     pub fn new_block(
         &mut self,
         textures: [BlockTextureId; 6],
         opacity: u32,
         solid: bool,
     ) -> Result<BlockId, ()> {
-        let ix = self.blocks.insert(());
-        if ix >= u32::MAX as usize {
-            Err(())
-        } else {
-            let id = BlockId(ix as u32);
+        let block_type = self.new_block_type(&[], |_| MeshingData {
+            textures,
+            opacity,
+            solid,
+            ..default()
+        })?;
+        Ok(self.default_state(block_type))
+    }
+
+    /// Register a block type with the given properties, allocating a [`BlockId`] for each of its
+    /// states
+    #[allow(clippy::result_unit_err)]
+    pub fn new_block_type(
+        &mut self,
+        properties: &[BlockProperty],
+        mut meshing_data: impl FnMut(StateValues) -> MeshingData,
+    ) -> Result<BlockTypeId, ()> {
+        let count = state_count(properties).ok_or(())?;
+        if self.types.len() >= u32::MAX as usize
+            || self.blocks.len() + count as usize >= u32::MAX as usize
+        {
+            return Err(());
+        }
+        let block_type = BlockTypeId(self.types.vacant_key() as u32);
+        let mut states = Vec::with_capacity(count as usize);
+        for state in 0..count {
+            let state = BlockState(state);
+            let ix = self.blocks.insert(BlockData { block_type, state });
             while self.meshing_data.len() <= ix {
                 self.meshing_data.push(default())
             }
-            self.meshing_data[ix] = MeshingData {
-                textures,
-                opacity,
-                solid,
-            };
-            Ok(id)
+            self.meshing_data[ix] = meshing_data(StateValues::new(properties, state));
+            states.push(BlockId(ix as u32));
         }
+        self.types.insert(BlockTypeData {
+            properties: properties.into(),
+            states,
+        });
+        Ok(block_type)
+    }
+
+    /// Unregister a block type, returning the ids of its states, which may be reused by block types
+    /// registered later.
+    ///
+    /// Chunks still containing the returned blocks must have them removed, and the handlers of the
+    /// block type forgotten; [`BlockRegistries::remove_block_type`] does both.
+    ///
+    /// [`BlockRegistries::remove_block_type`]: crate::chunk::BlockRegistries::remove_block_type
+    #[allow(clippy::result_unit_err)]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) -> Result<Vec<BlockId>, ()> {
+        let data = self.types.try_remove(block_type.0 as usize).ok_or(())?;
+        for state in &data.states {
+            self.blocks.remove(state.0 as usize);
+            self.meshing_data[state.0 as usize] = default();
+        }
+        Ok(data.states)
+    }
+
+    /// Get every block showing a texture on any face, directly or through its variants or
+    /// connected textures
+    pub fn blocks_with_texture(&self, texture: BlockTextureId) -> Vec<BlockId> {
+        self.blocks
+            .iter()
+            .map(|(ix, _)| BlockId(ix as u32))
+            .filter(|&id| self.meshing_data[id.0 as usize].uses_texture(self, texture))
+            .collect()
     }
 
     #[inline]
@@ -44,13 +136,289 @@ impl Blocks {
             .copied()
             .unwrap_or_default()
     }
+
+    /// Register a new block model
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn new_model(&mut self, model: BlockModel) -> Result<BlockModelId, ()> {
+        if self.models.len() >= u32::MAX as usize {
+            return Err(());
+        }
+        let id = BlockModelId(self.models.len() as u32);
+        self.models.push(ModelData { model, asset: None });
+        Ok(id)
+    }
+
+    /// Get a block model, defaulting to a full cube
+    #[inline]
+    pub fn get_model(&self, model: BlockModelId) -> &BlockModel {
+        const CUBE: BlockModel = BlockModel::Cube;
+        self.models
+            .get(model.0 as usize)
+            .map_or(&CUBE, |data| &data.model)
+    }
+
+    /// Get whether blocks with the given meshing data are full cubes
+    #[inline]
+    pub fn is_full_cube(&self, data: &MeshingData) -> bool {
+        data.model
+            .is_none_or(|model| *self.get_model(model) == BlockModel::Cube)
+    }
+
+    /// Set a block model to be loaded from an asset, returning the previous asset if any.
+    ///
+    /// The model is updated whenever the asset is loaded or modified by [`load_block_models`].
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn set_model_asset(
+        &mut self,
+        model: BlockModelId,
+        asset: Handle<BlockModel>,
+        models: &Assets<BlockModel>,
+    ) -> Result<Option<Handle<BlockModel>>, ()> {
+        let data = self.models.get_mut(model.0 as usize).ok_or(())?;
+        if let Some(old_asset) = &data.asset {
+            self.model_assets.remove(&(old_asset.id(), model));
+        }
+        self.model_assets.insert((asset.id(), model));
+        if let Some(loaded) = models.get(&asset) {
+            data.model = loaded.clone();
+        }
+        Ok(data.asset.replace(asset))
+    }
+
+    /// Register a new set of random texture variants
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn new_texture_variants(
+        &mut self,
+        variants: impl Into<Vec<TextureVariant>>,
+    ) -> Result<TextureVariantsId, ()> {
+        if self.texture_variants.len() >= u32::MAX as usize {
+            return Err(());
+        }
+        let id = TextureVariantsId(self.texture_variants.len() as u32);
+        self.texture_variants
+            .push(TextureVariants::new(variants.into())?);
+        Ok(id)
+    }
+
+    #[inline]
+    pub fn get_texture_variants(&self, variants: TextureVariantsId) -> &TextureVariants {
+        &self.texture_variants[variants.0 as usize]
+    }
+
+    /// Register a new set of connected textures
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn new_connected_textures(
+        &mut self,
+        textures: ConnectedTextures,
+    ) -> Result<ConnectedTexturesId, ()> {
+        if self.connected_textures.len() >= u32::MAX as usize {
+            return Err(());
+        }
+        let id = ConnectedTexturesId(self.connected_textures.len() as u32);
+        self.connected_textures.push(textures);
+        Ok(id)
+    }
+
+    #[inline]
+    pub fn get_connected_textures(&self, textures: ConnectedTexturesId) -> &ConnectedTextures {
+        &self.connected_textures[textures.0 as usize]
+    }
+
+    /// Get the type of a block
+    #[inline]
+    pub fn block_type(&self, id: BlockId) -> Option<BlockTypeId> {
+        Some(self.blocks.get(id.0 as usize)?.block_type)
+    }
+
+    /// Get the property values of a block
+    #[inline]
+    pub fn state(&self, id: BlockId) -> Option<StateValues<'_>> {
+        let data = self.blocks.get(id.0 as usize)?;
+        let properties = &self.types[data.block_type.0 as usize].properties;
+        Some(StateValues::new(properties, data.state))
+    }
+
+    /// Get the default state of a block type, or the empty block if it does not exist
+    #[inline]
+    pub fn default_state(&self, block_type: BlockTypeId) -> BlockId {
+        self.get_state(block_type, BlockState::default())
+            .unwrap_or_default()
+    }
+
+    /// Get the block for a given state of a block type
+    #[inline]
+    pub fn get_state(&self, block_type: BlockTypeId, state: BlockState) -> Option<BlockId> {
+        self.types
+            .get(block_type.0 as usize)?
+            .states
+            .get(state.0 as usize)
+            .copied()
+    }
+
+    /// Get a block with one of its properties changed
+    #[inline]
+    pub fn with_property(
+        &self,
+        id: BlockId,
+        property: BlockProperty,
+        value: u16,
+    ) -> Option<BlockId> {
+        let data = self.blocks.get(id.0 as usize)?;
+        let state = self.state(id)?.with(property, value)?;
+        self.get_state(data.block_type, state)
+    }
+
+    /// Get the state of a block type to place against the `clicked` face of another block, while
+    /// looking along `look`
+    pub fn placement_state(&self, block_type: BlockTypeId, clicked: Face, look: Vec3) -> BlockId {
+        let mut id = self.default_state(block_type);
+        let facing = Face::from_direction(-look);
+        id = self
+            .with_property(id, BlockProperty::Facing, facing as u16)
+            .unwrap_or(id);
+        id = self
+            .with_property(id, BlockProperty::Axis, clicked.axis() as u16)
+            .unwrap_or(id);
+        id = self
+            .with_property(id, BlockProperty::Attached, clicked.opposite() as u16)
+            .unwrap_or(id);
+        id
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
 pub struct MeshingData {
     pub textures: [BlockTextureId; 6],
+    pub transforms: [FaceTransform; 6],
+    /// Random texture variants replacing `textures` on each face, if any
+    pub variants: [Option<TextureVariantsId>; 6],
+    /// Textures replacing `textures` and `variants` on each face, if any, joining up with
+    /// neighbouring blocks of the same type
+    pub connected: [Option<ConnectedTexturesId>; 6],
     pub opacity: u32,
     pub solid: bool,
+    /// The shape of this block, or `None` for a full cube
+    pub model: Option<BlockModelId>,
+    /// The color each face's texture is multiplied by
+    pub tints: [Tint; 6],
+    /// A texture drawn over each face, if any, along with its own tint, such as the grass fringe on
+    /// the sides of a grass block. Overlays share the transformation of the face they cover.
+    pub overlays: [Option<(BlockTextureId, Tint)>; 6],
+    /// The sRGB color of the fog seen while the camera is inside this block, such as a fluid
+    pub fog: Option<[u8; 3]>,
+    /// The height, in sixteenths of a block, of the surface of a fluid, meshing this block as a
+    /// fluid which joins up with neighbouring blocks of the same type
+    pub fluid: Option<u8>,
+}
+
+/// The source of the color a block face's texture is multiplied by, letting grayscale textures
+/// take on the colors of the biome they are placed in
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub enum Tint {
+    /// Show the texture's own colors
+    #[default]
+    None,
+    /// The grass color of the biome at the block
+    Grass,
+    /// The foliage color of the biome at the block
+    Foliage,
+    /// A fixed sRGB color
+    Constant([u8; 3]),
+}
+
+impl MeshingData {
+    /// Get the texture shown on a face of this block at `position`, along with its transformation
+    #[inline]
+    pub fn face_texture(
+        &self,
+        blocks: &Blocks,
+        face: Face,
+        position: IVec3,
+    ) -> (BlockTextureId, FaceTransform) {
+        let transform = self.transforms[face.index()];
+        match self.variants[face.index()] {
+            Some(variants) => {
+                let variant = blocks.get_texture_variants(variants).pick(position, face);
+                (variant.texture, transform.then(variant.transform))
+            }
+            None => (self.textures[face.index()], transform),
+        }
+    }
+
+    /// Get whether any face of this block may show a texture
+    pub fn uses_texture(&self, blocks: &Blocks, texture: BlockTextureId) -> bool {
+        (0..6).any(|face| {
+            self.textures[face] == texture
+                || self.overlays[face].is_some_and(|(overlay, _)| overlay == texture)
+                || self.variants[face].is_some_and(|variants| {
+                    blocks
+                        .get_texture_variants(variants)
+                        .variants()
+                        .iter()
+                        .any(|variant| variant.texture == texture)
+                })
+                || self.connected[face].is_some_and(|connected| {
+                    blocks
+                        .get_connected_textures(connected)
+                        .tiles
+                        .contains(&texture)
+                })
+        })
+    }
+
+    /// Rotate this block by a quarter-turn rotation, moving face textures and adjusting their
+    /// transformations to match.
+    ///
+    /// This does not rotate the block's model; use [`BlockModel::rotated`] to register a rotated
+    /// copy instead.
+    pub fn rotated(self, rotation: Mat3) -> MeshingData {
+        let mut result = self;
+        for face in Face::ALL {
+            let old = Face::from_direction(rotation.transpose() * face.normal());
+            result.textures[face.index()] = self.textures[old.index()];
+            result.variants[face.index()] = self.variants[old.index()];
+            result.tints[face.index()] = self.tints[old.index()];
+            result.overlays[face.index()] = self.overlays[old.index()];
+            result.connected[face.index()] = self.connected[old.index()];
+            // Find which corner of the old texture lands on the first vertex of the new face
+            let target = Vec3::from(face.vertices()[0]);
+            let vertex = old
+                .vertices()
+                .iter()
+                .position(|&v| (rotation * Vec3::from(v)).distance_squared(target) < 0.01)
+                .unwrap();
+            let old_transform = self.transforms[old.index()];
+            let corner = old.uv_corners()[vertex].transform(old_transform);
+            // Rotations preserve handedness, so only the rotation of the texture changes
+            let unrotated = FaceTransform {
+                rotation: FaceRotation::R0,
+                mirrored: old_transform.mirrored,
+            };
+            let base = face.uv_corners()[0].transform(unrotated);
+            let turns = corner as usize + 4 - base as usize;
+            result.transforms[face.index()] = FaceTransform {
+                rotation: FaceRotation::from_quarter_turns(turns),
+                mirrored: old_transform.mirrored,
+            };
+        }
+        result
+    }
+
+    /// Rotate this block so that its top face lies along `axis`
+    #[inline]
+    pub fn along_axis(self, axis: Axis) -> MeshingData {
+        self.rotated(Face::Top.rotation_to(axis.positive_face()))
+    }
+
+    /// Rotate this block so that its front face points towards `facing`
+    #[inline]
+    pub fn facing(self, facing: Face) -> MeshingData {
+        self.rotated(Face::Front.rotation_to(facing))
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Zeroable, Pod)]
@@ -63,3 +431,54 @@ impl Default for BlockId {
         Self(u32::MAX)
     }
 }
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Zeroable, Pod)]
+#[repr(transparent)]
+pub struct BlockTypeId(u32);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Zeroable, Pod)]
+#[repr(transparent)]
+pub struct BlockModelId(u32);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Zeroable, Pod)]
+#[repr(transparent)]
+pub struct TextureVariantsId(u32);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Zeroable, Pod)]
+#[repr(transparent)]
+pub struct ConnectedTexturesId(u32);
+
+/// Update block models from their assets when they are loaded or modified, remeshing every chunk
+pub fn load_block_models(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<BlockModel>>,
+    mut blocks: ResMut<Blocks>,
+    assets: Res<Assets<BlockModel>>,
+    chunks: Query<Entity, With<Chunk>>,
+) {
+    let mut changed = false;
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let Some(loaded) = assets.get(handle) else {
+                    continue;
+                };
+                let id = handle.id();
+                let blocks = &mut *blocks;
+                for &(_, model) in blocks
+                    .model_assets
+                    .range((id, BlockModelId(0))..=(id, BlockModelId(u32::MAX)))
+                {
+                    blocks.models[model.0 as usize].model = loaded.clone();
+                    changed = true;
+                }
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+    if changed {
+        for chunk in chunks.iter() {
+            commands.entity(chunk).insert(ChunkNeedsMeshing);
+        }
+    }
+}
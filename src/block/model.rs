@@ -0,0 +1,280 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+
+use super::face::Face;
+
+/// The shape of a block
+#[derive(Debug, Clone, PartialEq, TypeUuid, TypePath)]
+#[uuid = "5a1e6fb4-3f4c-4d0e-9a43-2c3be7a0f1d2"]
+pub enum BlockModel {
+    /// A full unit cube
+    Cube,
+    /// A set of axis-aligned boxes, such as a slab, stairs or a fence post
+    Boxes(Vec<ModelBox>),
+    /// Two crossed, double-sided quads textured with the block's front face, such as a flower
+    Cross,
+}
+
+impl Default for BlockModel {
+    #[inline]
+    fn default() -> Self {
+        BlockModel::Cube
+    }
+}
+
+impl BlockModel {
+    /// Rotate this model by a quarter-turn rotation about the center of the block
+    pub fn rotated(&self, rotation: Mat3) -> BlockModel {
+        match self {
+            BlockModel::Boxes(boxes) => {
+                BlockModel::Boxes(boxes.iter().map(|b| b.rotated(rotation)).collect())
+            }
+            model => model.clone(),
+        }
+    }
+}
+
+/// An axis-aligned box within a block, in coordinates relative to the block's center
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ModelBox {
+    pub min: Vec3,
+    pub max: Vec3,
+    /// Whether each face of this box is hidden by a full neighbouring block on that side
+    pub cull: [bool; 6],
+}
+
+impl ModelBox {
+    /// A box filling the entire block
+    pub const FULL: ModelBox = ModelBox {
+        min: Vec3::splat(-0.5),
+        max: Vec3::splat(0.5),
+        cull: [true; 6],
+    };
+
+    /// Create a new box, culling exactly the faces which lie on the boundary of the block
+    pub fn new(min: Vec3, max: Vec3) -> ModelBox {
+        let cull = Face::ALL.map(|face| {
+            let normal = face.normal();
+            let extent = if normal.max_element() > 0.0 { max } else { min };
+            (extent.dot(normal) - 0.5).abs() < 1e-4
+        });
+        ModelBox { min, max, cull }
+    }
+
+    /// Create a new box from coordinates in sixteenths of a block, measured from the block's
+    /// minimum corner
+    #[inline]
+    pub fn from_pixels(min: [f32; 3], max: [f32; 3]) -> ModelBox {
+        ModelBox::new(Vec3::from(min) / 16.0 - 0.5, Vec3::from(max) / 16.0 - 0.5)
+    }
+
+    /// Get the position of a corner of a face of this box, given the corresponding corner of the
+    /// face of a unit block
+    #[inline]
+    pub fn map_vertex(&self, vertex: Vec3) -> Vec3 {
+        self.min + (vertex + 0.5) * (self.max - self.min)
+    }
+
+    /// Rotate this box by a quarter-turn rotation about the center of the block
+    pub fn rotated(&self, rotation: Mat3) -> ModelBox {
+        let a = rotation * self.min;
+        let b = rotation * self.max;
+        let cull = Face::ALL.map(|face| {
+            let old = Face::from_direction(rotation.transpose() * face.normal());
+            self.cull[old.index()]
+        });
+        ModelBox {
+            min: a.min(b),
+            max: a.max(b),
+            cull,
+        }
+    }
+}
+
+/// An error parsing a block model file
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ModelParseError {
+    /// The line the error occurred on, starting at 1
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ModelParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ModelParseError {}
+
+/// Parse a block model file.
+///
+/// Each non-empty line not starting with `#` is one of:
+/// - `cube`, for a full cube
+/// - `cross`, for crossed quads
+/// - `box x0 y0 z0 x1 y1 z1 [nocull <face>...]`, adding a box with corners in sixteenths of a
+///   block, optionally never culling the listed faces (`top`, `bottom`, `right`, `left`, `back`
+///   or `front`)
+impl FromStr for BlockModel {
+    type Err = ModelParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut model = None;
+        for (ix, line) in s.lines().enumerate() {
+            let error = |message| ModelParseError {
+                line: ix + 1,
+                message,
+            };
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            match (keyword, &mut model) {
+                (keyword, _) if keyword.starts_with('#') => continue,
+                ("cube", None) => model = Some(BlockModel::Cube),
+                ("cross", None) => model = Some(BlockModel::Cross),
+                ("box", None | Some(BlockModel::Boxes(_))) => {
+                    let mut coords = [0.0; 6];
+                    for coord in &mut coords {
+                        *coord = tokens
+                            .next()
+                            .ok_or(error("expected six box coordinates"))?
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|coord| coord.is_finite())
+                            .ok_or(error("invalid box coordinate"))?;
+                    }
+                    if (0..3).any(|axis| coords[axis] > coords[axis + 3]) {
+                        return Err(error("box minimum corner exceeds its maximum corner"));
+                    }
+                    let mut model_box = ModelBox::from_pixels(
+                        [coords[0], coords[1], coords[2]],
+                        [coords[3], coords[4], coords[5]],
+                    );
+                    match tokens.next() {
+                        None => {}
+                        Some("nocull") => {
+                            for face in tokens.by_ref() {
+                                let face = parse_face(face).ok_or(error("invalid face name"))?;
+                                model_box.cull[face.index()] = false;
+                            }
+                        }
+                        Some(_) => return Err(error("unexpected token after box")),
+                    }
+                    if let Some(BlockModel::Boxes(boxes)) = &mut model {
+                        boxes.push(model_box)
+                    } else {
+                        model = Some(BlockModel::Boxes(vec![model_box]))
+                    }
+                }
+                ("cube" | "cross" | "box", Some(_)) => {
+                    return Err(error("cannot combine this shape with the previous ones"))
+                }
+                _ => return Err(error("unknown keyword")),
+            }
+            if keyword != "box" && tokens.next().is_some() {
+                return Err(error("unexpected token"));
+            }
+        }
+        model.ok_or(ModelParseError {
+            line: 0,
+            message: "model is empty",
+        })
+    }
+}
+
+fn parse_face(name: &str) -> Option<Face> {
+    Some(match name {
+        "top" => Face::Top,
+        "bottom" => Face::Bottom,
+        "right" => Face::Right,
+        "left" => Face::Left,
+        "back" => Face::Back,
+        "front" => Face::Front,
+        _ => return None,
+    })
+}
+
+/// Loads [`BlockModel`]s from `.blockmodel` files
+#[derive(Debug, Default)]
+pub struct BlockModelLoader;
+
+impl AssetLoader for BlockModelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let model: BlockModel = std::str::from_utf8(bytes)?.parse()?;
+            load_context.set_default_asset(LoadedAsset::new(model));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blockmodel"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_shapes() {
+        assert_eq!("cube".parse::<BlockModel>().unwrap(), BlockModel::Cube);
+        assert_eq!(
+            "# a flower\ncross\n".parse::<BlockModel>().unwrap(),
+            BlockModel::Cross
+        );
+        let slab = "box 0 0 0 16 8 16\n".parse::<BlockModel>().unwrap();
+        let BlockModel::Boxes(boxes) = slab else {
+            panic!("expected boxes, got {slab:?}");
+        };
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].min, Vec3::splat(-0.5));
+        assert_eq!(boxes[0].max, Vec3::new(0.5, 0.0, 0.5));
+        assert!(!boxes[0].cull[Face::Top.index()]);
+        assert!(boxes[0].cull[Face::Bottom.index()]);
+    }
+
+    #[test]
+    fn parse_nocull() {
+        let model = "box 0 0 0 16 16 16 nocull top left"
+            .parse::<BlockModel>()
+            .unwrap();
+        let BlockModel::Boxes(boxes) = model else {
+            panic!("expected boxes, got {model:?}");
+        };
+        assert!(!boxes[0].cull[Face::Top.index()]);
+        assert!(!boxes[0].cull[Face::Left.index()]);
+        assert!(boxes[0].cull[Face::Right.index()]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |s: &str| s.parse::<BlockModel>().unwrap_err().line;
+        assert_eq!(line(""), 0);
+        assert_eq!(line("cube\ncross"), 2);
+        assert_eq!(line("box 0 0 0 16 16"), 1);
+        assert_eq!(line("box 0 0 0 16 16 sixteen"), 1);
+        assert_eq!(line("box 0 0 0 16 nan 16"), 1);
+        assert_eq!(line("cube\n\nbox 0 0 0 16 16 16"), 3);
+        assert_eq!(line("box 0 0 0 16 16 16 nocull sideways"), 1);
+        assert_eq!(line("sphere"), 1);
+    }
+
+    #[test]
+    fn parse_rejects_inverted_boxes() {
+        let error = "box 0 8 0 16 4 16".parse::<BlockModel>().unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!("box 0 0 0 0 16 16".parse::<BlockModel>().is_ok());
+    }
+}
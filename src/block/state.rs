@@ -0,0 +1,171 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::face::{Axis, Face};
+
+/// A property which a block type may declare, each combination of values of which is a distinct
+/// block state
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum BlockProperty {
+    /// The face of the block pointing towards the player who placed it
+    Facing,
+    /// The axis the block is aligned along
+    Axis,
+    /// Whether the block is lit
+    Lit,
+    /// A fluid or fill level from `0` to `7`
+    Level,
+    /// The face of the block the block supporting it is on, such as the wall a torch hangs on
+    Attached,
+}
+
+impl BlockProperty {
+    /// Get the number of values this property can take
+    #[inline]
+    pub fn values(self) -> u16 {
+        match self {
+            BlockProperty::Facing => 6,
+            BlockProperty::Axis => 3,
+            BlockProperty::Lit => 2,
+            BlockProperty::Level => 8,
+            BlockProperty::Attached => 6,
+        }
+    }
+}
+
+/// The index of a state within its block type, with property values packed in mixed radix
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default, Pod, Zeroable)]
+#[repr(transparent)]
+pub struct BlockState(pub u16);
+
+/// The values of the properties of a block state
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct StateValues<'a> {
+    properties: &'a [BlockProperty],
+    state: BlockState,
+}
+
+impl<'a> StateValues<'a> {
+    #[inline]
+    pub fn new(properties: &'a [BlockProperty], state: BlockState) -> StateValues<'a> {
+        StateValues { properties, state }
+    }
+
+    /// Get the packed state
+    #[inline]
+    pub fn state(&self) -> BlockState {
+        self.state
+    }
+
+    /// Get the raw value of a property, if this block type declares it
+    #[inline]
+    pub fn get(&self, property: BlockProperty) -> Option<u16> {
+        let mut stride = 1;
+        for &p in self.properties {
+            if p == property {
+                return Some(self.state.0 / stride % p.values());
+            }
+            stride *= p.values();
+        }
+        None
+    }
+
+    /// Get the state with a property set to a new raw value, if this block type declares it
+    #[inline]
+    pub fn with(&self, property: BlockProperty, value: u16) -> Option<BlockState> {
+        let mut stride = 1;
+        for &p in self.properties {
+            if p == property {
+                if value >= p.values() {
+                    return None;
+                }
+                let old = self.state.0 / stride % p.values();
+                return Some(BlockState(self.state.0 - old * stride + value * stride));
+            }
+            stride *= p.values();
+        }
+        None
+    }
+
+    #[inline]
+    pub fn facing(&self) -> Option<Face> {
+        Face::from_index(self.get(BlockProperty::Facing)? as usize)
+    }
+
+    #[inline]
+    pub fn axis(&self) -> Option<Axis> {
+        Axis::from_index(self.get(BlockProperty::Axis)? as usize)
+    }
+
+    #[inline]
+    pub fn lit(&self) -> Option<bool> {
+        Some(self.get(BlockProperty::Lit)? != 0)
+    }
+
+    #[inline]
+    pub fn level(&self) -> Option<u8> {
+        Some(self.get(BlockProperty::Level)? as u8)
+    }
+
+    #[inline]
+    pub fn attached(&self) -> Option<Face> {
+        Face::from_index(self.get(BlockProperty::Attached)? as usize)
+    }
+}
+
+/// Get the number of states of a block type with the given properties
+#[inline]
+pub fn state_count(properties: &[BlockProperty]) -> Option<u16> {
+    properties
+        .iter()
+        .try_fold(1u16, |acc, p| acc.checked_mul(p.values()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROPERTIES: [BlockProperty; 3] = [
+        BlockProperty::Facing,
+        BlockProperty::Lit,
+        BlockProperty::Level,
+    ];
+
+    #[test]
+    fn state_count_multiplies_values() {
+        assert_eq!(state_count(&[]), Some(1));
+        assert_eq!(state_count(&PROPERTIES), Some(6 * 2 * 8));
+        assert_eq!(state_count(&[BlockProperty::Level; 6]), None);
+    }
+
+    #[test]
+    fn every_state_round_trips() {
+        for state in 0..state_count(&PROPERTIES).unwrap() {
+            let values = StateValues::new(&PROPERTIES, BlockState(state));
+            let [facing, lit, level] = PROPERTIES.map(|p| values.get(p).unwrap());
+            assert!(facing < 6 && lit < 2 && level < 8);
+            assert_eq!(facing + 6 * lit + 12 * level, state);
+            for property in PROPERTIES {
+                let value = values.get(property).unwrap();
+                assert_eq!(values.with(property, value), Some(BlockState(state)));
+            }
+        }
+    }
+
+    #[test]
+    fn with_changes_only_its_property() {
+        let values = StateValues::new(&PROPERTIES, BlockState(0));
+        let state = values.with(BlockProperty::Lit, 1).unwrap();
+        let state = StateValues::new(&PROPERTIES, state)
+            .with(BlockProperty::Level, 5)
+            .unwrap();
+        let values = StateValues::new(&PROPERTIES, state);
+        assert_eq!(values.facing(), Some(Face::from_index(0).unwrap()));
+        assert_eq!(values.lit(), Some(true));
+        assert_eq!(values.level(), Some(5));
+
+        assert_eq!(values.with(BlockProperty::Level, 8), None);
+        assert_eq!(values.with(BlockProperty::Axis, 0), None);
+        assert_eq!(values.axis(), None);
+        assert_eq!(values.attached(), None);
+    }
+}
@@ -1,86 +1,466 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, error::Error, fmt};
 
 use bevy::{
     asset::HandleId,
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    render::{
+        render_asset::RenderAssets,
+        render_resource::{
+            Extent3d, FilterMode, ImageCopyTexture, ImageDataLayout, Origin3d, SamplerDescriptor,
+            TextureAspect, TextureDimension, TextureFormat,
+        },
+        renderer::RenderQueue,
+        texture::ImageSampler,
+        Extract,
+    },
 };
 use bytemuck::{Pod, Zeroable};
 use slab::Slab;
 
-const TEXTURE_HEIGHT: usize = 16;
+/// The default resolution of block textures, in pixels
+pub const DEFAULT_RESOLUTION: u32 = 16;
+/// The largest supported block texture resolution, keeping sheets at most 8192 pixels wide
+pub const MAX_RESOLUTION: u32 = 32;
 const LOG_SHEET_SIZE: u32 = 8;
-const SHEET_HEIGHT: usize = 1 << LOG_SHEET_SIZE;
+/// The number of tiles along each side of a texture sheet
+pub const SHEET_HEIGHT: usize = 1 << LOG_SHEET_SIZE;
 const SHEET_SIZE: usize = SHEET_HEIGHT * SHEET_HEIGHT;
 
 #[derive(Resource, Default)]
 pub struct BlockMaterials {
-    sheet_materials: Vec<Handle<StandardMaterial>>,
-    blocks: Vec<Vec<BlockData>>,
+    sheets: Vec<SheetData>,
+    /// The textures in each sheet, or `None` for freed slots
+    blocks: Vec<Vec<Option<BlockData>>>,
     materials: Slab<MaterialData>,
-    textures: BTreeSet<(HandleId, BlockTextureId)>,
+    textures: BTreeSet<(HandleId, BlockTextureId, TextureMap)>,
+    animated: BTreeSet<BlockTextureId>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 struct MaterialData {
     template: Handle<StandardMaterial>,
+    settings: MaterialSettings,
     curr_texture: usize,
+    sheets: Vec<usize>,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, PartialEq)]
+struct SheetData {
+    material: Handle<StandardMaterial>,
+    settings: MaterialSettings,
+    /// Slots freed by removed textures, to be reused before the sheet grows
+    free: Vec<u16>,
+    /// For each slot, a bitmask of the maps which have been blitted into it, indexed by
+    /// [`TextureMap::index`]
+    blitted: Vec<u8>,
+}
+
+/// One of the images making up a block texture, each blitted into a parallel sheet bound to the
+/// corresponding map of the sheet's [`StandardMaterial`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum TextureMap {
+    BaseColor,
+    /// A tangent-space normal map
+    Normal,
+    /// Roughness in the green channel and metallic in the blue channel, scaling the material's
+    /// `perceptual_roughness` and `metallic`
+    MetallicRoughness,
+    Emissive,
+}
+
+impl TextureMap {
+    pub const ALL: [TextureMap; 4] = [
+        TextureMap::BaseColor,
+        TextureMap::Normal,
+        TextureMap::MetallicRoughness,
+        TextureMap::Emissive,
+    ];
+
+    #[inline]
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Whether this map stores colors, rather than linear data
+    #[inline]
+    pub fn is_srgb(self) -> bool {
+        matches!(self, TextureMap::BaseColor | TextureMap::Emissive)
+    }
+
+    /// Get the value of texels of this map which no texture has been blitted to, leaving the
+    /// material's own parameters unchanged
+    #[inline]
+    fn fill(self) -> [u8; 4] {
+        match self {
+            TextureMap::BaseColor | TextureMap::Emissive => [0, 0, 0, 255],
+            TextureMap::Normal => [128, 128, 255, 255],
+            TextureMap::MetallicRoughness => [255, 255, 255, 255],
+        }
+    }
+
+    /// Get the field of a material this map's sheet is bound to
+    #[inline]
+    fn slot(self, material: &mut StandardMaterial) -> &mut Option<Handle<Image>> {
+        match self {
+            TextureMap::BaseColor => &mut material.base_color_texture,
+            TextureMap::Normal => &mut material.normal_map_texture,
+            TextureMap::MetallicRoughness => &mut material.metallic_roughness_texture,
+            TextureMap::Emissive => &mut material.emissive_texture,
+        }
+    }
+
+    #[inline]
+    fn image(self, material: &StandardMaterial) -> &Option<Handle<Image>> {
+        match self {
+            TextureMap::BaseColor => &material.base_color_texture,
+            TextureMap::Normal => &material.normal_map_texture,
+            TextureMap::MetallicRoughness => &material.metallic_roughness_texture,
+            TextureMap::Emissive => &material.emissive_texture,
+        }
+    }
+
+    /// Decode a texel of this map into a linear value which can be averaged
+    #[inline]
+    fn decode(self, texel: &[u8]) -> Vec3 {
+        let unorm = Vec3::new(texel[0] as f32, texel[1] as f32, texel[2] as f32) / 255.0;
+        match self {
+            TextureMap::BaseColor | TextureMap::Emissive => unorm.powf(2.2),
+            TextureMap::Normal => unorm * 2.0 - 1.0,
+            TextureMap::MetallicRoughness => unorm,
+        }
+    }
+
+    #[inline]
+    fn encode(self, value: Vec3) -> [u8; 3] {
+        let unorm = match self {
+            TextureMap::BaseColor | TextureMap::Emissive => value.powf(1.0 / 2.2),
+            TextureMap::Normal => value.normalize_or_zero() * 0.5 + 0.5,
+            TextureMap::MetallicRoughness => value,
+        };
+        (unorm.clamp(Vec3::ZERO, Vec3::ONE) * 255.0)
+            .round()
+            .to_array()
+            .map(|c| c as u8)
+    }
+}
+
+/// How the textures of a material set are imported into its sheets
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MaterialSettings {
+    /// The width and height, in pixels, of each texture (or animation frame) in the sheets
+    pub resolution: u32,
+    /// How to import images whose width differs from `resolution`
+    pub resampling: TextureResampling,
+    /// How the sheets are sampled when rendering
+    pub filtering: TextureFiltering,
+}
+
+impl Default for MaterialSettings {
+    #[inline]
+    fn default() -> Self {
+        MaterialSettings {
+            resolution: DEFAULT_RESOLUTION,
+            resampling: TextureResampling::Reject,
+            filtering: TextureFiltering::Mipmapped,
+        }
+    }
+}
+
+/// How the sheets of a material set are sampled
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub enum TextureFiltering {
+    /// Sample the nearest texel of a single level, which shimmers heavily in the distance
+    Nearest,
+    /// Sample the nearest texel, blending between mip levels when textures are minified
+    #[default]
+    Mipmapped,
+    /// Blend linearly between texels and mip levels, taking up to the given number of samples,
+    /// from 1 to 16, along surfaces viewed at a glancing angle
+    Anisotropic(u16),
+}
+
+impl TextureFiltering {
+    /// Get the number of mip levels of a sheet of textures of the given resolution.
+    ///
+    /// Each level halves the size of every tile, stopping once a tile can no longer be halved
+    /// evenly, so that tiles never straddle each other in any level.
+    #[inline]
+    pub fn mip_levels(&self, resolution: u32) -> u32 {
+        match self {
+            TextureFiltering::Nearest => 1,
+            _ => resolution.trailing_zeros() + 1,
+        }
+    }
+
+    #[inline]
+    fn sampler(&self) -> ImageSampler {
+        let (filter, anisotropy_clamp) = match *self {
+            TextureFiltering::Nearest => return ImageSampler::nearest(),
+            TextureFiltering::Mipmapped => (FilterMode::Nearest, 1),
+            TextureFiltering::Anisotropic(clamp) => (FilterMode::Linear, clamp),
+        };
+        ImageSampler::Descriptor(SamplerDescriptor {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: FilterMode::Linear,
+            anisotropy_clamp,
+            ..default()
+        })
+    }
+}
+
+/// How to scale images which do not match the resolution of their material set
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub enum TextureResampling {
+    /// Refuse to import the image, failing with [`TextureError::SizeMismatch`]
+    #[default]
+    Reject,
+    /// Scale each frame using the nearest pixel, keeping pixel art crisp
+    Nearest,
+    /// Scale each frame by bilinearly interpolating between pixels
+    Linear,
+}
+
+/// An error importing an image into a texture sheet
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TextureError {
+    /// The block texture does not exist
+    UnknownTexture,
+    /// The image could not be converted to 8-bit sRGBA
+    UnsupportedFormat(TextureFormat),
+    /// The image is not a vertical strip of square frames
+    NonSquareFrames { width: u32, height: u32 },
+    /// The image's frames are not the resolution of the material set, which does not resample
+    SizeMismatch { expected: u32, width: u32 },
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::UnknownTexture => write!(f, "unknown block texture"),
+            TextureError::UnsupportedFormat(format) => {
+                write!(f, "unsupported texture format {:?}", format)
+            }
+            TextureError::NonSquareFrames { width, height } => write!(
+                f,
+                "a {}x{} image is not a vertical strip of square frames",
+                width, height
+            ),
+            TextureError::SizeMismatch { expected, width } => write!(
+                f,
+                "expected {}x{} frames, found {}x{}",
+                expected, expected, width, width
+            ),
+        }
+    }
+}
+
+impl Error for TextureError {}
+
+#[derive(Debug, Clone, PartialEq)]
 struct BlockData {
-    texture: Option<Handle<Image>>,
+    textures: [Option<Handle<Image>>; 4],
     material: BlockMaterialId,
+    animation: Option<AnimationState>,
+    /// The strip frame shown when not animated
+    frame: u32,
+}
+
+/// An animation for a block texture whose image is a vertical strip of square frames
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TextureAnimation {
+    /// The duration, in seconds, of frames without an explicit duration
+    pub frame_time: f32,
+    /// The frames to show, in order. If empty, every frame of the strip is shown in order.
+    pub frames: Vec<AnimationFrame>,
+}
+
+/// A frame of a [`TextureAnimation`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnimationFrame {
+    /// The index of the frame in the strip, from the top
+    pub index: u32,
+    /// The duration of this frame in seconds, overriding the animation's `frame_time`
+    pub duration: Option<f32>,
+}
+
+impl TextureAnimation {
+    /// Show every frame of the strip in order, each for `frame_time` seconds
+    #[inline]
+    pub fn uniform(frame_time: f32) -> TextureAnimation {
+        TextureAnimation {
+            frame_time,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Get the number of steps in this animation, given the number of frames in the strip
+    #[inline]
+    fn steps(&self, strip_frames: u32) -> usize {
+        if self.frames.is_empty() {
+            strip_frames as usize
+        } else {
+            self.frames.len()
+        }
+    }
+
+    /// Get the strip frame and duration of a step of this animation
+    #[inline]
+    fn step(&self, step: usize) -> (u32, f32) {
+        match self.frames.get(step) {
+            Some(frame) => (frame.index, frame.duration.unwrap_or(self.frame_time)),
+            None => (step as u32, self.frame_time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AnimationState {
+    animation: TextureAnimation,
+    step: usize,
+    elapsed: f32,
 }
 
 impl BlockMaterials {
+    /// Create a new material set of 16-pixel textures, rejecting images of any other size
     #[inline]
+    #[allow(clippy::result_unit_err)]
     pub fn new_material(
         &mut self,
         template: Handle<StandardMaterial>,
     ) -> Result<BlockMaterialId, ()> {
-        if self.materials.len() >= u16::MAX as usize {
+        self.new_material_with(template, MaterialSettings::default())
+    }
+
+    /// Create a new material set, failing if its resolution is zero or above [`MAX_RESOLUTION`],
+    /// or its anisotropic filtering takes fewer than 1 or more than 16 samples
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn new_material_with(
+        &mut self,
+        template: Handle<StandardMaterial>,
+        settings: MaterialSettings,
+    ) -> Result<BlockMaterialId, ()> {
+        if self.materials.len() >= u16::MAX as usize
+            || settings.resolution == 0
+            || settings.resolution > MAX_RESOLUTION
+            || matches!(settings.filtering, TextureFiltering::Anisotropic(c) if !(1..=16).contains(&c))
+        {
             return Err(());
         }
         let ix = self.materials.insert(MaterialData {
             template,
+            settings,
             curr_texture: usize::MAX,
+            sheets: Vec::new(),
         });
         Ok(BlockMaterialId(ix as u16))
     }
 
+    /// Allocate a texture in a material set, reusing a slot freed by
+    /// [`BlockMaterials::remove_texture`] if there is one
     #[inline]
+    #[allow(clippy::result_unit_err)]
     pub fn new_texture(
         &mut self,
         material: BlockMaterialId,
         materials: &mut Assets<StandardMaterial>,
     ) -> Result<BlockTextureId, ()> {
         let curr_material = self.materials.get_mut(material.0 as usize).ok_or(())?;
+        let data = BlockData {
+            textures: Default::default(),
+            material,
+            animation: None,
+            frame: 0,
+        };
+        for &sheet in &curr_material.sheets {
+            if let Some(slot) = self.sheets[sheet].free.pop() {
+                self.blocks[sheet][slot as usize] = Some(data);
+                return Ok(BlockTextureId((sheet * SHEET_SIZE) as u32 + slot as u32));
+            }
+        }
         let l = self.blocks.len();
         let blocks = match self.blocks.get_mut(curr_material.curr_texture) {
-            Some(blocks) if blocks.len() <= SHEET_SIZE => blocks,
+            Some(blocks) if blocks.len() < SHEET_SIZE => blocks,
             _ if l < u32::MAX as usize / SHEET_SIZE => {
                 curr_material.curr_texture = l;
+                curr_material.sheets.push(l);
                 self.blocks.push(Vec::new());
                 let template = materials
                     .get_mut(&curr_material.template)
                     .ok_or(())?
                     .clone();
-                self.sheet_materials.push(materials.add(template));
+                self.sheets.push(SheetData {
+                    material: materials.add(template),
+                    settings: curr_material.settings,
+                    free: Vec::new(),
+                    blitted: Vec::new(),
+                });
                 self.blocks.last_mut().unwrap()
             }
             _ => return Err(()),
         };
         let block_ix = blocks.len();
-        blocks.push(BlockData {
-            texture: None,
-            material,
-        });
+        blocks.push(Some(data));
         let texture_ix = curr_material.curr_texture;
-        let id = BlockTextureId((texture_ix * SHEET_HEIGHT * SHEET_HEIGHT + block_ix) as u32);
+        let id = BlockTextureId((texture_ix * SHEET_SIZE + block_ix) as u32);
         Ok(id)
     }
 
+    /// Unregister a texture, clearing its pixels from every sheet and freeing its slot for reuse.
+    /// Once every texture in a sheet is removed, the sheet's images are dropped.
+    ///
+    /// Blocks still showing the texture must be changed and their chunks remeshed; see
+    /// [`Blocks::blocks_with_texture`](super::Blocks::blocks_with_texture).
+    #[allow(clippy::result_unit_err)]
+    pub fn remove_texture(
+        &mut self,
+        block: BlockTextureId,
+        images: &mut Assets<Image>,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<(), ()> {
+        let sheet_ix = block.sheet().0 as usize;
+        let data = self
+            .blocks
+            .get_mut(sheet_ix)
+            .and_then(|blocks| blocks.get_mut(block.coords().0 as usize))
+            .and_then(Option::take)
+            .ok_or(())?;
+        for map in TextureMap::ALL {
+            if let Some(texture) = &data.textures[map.index()] {
+                self.textures.remove(&(texture.id(), block, map));
+            }
+        }
+        self.animated.remove(&block);
+        let sheet = &mut self.sheets[sheet_ix];
+        sheet.free.push(block.coords().0);
+        if let Some(blitted) = sheet.blitted.get_mut(block.coords().0 as usize) {
+            *blitted = 0;
+        }
+        let empty = sheet.free.len() == self.blocks[sheet_ix].len();
+        let Some(material) = materials.get_mut(&sheet.material) else {
+            return Ok(());
+        };
+        for map in TextureMap::ALL {
+            let slot = map.slot(material);
+            if empty {
+                // Sheets are recreated by the next blit into them
+                if let Some(image) = slot.take() {
+                    images.remove(&image);
+                    if map == TextureMap::Emissive {
+                        material.emissive = Color::BLACK;
+                    }
+                }
+            } else if let Some(image) = slot.as_ref().and_then(|image| images.get_mut(image)) {
+                let levels = image.texture_descriptor.mip_level_count;
+                let resolution = sheet.settings.resolution as usize;
+                clear_tile(&mut image.data, map, block.coords(), resolution, levels);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the image shown by a block texture, returning the previous one
     #[inline]
     pub fn set_block_texture(
         &mut self,
@@ -88,75 +468,245 @@ impl BlockMaterials {
         texture: Handle<Image>,
         images: &mut Assets<Image>,
         materials: &mut Assets<StandardMaterial>,
-    ) -> Result<Option<Handle<Image>>, ()> {
+    ) -> Result<Option<Handle<Image>>, TextureError> {
+        self.set_block_map(block, TextureMap::BaseColor, texture, images, materials)
+    }
+
+    /// Set one map of a block texture, returning the previous image.
+    ///
+    /// If the image is already loaded and cannot be imported into the texture's sheet, the image
+    /// is still assigned, so that it is blitted once it is modified into a valid texture.
+    pub fn set_block_map(
+        &mut self,
+        block: BlockTextureId,
+        map: TextureMap,
+        texture: Handle<Image>,
+        images: &mut Assets<Image>,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<Option<Handle<Image>>, TextureError> {
         let data = self
             .blocks
             .get_mut(block.sheet().0 as usize)
-            .ok_or(())?
+            .ok_or(TextureError::UnknownTexture)?
             .get_mut(block.coords().0 as usize)
-            .ok_or(())?;
+            .and_then(Option::as_mut)
+            .ok_or(TextureError::UnknownTexture)?;
+        let mut result = Ok(());
         'precomp: {
-            if let Some(old_texture) = &data.texture {
+            if let Some(old_texture) = &data.textures[map.index()] {
                 if old_texture == &texture {
                     break 'precomp;
                 }
-                self.textures.remove(&(old_texture.id(), block));
+                self.textures.remove(&(old_texture.id(), block, map));
             }
 
-            self.textures.insert((texture.id(), block));
-            if let Some(data) = images.get(&texture) {
-                Self::blit_texture_inner(
-                    &mut self.sheet_materials,
+            self.textures.insert((texture.id(), block, map));
+            if let Some(image) = images.get(&texture) {
+                let image = image.clone();
+                result = Self::blit_texture_inner(
+                    &mut self.sheets,
                     block,
-                    &data.convert(TextureFormat::Rgba8UnormSrgb).unwrap(),
+                    map,
+                    &image,
+                    data.current_frame(),
                     images,
                     materials,
                 )
             }
         }
-        Ok(data.texture.replace(texture))
+        let old = data.textures[map.index()].replace(texture);
+        result.map(|()| old)
+    }
+
+    /// Animate a block texture, or stop animating it if `animation` is `None`.
+    ///
+    /// The texture's images are treated as vertical strips of square frames, which are written into
+    /// the tiles of their sheets in turn by [`animate_block_textures`]. The number of frames is
+    /// taken from the base color image.
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn set_block_animation(
+        &mut self,
+        block: BlockTextureId,
+        animation: Option<TextureAnimation>,
+    ) -> Result<Option<TextureAnimation>, ()> {
+        let data = self
+            .blocks
+            .get_mut(block.sheet().0 as usize)
+            .ok_or(())?
+            .get_mut(block.coords().0 as usize)
+            .and_then(Option::as_mut)
+            .ok_or(())?;
+        if animation.is_some() {
+            self.animated.insert(block);
+        } else {
+            self.animated.remove(&block);
+        }
+        let old = std::mem::replace(
+            &mut data.animation,
+            animation.map(|animation| AnimationState {
+                animation,
+                step: 0,
+                elapsed: 0.0,
+            }),
+        );
+        Ok(old.map(|state| state.animation))
+    }
+
+    /// Show a fixed frame of a block texture's images, treated as vertical strips of square
+    /// frames, while it is not animated, returning the previous frame.
+    ///
+    /// This lets one image hold a family of related textures, such as the tiles of a
+    /// [`ConnectedTextures`](super::connected::ConnectedTextures) set.
+    pub fn set_block_frame(
+        &mut self,
+        block: BlockTextureId,
+        frame: u32,
+        images: &mut Assets<Image>,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<u32, TextureError> {
+        let data = self
+            .blocks
+            .get_mut(block.sheet().0 as usize)
+            .ok_or(TextureError::UnknownTexture)?
+            .get_mut(block.coords().0 as usize)
+            .and_then(Option::as_mut)
+            .ok_or(TextureError::UnknownTexture)?;
+        let old = std::mem::replace(&mut data.frame, frame);
+        if old != frame && data.animation.is_none() {
+            for map in TextureMap::ALL {
+                let Some(image) = data.textures[map.index()]
+                    .as_ref()
+                    .and_then(|texture| images.get(texture))
+                else {
+                    continue;
+                };
+                let image = image.clone();
+                Self::blit_texture_inner(
+                    &mut self.sheets,
+                    block,
+                    map,
+                    &image,
+                    frame,
+                    images,
+                    materials,
+                )?;
+            }
+        }
+        Ok(old)
     }
 
     #[inline]
     pub fn get_sheet_material(&self, sheet: SheetId) -> Handle<StandardMaterial> {
-        self.sheet_materials[sheet.0 as usize].clone()
+        self.sheets[sheet.0 as usize].material.clone()
+    }
+
+    /// Get the settings of the material set a sheet belongs to
+    #[inline]
+    pub fn get_sheet_settings(&self, sheet: SheetId) -> MaterialSettings {
+        self.sheets[sheet.0 as usize].settings
+    }
+
+    /// Get every texture sheet, in order of creation
+    #[inline]
+    pub fn sheets(&self) -> impl Iterator<Item = SheetId> {
+        (0..self.sheets.len()).map(|ix| SheetId(ix as u16))
+    }
+
+    /// Get the image one map of a sheet is blitted into, if any texture has been blitted to it
+    #[inline]
+    pub fn get_sheet_image(
+        &self,
+        sheet: SheetId,
+        map: TextureMap,
+        materials: &Assets<StandardMaterial>,
+    ) -> Option<Handle<Image>> {
+        let material = materials.get(&self.sheets.get(sheet.0 as usize)?.material)?;
+        map.image(material).clone()
+    }
+
+    /// Get the number of rows of tiles a sheet has allocated, including freed slots
+    #[inline]
+    pub fn sheet_rows(&self, sheet: SheetId) -> usize {
+        self.blocks
+            .get(sheet.0 as usize)
+            .map_or(0, |blocks| blocks.len().div_ceil(SHEET_HEIGHT))
+    }
+
+    /// Get the textures allocated in a sheet, in order of their slots
+    pub fn sheet_textures(&self, sheet: SheetId) -> impl Iterator<Item = BlockTextureId> + '_ {
+        let ix = sheet.0 as usize;
+        self.blocks
+            .get(ix)
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, data)| data.is_some())
+            .map(move |(slot, _)| BlockTextureId((ix * SHEET_SIZE + slot) as u32))
+    }
+
+    /// Get the image assigned to one map of a block texture
+    #[inline]
+    pub fn get_block_map(&self, block: BlockTextureId, map: TextureMap) -> Option<&Handle<Image>> {
+        self.blocks
+            .get(block.sheet().0 as usize)?
+            .get(block.coords().0 as usize)?
+            .as_ref()?
+            .textures[map.index()]
+        .as_ref()
+    }
+
+    /// Get whether an image has ever been blitted into one map of a block texture's tile
+    #[inline]
+    pub fn is_blitted(&self, block: BlockTextureId, map: TextureMap) -> bool {
+        self.sheets
+            .get(block.sheet().0 as usize)
+            .and_then(|sheet| sheet.blitted.get(block.coords().0 as usize))
+            .is_some_and(|blitted| blitted & (1 << map.index()) != 0)
     }
 
     #[inline]
     fn blit_texture_inner(
-        texture_sheets: &mut [Handle<StandardMaterial>],
+        sheets: &mut [SheetData],
         block: BlockTextureId,
+        map: TextureMap,
         texture: &Image,
+        frame: u32,
         images: &mut Assets<Image>,
         materials: &mut Assets<StandardMaterial>,
-    ) {
-        let sheet = &mut texture_sheets[block.sheet().0 as usize];
-        let target_mat = materials.get_mut(sheet).unwrap();
-        let base_color_texture = target_mat.base_color_texture.get_or_insert_with(|| {
-            let image = Image::new_fill(
-                Extent3d {
-                    width: (TEXTURE_HEIGHT * SHEET_HEIGHT) as u32,
-                    height: (TEXTURE_HEIGHT * SHEET_HEIGHT) as u32,
-                    depth_or_array_layers: 1,
-                },
-                TextureDimension::D2,
-                &[0, 0, 0, 255],
-                TextureFormat::Rgba8UnormSrgb,
-            );
-            images.add(image)
-        });
-        let target_data = images.get_mut(base_color_texture).unwrap();
+    ) -> Result<(), TextureError> {
+        let sheet = &mut sheets[block.sheet().0 as usize];
+        let texture = sheet.settings.import(texture)?;
+        let resolution = sheet.settings.resolution as usize;
+        let target_mat = materials.get_mut(&sheet.material).unwrap();
+        if map == TextureMap::Emissive && target_mat.emissive_texture.is_none() {
+            // The emissive map is scaled by the material's emissive color, which defaults to black
+            target_mat.emissive = Color::WHITE;
+        }
+        let sheet_texture = map
+            .slot(target_mat)
+            .get_or_insert_with(|| images.add(new_sheet_image(&sheet.settings, map)));
+        let target_data = images.get_mut(sheet_texture).unwrap();
         let coords = block.coords();
-        let start = coords.x_ix() as usize * 4 * TEXTURE_HEIGHT
-            + coords.y_ix() as usize * 4 * TEXTURE_HEIGHT * SHEET_HEIGHT;
-        for y in 0..TEXTURE_HEIGHT {
-            for x in 0..TEXTURE_HEIGHT {
-                for c in 0..4 {
-                    target_data.data[start + c + x * 4 + y * 4 * TEXTURE_HEIGHT * SHEET_HEIGHT] =
-                        texture.data[c + x * 4 + y * 4 * TEXTURE_HEIGHT]
-                }
-            }
+        let row = 4 * resolution;
+        let target_row = row * SHEET_HEIGHT;
+        let start = coords.x_ix() as usize * row + coords.y_ix() as usize * resolution * target_row;
+        let frame = frame % strip_frames(&texture);
+        let source_start = frame as usize * resolution * row;
+        for y in 0..resolution {
+            let target = start + y * target_row;
+            let source = source_start + y * row;
+            target_data.data[target..target + row]
+                .copy_from_slice(&texture.data[source..source + row]);
+        }
+        let levels = target_data.texture_descriptor.mip_level_count;
+        update_mip_tiles(&mut target_data.data, map, coords, resolution, levels);
+        let slot = coords.0 as usize;
+        if sheet.blitted.len() <= slot {
+            sheet.blitted.resize(slot + 1, 0);
         }
+        sheet.blitted[slot] |= 1 << map.index();
+        Ok(())
     }
 
     // #[inline]
@@ -172,6 +722,241 @@ impl BlockMaterials {
     // }
 }
 
+impl BlockData {
+    /// Get the strip frame currently shown by this texture
+    #[inline]
+    fn current_frame(&self) -> u32 {
+        self.animation
+            .as_ref()
+            .map_or(self.frame, |state| state.animation.step(state.step).0)
+    }
+}
+
+/// Create an empty sheet for a map, with a mip chain if its material set is filtered
+fn new_sheet_image(settings: &MaterialSettings, map: TextureMap) -> Image {
+    let side = settings.resolution as usize * SHEET_HEIGHT;
+    let levels = settings.filtering.mip_levels(settings.resolution);
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: side as u32,
+            height: side as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &map.fill(),
+        if map.is_srgb() {
+            TextureFormat::Rgba8UnormSrgb
+        } else {
+            TextureFormat::Rgba8Unorm
+        },
+    );
+    // Mip levels follow the base level in the image data, each half the size of the last
+    let pixels: usize = (0..levels).map(|level| (side >> level).pow(2)).sum();
+    image.data = map.fill().repeat(pixels);
+    image.texture_descriptor.mip_level_count = levels;
+    image.sampler_descriptor = settings.filtering.sampler();
+    image
+}
+
+/// Reset a tile to the map's fill value in every mip level of a sheet
+fn clear_tile(
+    data: &mut [u8],
+    map: TextureMap,
+    coords: SheetCoords,
+    resolution: usize,
+    levels: u32,
+) {
+    let mut offset = 0;
+    let mut side = resolution * SHEET_HEIGHT;
+    let mut tile = resolution;
+    for _ in 0..levels {
+        let (x0, y0) = (coords.x_ix() as usize * tile, coords.y_ix() as usize * tile);
+        for y in y0..y0 + tile {
+            let start = offset + 4 * (x0 + y * side);
+            for texel in data[start..start + 4 * tile].chunks_exact_mut(4) {
+                texel.copy_from_slice(&map.fill());
+            }
+        }
+        offset += 4 * side * side;
+        side /= 2;
+        tile /= 2;
+    }
+}
+
+/// Regenerate a tile in every mip level of a sheet from the level above it.
+///
+/// Each texel is the alpha-weighted average of the four texels above it, computed in linear
+/// space, so transparent texels do not darken the edges of cut-out textures. Normals are
+/// renormalized after averaging. Tiles are aligned in every level, so texels are never averaged
+/// with neighbouring tiles.
+fn update_mip_tiles(
+    data: &mut [u8],
+    map: TextureMap,
+    coords: SheetCoords,
+    resolution: usize,
+    levels: u32,
+) {
+    let mut offset = 0;
+    let mut side = resolution * SHEET_HEIGHT;
+    let mut tile = resolution;
+    for _ in 1..levels {
+        let (source, target) = data[offset..].split_at_mut(4 * side * side);
+        let (x0, y0) = (coords.x_ix() as usize * tile, coords.y_ix() as usize * tile);
+        for y in 0..tile / 2 {
+            for x in 0..tile / 2 {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                    let ix = 4 * (x0 + 2 * x + dx + (y0 + 2 * y + dy) * side);
+                    &source[ix..ix + 4]
+                });
+                let ix = 4 * (x0 / 2 + x + (y0 / 2 + y) * side / 2);
+                target[ix..ix + 4].copy_from_slice(&mip_texel(map, texels));
+            }
+        }
+        offset += 4 * side * side;
+        side /= 2;
+        tile /= 2;
+    }
+}
+
+/// Average four texels of one mip level into a texel of the next, as described in
+/// [`update_mip_tiles`]
+fn mip_texel(map: TextureMap, texels: [&[u8]; 4]) -> [u8; 4] {
+    let mut color = Vec3::ZERO;
+    let mut alpha = 0.0;
+    let mut unweighted = Vec3::ZERO;
+    for texel in texels {
+        let linear = map.decode(texel);
+        let a = texel[3] as f32 / 255.0;
+        color += linear * a;
+        alpha += a;
+        unweighted += linear;
+    }
+    let color = if alpha > 0.0 {
+        color / alpha
+    } else {
+        unweighted / 4.0
+    };
+    let [r, g, b] = map.encode(color);
+    [r, g, b, (alpha / 4.0 * 255.0).round() as u8]
+}
+
+/// Build every mip level of a single tile showing one frame of an imported strip texture
+fn tile_levels(
+    texture: &Image,
+    map: TextureMap,
+    frame: u32,
+    resolution: usize,
+    levels: u32,
+) -> Vec<Vec<u8>> {
+    let row = 4 * resolution;
+    let frame = frame % strip_frames(texture);
+    let start = frame as usize * resolution * row;
+    let mut tiles = vec![texture.data[start..start + resolution * row].to_vec()];
+    let mut side = resolution;
+    for _ in 1..levels {
+        let source = tiles.last().unwrap();
+        let mut target = Vec::with_capacity(side * side);
+        for y in 0..side / 2 {
+            for x in 0..side / 2 {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| {
+                    let ix = 4 * (2 * x + dx + (2 * y + dy) * side);
+                    &source[ix..ix + 4]
+                });
+                target.extend(mip_texel(map, texels));
+            }
+        }
+        tiles.push(target);
+        side /= 2;
+    }
+    tiles
+}
+
+/// Get the number of square frames in a vertical strip texture
+#[inline]
+fn strip_frames(texture: &Image) -> u32 {
+    let size = texture.texture_descriptor.size;
+    (size.height / size.width.max(1)).max(1)
+}
+
+impl MaterialSettings {
+    /// Convert an image to 8-bit sRGBA, checking that it is a strip of square frames and
+    /// resampling each frame to this resolution if allowed
+    pub fn import(&self, texture: &Image) -> Result<Image, TextureError> {
+        let size = texture.texture_descriptor.size;
+        let (width, height) = (size.width, size.height);
+        if width == 0 || height == 0 || height % width != 0 {
+            return Err(TextureError::NonSquareFrames { width, height });
+        }
+        let image = texture.convert(TextureFormat::Rgba8UnormSrgb).ok_or(
+            TextureError::UnsupportedFormat(texture.texture_descriptor.format),
+        )?;
+        if width == self.resolution {
+            return Ok(image);
+        }
+        let mismatch = TextureError::SizeMismatch {
+            expected: self.resolution,
+            width,
+        };
+        match self.resampling {
+            TextureResampling::Reject => Err(mismatch),
+            resampling => Ok(resample_strip(&image, self.resolution, resampling)),
+        }
+    }
+}
+
+/// Resample each square frame of an 8-bit sRGBA strip to `resolution` pixels, without bleeding
+/// between frames
+fn resample_strip(image: &Image, resolution: u32, resampling: TextureResampling) -> Image {
+    let size = image.texture_descriptor.size;
+    let width = size.width as usize;
+    let frames = (size.height / size.width) as usize;
+    let resolution = resolution as usize;
+    let scale = width as f32 / resolution as f32;
+    let pixel = |frame: usize, x: usize, y: usize| {
+        let start = 4 * (x + (y + frame * width) * width);
+        &image.data[start..start + 4]
+    };
+    let mut data = Vec::with_capacity(4 * resolution * resolution * frames);
+    for frame in 0..frames {
+        for y in 0..resolution {
+            for x in 0..resolution {
+                // Sample at the center of the target pixel
+                let sx = (x as f32 + 0.5) * scale;
+                let sy = (y as f32 + 0.5) * scale;
+                match resampling {
+                    TextureResampling::Linear => {
+                        let fx = (sx - 0.5).clamp(0.0, (width - 1) as f32);
+                        let fy = (sy - 0.5).clamp(0.0, (width - 1) as f32);
+                        let (x0, y0) = (fx as usize, fy as usize);
+                        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(width - 1));
+                        let (tx, ty) = (fx.fract(), fy.fract());
+                        for c in 0..4 {
+                            let lerp = |a: u8, b: u8, t: f32| a as f32 + (b as f32 - a as f32) * t;
+                            let top = lerp(pixel(frame, x0, y0)[c], pixel(frame, x1, y0)[c], tx);
+                            let bottom = lerp(pixel(frame, x0, y1)[c], pixel(frame, x1, y1)[c], tx);
+                            data.push((top + (bottom - top) * ty).round() as u8);
+                        }
+                    }
+                    _ => {
+                        let (x, y) = ((sx as usize).min(width - 1), (sy as usize).min(width - 1));
+                        data.extend_from_slice(pixel(frame, x, y));
+                    }
+                }
+            }
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: resolution as u32,
+            height: (resolution * frames) as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Pod, Zeroable)]
 #[repr(transparent)]
 pub struct BlockTextureId(u32);
@@ -200,7 +985,12 @@ impl Default for BlockTextureId {
 #[repr(transparent)]
 pub struct SheetCoords(u16);
 
-pub const BORDER_WIDTH: f32 = 0.1 / (TEXTURE_HEIGHT * SHEET_HEIGHT) as f32;
+/// Get the width of the inset, in sheet texture coordinates, keeping samples of a tile from bleeding
+/// into its neighbours, for a sheet of the given resolution
+#[inline]
+pub fn border_width(resolution: u32) -> f32 {
+    0.1 / (resolution as usize * SHEET_HEIGHT) as f32
+}
 
 impl SheetCoords {
     pub fn x_ix(&self) -> u8 {
@@ -211,33 +1001,45 @@ impl SheetCoords {
         (self.0 / SHEET_HEIGHT as u16) as u8
     }
 
-    pub fn top_left(&self) -> [f32; 2] {
+    pub fn top_left(&self, resolution: u32) -> [f32; 2] {
+        let border = border_width(resolution);
         [
-            self.x_ix() as f32 / (SHEET_HEIGHT as f32) + BORDER_WIDTH,
-            self.y_ix() as f32 / (SHEET_HEIGHT as f32) + BORDER_WIDTH,
+            self.x_ix() as f32 / (SHEET_HEIGHT as f32) + border,
+            self.y_ix() as f32 / (SHEET_HEIGHT as f32) + border,
         ]
     }
 
-    pub fn top_right(&self) -> [f32; 2] {
+    pub fn top_right(&self, resolution: u32) -> [f32; 2] {
+        let border = border_width(resolution);
         [
-            (self.x_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - BORDER_WIDTH,
-            self.y_ix() as f32 / (SHEET_HEIGHT as f32) + BORDER_WIDTH,
+            (self.x_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - border,
+            self.y_ix() as f32 / (SHEET_HEIGHT as f32) + border,
         ]
     }
 
-    pub fn bottom_left(&self) -> [f32; 2] {
+    pub fn bottom_left(&self, resolution: u32) -> [f32; 2] {
+        let border = border_width(resolution);
         [
-            self.x_ix() as f32 / (SHEET_HEIGHT as f32) + BORDER_WIDTH,
-            (self.y_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - BORDER_WIDTH,
+            self.x_ix() as f32 / (SHEET_HEIGHT as f32) + border,
+            (self.y_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - border,
         ]
     }
 
-    pub fn bottom_right(&self) -> [f32; 2] {
+    pub fn bottom_right(&self, resolution: u32) -> [f32; 2] {
+        let border = border_width(resolution);
         [
-            (self.x_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - BORDER_WIDTH,
-            (self.y_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - BORDER_WIDTH,
+            (self.x_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - border,
+            (self.y_ix() as f32 + 1.0) / (SHEET_HEIGHT as f32) - border,
         ]
     }
+
+    /// Map texture coordinates in `[0, 1]` to coordinates within this tile of a sheet of the given
+    /// resolution
+    pub fn lerp(&self, uv: Vec2, resolution: u32) -> [f32; 2] {
+        let top_left = Vec2::from(self.top_left(resolution));
+        let bottom_right = Vec2::from(self.bottom_right(resolution));
+        (top_left + uv * (bottom_right - top_left)).to_array()
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Pod, Zeroable)]
@@ -259,35 +1061,263 @@ pub fn blit_loaded_textures(
             AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
                 let id: HandleId = handle.id();
                 let blocks = &mut *blocks;
-                for (lowest_id, block) in blocks
-                    .textures
-                    .range((id, BlockTextureId(0))..=(id, BlockTextureId(u32::MAX)))
-                {
+                let Some(data) = images.get(handle).cloned() else {
+                    continue;
+                };
+                for (lowest_id, block, map) in blocks.textures.range(
+                    (id, BlockTextureId(0), TextureMap::BaseColor)
+                        ..=(id, BlockTextureId(u32::MAX), TextureMap::Emissive),
+                ) {
                     debug_assert_eq!(id, *lowest_id);
-                    if let Some(data) = images.get(handle) {
-                        BlockMaterials::blit_texture_inner(
-                            &mut blocks.sheet_materials,
-                            *block,
-                            &data.convert(TextureFormat::Rgba8UnormSrgb).unwrap(),
-                            &mut images,
-                            &mut materials,
-                        )
+                    let frame = blocks.blocks[block.sheet().0 as usize][block.coords().0 as usize]
+                        .as_ref()
+                        .map_or(0, BlockData::current_frame);
+                    if let Err(err) = BlockMaterials::blit_texture_inner(
+                        &mut blocks.sheets,
+                        *block,
+                        *map,
+                        &data,
+                        frame,
+                        &mut images,
+                        &mut materials,
+                    ) {
+                        error!(
+                            "failed to import {:?} of block texture {:?}: {}",
+                            map, block, err
+                        );
                     }
                 }
             }
             AssetEvent::Removed { handle } => {
                 //TODO: optimize
                 let id = handle.id();
-                while let Some(&(lowest_id, block)) = blocks
+                while let Some(&(lowest_id, block, map)) = blocks
                     .textures
-                    .range((id, BlockTextureId(0))..=(id, BlockTextureId(u32::MAX)))
+                    .range(
+                        (id, BlockTextureId(0), TextureMap::BaseColor)
+                            ..=(id, BlockTextureId(u32::MAX), TextureMap::Emissive),
+                    )
                     .next()
                 {
                     debug_assert_eq!(id, lowest_id);
-                    let removed = blocks.textures.remove(&(lowest_id, block));
+                    let removed = blocks.textures.remove(&(lowest_id, block, map));
                     debug_assert!(removed);
                 }
             }
         }
     }
 }
+
+/// Writes of single tiles of sheet images, made directly on the GPU by [`write_animated_tiles`]
+/// so that animating a texture does not upload its whole sheet again
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AnimatedTileWrites {
+    writes: Vec<TileWrite>,
+}
+
+#[derive(Debug, Clone)]
+struct TileWrite {
+    image: Handle<Image>,
+    level: u32,
+    origin: UVec2,
+    side: u32,
+    data: Vec<u8>,
+}
+
+/// Advance animated block textures, writing their new frames into the tiles of their sheets.
+///
+/// The images of the sheets keep the frame which was shown when each texture was blitted, so the
+/// current frame of every animated tile is written again whenever a sheet image is re-uploaded.
+pub fn animate_block_textures(
+    time: Res<Time>,
+    mut events: EventReader<AssetEvent<Image>>,
+    mut blocks: ResMut<BlockMaterials>,
+    mut writes: ResMut<AnimatedTileWrites>,
+    images: Res<Assets<Image>>,
+    materials: Res<Assets<StandardMaterial>>,
+) {
+    let uploaded: BTreeSet<HandleId> = events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.id()),
+            AssetEvent::Removed { .. } => None,
+        })
+        .collect();
+    writes.writes.clear();
+    let delta = time.delta_seconds();
+    let blocks = &mut *blocks;
+    for &block in &blocks.animated {
+        let Some(data) = &mut blocks.blocks[block.sheet().0 as usize][block.coords().0 as usize]
+        else {
+            continue;
+        };
+        let (Some(texture), Some(state)) = (
+            &data.textures[TextureMap::BaseColor.index()],
+            &mut data.animation,
+        ) else {
+            continue;
+        };
+        let Some(image) = images.get(texture) else {
+            continue;
+        };
+        let steps = state.animation.steps(strip_frames(image));
+        let old_step = state.step;
+        state.elapsed += delta;
+        loop {
+            let (_, duration) = state.animation.step(state.step);
+            // Guard against zero-length frames spinning forever
+            if state.elapsed < duration || duration <= 0.0 {
+                break;
+            }
+            state.elapsed -= duration;
+            state.step = (state.step + 1) % steps.max(1);
+        }
+        let (frame, _) = state.animation.step(state.step);
+        let sheet = &blocks.sheets[block.sheet().0 as usize];
+        let Some(material) = materials.get(&sheet.material) else {
+            continue;
+        };
+        let resolution = sheet.settings.resolution as usize;
+        let levels = sheet
+            .settings
+            .filtering
+            .mip_levels(sheet.settings.resolution);
+        for map in TextureMap::ALL {
+            let Some(target) = map.image(material) else {
+                continue;
+            };
+            if state.step == old_step && !uploaded.contains(&target.id()) {
+                continue;
+            }
+            // Images which fail to import were already reported when they were loaded
+            let Some(Ok(texture)) = data.textures[map.index()]
+                .as_ref()
+                .and_then(|texture| images.get(texture))
+                .map(|image| sheet.settings.import(image))
+            else {
+                continue;
+            };
+            let coords = block.coords();
+            let origin = UVec2::new(coords.x_ix() as u32, coords.y_ix() as u32) * resolution as u32;
+            let tiles = tile_levels(&texture, map, frame, resolution, levels);
+            for (level, data) in tiles.into_iter().enumerate() {
+                writes.writes.push(TileWrite {
+                    image: target.clone(),
+                    level: level as u32,
+                    origin: origin >> level as u32,
+                    side: (resolution >> level) as u32,
+                    data,
+                });
+            }
+        }
+    }
+}
+
+/// Copy the tile writes made this frame into the render world
+pub fn extract_animated_tiles(mut commands: Commands, writes: Extract<Res<AnimatedTileWrites>>) {
+    commands.insert_resource(writes.clone());
+}
+
+/// Write animated tiles into the GPU textures of their sheets, after the sheets are prepared
+pub fn write_animated_tiles(
+    writes: Res<AnimatedTileWrites>,
+    images: Res<RenderAssets<Image>>,
+    queue: Res<RenderQueue>,
+) {
+    for write in &writes.writes {
+        let Some(image) = images.get(&write.image) else {
+            continue;
+        };
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &image.texture,
+                mip_level: write.level,
+                origin: Origin3d {
+                    x: write.origin.x,
+                    y: write.origin.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            &write.data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * write.side),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: write.side,
+                height: write.side,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A strip of two 4x4 frames with varying color and alpha
+    fn strip() -> Image {
+        let data = (0..2 * 4 * 4u8)
+            .flat_map(|i| [i * 7, 255 - i * 5, i * 3, if i % 3 == 0 { 0 } else { 255 }])
+            .collect();
+        Image::new(
+            Extent3d {
+                width: 4,
+                height: 8,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    #[test]
+    fn tile_levels_match_sheet_mips() {
+        let settings = MaterialSettings {
+            resolution: 4,
+            ..default()
+        };
+        let levels = settings.filtering.mip_levels(settings.resolution);
+        let mut sheet = new_sheet_image(&settings, TextureMap::BaseColor);
+        let coords = SheetCoords(3 + 2 * SHEET_HEIGHT as u16);
+        let texture = strip();
+        // Blit the second frame into the sheet as `blit_texture_inner` does
+        let side = 4 * SHEET_HEIGHT;
+        for y in 0..4 {
+            let target = 4 * (12 + (8 + y) * side);
+            let source = 4 * (16 + 4 * y);
+            sheet.data[target..target + 16].copy_from_slice(&texture.data[source..source + 16]);
+        }
+        update_mip_tiles(&mut sheet.data, TextureMap::BaseColor, coords, 4, levels);
+        let tiles = tile_levels(&texture, TextureMap::BaseColor, 1, 4, levels);
+        assert_eq!(tiles.len(), levels as usize);
+        let (mut offset, mut side, mut tile) = (0, side, 4);
+        for data in tiles {
+            assert_eq!(data.len(), 4 * tile * tile);
+            let (x0, y0) = (3 * tile, 2 * tile);
+            for y in 0..tile {
+                let start = offset + 4 * (x0 + (y0 + y) * side);
+                assert_eq!(
+                    sheet.data[start..start + 4 * tile],
+                    data[4 * tile * y..4 * tile * (y + 1)]
+                );
+            }
+            offset += 4 * side * side;
+            side /= 2;
+            tile /= 2;
+        }
+    }
+
+    #[test]
+    fn tile_levels_wrap_frames() {
+        let texture = strip();
+        assert_eq!(
+            tile_levels(&texture, TextureMap::BaseColor, 3, 4, 1),
+            tile_levels(&texture, TextureMap::BaseColor, 1, 4, 1)
+        );
+    }
+}
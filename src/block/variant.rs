@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+
+use super::{
+    face::{Face, FaceRotation, FaceTransform},
+    texture::BlockTextureId,
+};
+
+/// One of several textures a block face may show, chosen at random from the block's position
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TextureVariant {
+    pub texture: BlockTextureId,
+    pub transform: FaceTransform,
+    /// The relative probability of choosing this variant
+    pub weight: u32,
+}
+
+impl TextureVariant {
+    #[inline]
+    pub fn new(texture: BlockTextureId, transform: FaceTransform, weight: u32) -> TextureVariant {
+        TextureVariant {
+            texture,
+            transform,
+            weight,
+        }
+    }
+
+    /// Every rotation of a texture, with equal weights
+    #[inline]
+    pub fn rotations(texture: BlockTextureId) -> [TextureVariant; 4] {
+        FaceRotation::ALL
+            .map(|rotation| TextureVariant::new(texture, FaceTransform::rotated(rotation), 1))
+    }
+
+    /// Every rotation and mirroring of a texture, with equal weights
+    #[inline]
+    pub fn transforms(texture: BlockTextureId) -> [TextureVariant; 8] {
+        FaceTransform::ALL.map(|transform| TextureVariant::new(texture, transform, 1))
+    }
+}
+
+/// A weighted set of texture variants
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TextureVariants {
+    variants: Vec<TextureVariant>,
+    total_weight: u32,
+}
+
+impl TextureVariants {
+    /// Create a new set of variants, failing if their weights sum to zero or overflow
+    #[allow(clippy::result_unit_err)]
+    pub fn new(variants: Vec<TextureVariant>) -> Result<TextureVariants, ()> {
+        let total_weight = variants
+            .iter()
+            .try_fold(0u32, |acc, v| acc.checked_add(v.weight))
+            .ok_or(())?;
+        if total_weight == 0 {
+            return Err(());
+        }
+        Ok(TextureVariants {
+            variants,
+            total_weight,
+        })
+    }
+
+    #[inline]
+    pub fn variants(&self) -> &[TextureVariant] {
+        &self.variants
+    }
+
+    /// Pick the variant shown on a face of the block at `position`
+    pub fn pick(&self, position: IVec3, face: Face) -> &TextureVariant {
+        let mut choice = position_hash(position, face as u32) % self.total_weight;
+        for variant in &self.variants {
+            if choice < variant.weight {
+                return variant;
+            }
+            choice -= variant.weight;
+        }
+        unreachable!("choice is less than the total weight")
+    }
+}
+
+/// Hash a block position, along with a salt, into well-mixed bits
+#[inline]
+pub fn position_hash(position: IVec3, salt: u32) -> u32 {
+    let mut h = (position.x as u32).wrapping_mul(0x9E37_79B1)
+        ^ (position.y as u32).wrapping_mul(0x85EB_CA77)
+        ^ (position.z as u32).wrapping_mul(0xC2B2_AE3D)
+        ^ salt.wrapping_mul(0x27D4_EB2F);
+    // murmur3 finalizer
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xC2B2_AE35);
+    h ^= h >> 16;
+    h
+}
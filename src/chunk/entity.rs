@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    ecs::{system::EntityCommands, world::EntityRef},
+    prelude::*,
+};
+use fxhash::FxHashMap;
+
+use crate::block::{BlockId, BlockTypeId, Blocks};
+
+use super::{map::ChunkMap, BlockChanged, Chunk, ChunkData};
+
+/// Adds the components of a new block entity, given the block it belongs to
+pub type BlockEntitySpawner = Box<dyn Fn(&mut EntityCommands, BlockId) + Send + Sync>;
+
+/// Encodes the data of a block entity to be saved with its chunk
+pub type BlockEntitySaver = Box<dyn Fn(EntityRef) -> Vec<u8> + Send + Sync>;
+
+/// Restores the saved data of a block entity, once its spawner has added its components
+pub type BlockEntityLoader = Box<dyn Fn(&mut EntityCommands, &[u8]) + Send + Sync>;
+
+/// An entity holding the extra data of a single block, such as the contents of a chest.
+///
+/// Block entities are children of their chunk, so they are despawned, saved and loaded along with
+/// it.
+#[derive(Component, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BlockEntity {
+    /// The world position of the block
+    pub position: IVec3,
+}
+
+/// The block entities in a chunk, by the index of their block
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ChunkEntities {
+    entities: FxHashMap<[u8; 3], Entity>,
+}
+
+impl ChunkEntities {
+    /// Get the block entity of the block at `index`, if it has one
+    #[inline]
+    pub fn get(&self, index: [usize; 3]) -> Option<Entity> {
+        self.entities.get(&index.map(|i| i as u8)).copied()
+    }
+
+    /// Set the block entity of the block at `index`, returning the previous one
+    #[inline]
+    pub fn insert(&mut self, index: [usize; 3], entity: Entity) -> Option<Entity> {
+        self.entities.insert(index.map(|i| i as u8), entity)
+    }
+
+    /// Remove the block entity of the block at `index`, returning it
+    #[inline]
+    pub fn remove(&mut self, index: [usize; 3]) -> Option<Entity> {
+        self.entities.remove(&index.map(|i| i as u8))
+    }
+
+    /// Iterate over the indices of the blocks with block entities, and their entities
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = ([usize; 3], Entity)> + '_ {
+        self.entities
+            .iter()
+            .map(|(index, &entity)| (index.map(|i| i as usize), entity))
+    }
+}
+
+/// The saved data of the block entities of a chunk, by the index of their block, which is restored
+/// as they are spawned when the chunk is loaded
+#[derive(Component, Debug, Clone, Eq, PartialEq, Default)]
+pub struct SavedBlockEntities {
+    entities: BTreeMap<[u8; 3], Vec<u8>>,
+}
+
+impl SavedBlockEntities {
+    /// Get the saved data of the block entity of the block at `index`, if it has any
+    #[inline]
+    pub fn get(&self, index: [usize; 3]) -> Option<&[u8]> {
+        self.entities
+            .get(&index.map(|i| i as u8))
+            .map(Vec::as_slice)
+    }
+
+    /// Get whether no block entities were saved
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Encode the saved block entities, each as the index of its block then its length-prefixed
+    /// data
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (index, data) in &self.entities {
+            bytes.extend_from_slice(index);
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Decode block entities saved by [`SavedBlockEntities::to_bytes`], or `None` if the bytes are
+    /// malformed
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<SavedBlockEntities> {
+        let mut saved = SavedBlockEntities::default();
+        while !bytes.is_empty() {
+            let index: [u8; 3] = bytes.get(..3)?.try_into().ok()?;
+            let len = u32::from_le_bytes(bytes.get(3..7)?.try_into().ok()?) as usize;
+            let data = bytes.get(7..7 + len)?;
+            if index.iter().any(|&i| i >= 16) {
+                return None;
+            }
+            saved.entities.insert(index, data.to_vec());
+            bytes = &bytes[7 + len..];
+        }
+        Some(saved)
+    }
+}
+
+/// The block types whose blocks each have a block entity, and how to spawn, save and load them
+#[derive(Resource, Default)]
+pub struct BlockEntityTypes {
+    spawners: FxHashMap<BlockTypeId, BlockEntitySpawner>,
+    persisted: FxHashMap<BlockTypeId, (BlockEntitySaver, BlockEntityLoader)>,
+}
+
+impl BlockEntityTypes {
+    /// Give every block of a type a block entity, whose components are added by `spawner`
+    #[inline]
+    pub fn register(
+        &mut self,
+        block_type: BlockTypeId,
+        spawner: impl Fn(&mut EntityCommands, BlockId) + Send + Sync + 'static,
+    ) {
+        self.spawners.insert(block_type, Box::new(spawner));
+    }
+
+    /// Save the data of the block entities of a type along with their chunk, encoding it with
+    /// `save` and restoring it with `load` once the chunk is loaded and they are spawned again.
+    ///
+    /// Block entities of types without these are spawned afresh when their chunk is loaded.
+    #[inline]
+    pub fn persist(
+        &mut self,
+        block_type: BlockTypeId,
+        save: impl Fn(EntityRef) -> Vec<u8> + Send + Sync + 'static,
+        load: impl Fn(&mut EntityCommands, &[u8]) + Send + Sync + 'static,
+    ) {
+        self.persisted
+            .insert(block_type, (Box::new(save), Box::new(load)));
+    }
+
+    /// Forget the block entity spawner of a block type which is being unregistered
+    #[inline]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) {
+        self.spawners.remove(&block_type);
+        self.persisted.remove(&block_type);
+    }
+
+    /// Get whether blocks of a type have block entities
+    #[inline]
+    pub fn has_entity(&self, block_type: BlockTypeId) -> bool {
+        self.spawners.contains_key(&block_type)
+    }
+
+    /// Encode the data of the block entities in a chunk whose types are saved, to be saved with
+    /// the chunk by [`Chunk::save`]
+    pub fn save(&self, world: &World, chunk: &Chunk) -> SavedBlockEntities {
+        let blocks = world.resource::<Blocks>();
+        let mut saved = SavedBlockEntities::default();
+        for (index, entity) in chunk.entities.iter() {
+            let block = chunk.data.blocks[index[0]][index[1]][index[2]];
+            let Some((save, _)) = blocks
+                .block_type(block)
+                .and_then(|block_type| self.persisted.get(&block_type))
+            else {
+                continue;
+            };
+            if let Some(entity) = world.get_entity(entity) {
+                saved.entities.insert(index.map(|i| i as u8), save(entity));
+            }
+        }
+        saved
+    }
+
+    /// Spawn the block entity of a block in a chunk, if its type has one, restoring its `saved`
+    /// data if it was loaded with the chunk
+    #[allow(clippy::too_many_arguments)]
+    fn spawn(
+        &self,
+        commands: &mut Commands,
+        blocks: &Blocks,
+        chunk_entity: Entity,
+        chunk: &mut Chunk,
+        origin: IVec3,
+        index: [usize; 3],
+        saved: Option<&[u8]>,
+    ) {
+        let block = chunk.data.blocks[index[0]][index[1]][index[2]];
+        let Some(block_type) = blocks.block_type(block) else {
+            return;
+        };
+        let Some(spawner) = self.spawners.get(&block_type) else {
+            return;
+        };
+        let position = origin + IVec3::new(index[0] as i32, index[1] as i32, index[2] as i32);
+        let mut entity = commands.spawn((
+            BlockEntity { position },
+            SpatialBundle::from_transform(Transform::from_translation(ChunkData::block_center(
+                index,
+            ))),
+        ));
+        spawner(&mut entity, block);
+        if let (Some(saved), Some((_, load))) = (saved, self.persisted.get(&block_type)) {
+            load(&mut entity, saved);
+        }
+        let entity = entity.set_parent(chunk_entity).id();
+        if let Some(old) = chunk.entities.insert(index, entity) {
+            commands.entity(old).despawn_recursive();
+        }
+    }
+}
+
+/// Spawn the block entities of blocks in newly spawned chunks which don't have them yet, restoring
+/// the data of those saved with a loaded chunk
+pub fn spawn_chunk_block_entities(
+    mut commands: Commands,
+    blocks: Res<Blocks>,
+    types: Res<BlockEntityTypes>,
+    mut chunks: Query<(Entity, &mut Chunk, &Transform, Option<&SavedBlockEntities>), Added<Chunk>>,
+) {
+    for (chunk_entity, mut chunk, transform, saved) in chunks.iter_mut() {
+        let origin = Chunk::block_origin(transform);
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    if chunk.entities.get([x, y, z]).is_none() {
+                        types.spawn(
+                            &mut commands,
+                            &blocks,
+                            chunk_entity,
+                            &mut chunk,
+                            origin,
+                            [x, y, z],
+                            saved.and_then(|saved| saved.get([x, y, z])),
+                        );
+                    }
+                }
+            }
+        }
+        if saved.is_some() {
+            commands.entity(chunk_entity).remove::<SavedBlockEntities>();
+        }
+    }
+}
+
+/// Despawn the block entities of changed blocks, and spawn those of the blocks replacing them,
+/// keeping block entities across changes to the state of their block
+pub fn update_block_entities(
+    mut commands: Commands,
+    mut changed: EventReader<BlockChanged>,
+    blocks: Res<Blocks>,
+    types: Res<BlockEntityTypes>,
+    map: Res<ChunkMap>,
+    mut chunks: Query<&mut Chunk>,
+) {
+    for event in changed.iter() {
+        let old_type = blocks.block_type(event.old);
+        let new_type = blocks.block_type(event.new);
+        if old_type == new_type {
+            continue;
+        }
+        let (coords, index) = ChunkMap::chunk_coords(event.position);
+        let Some(chunk_entity) = map.get(coords) else {
+            continue;
+        };
+        let Ok(mut chunk) = chunks.get_mut(chunk_entity) else {
+            continue;
+        };
+        if let Some(entity) = chunk.entities.remove(index) {
+            commands.entity(entity).despawn_recursive();
+        }
+        if chunk.data.blocks[index[0]][index[1]][index[2]] == event.new {
+            let origin = ChunkMap::chunk_origin(coords);
+            types.spawn(
+                &mut commands,
+                &blocks,
+                chunk_entity,
+                &mut chunk,
+                origin,
+                index,
+                None,
+            );
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeSet;
+
+use bevy::{ecs::system::SystemParam, math::Vec3Swizzles, prelude::*};
+use fxhash::{FxHashMap, FxHashSet};
+
+use crate::{
+    block::{face::Face, BlockId, Blocks},
+    tick::WorldTick,
+};
+
+use super::{mesher::ChunkNeedsMeshing, BlockBroken, BlockChanged, Chunk, CHUNK_SIZE};
+
+/// An index of the chunks in the world by their chunk coordinates, where the chunk at coordinates
+/// `c` holds the blocks from `CHUNK_SIZE * c` up to, but excluding, `CHUNK_SIZE * (c + 1)`
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ChunkMap {
+    chunks: FxHashMap<IVec3, Entity>,
+    /// The loaded chunks and sky heights of each column of chunks, by the chunks' x and z
+    /// coordinates
+    columns: FxHashMap<IVec2, ChunkColumn>,
+}
+
+#[derive(Debug, Clone)]
+struct ChunkColumn {
+    /// The y coordinates of the loaded chunks in this column
+    chunks: BTreeSet<i32>,
+    /// The sky height of each column of blocks, indexed by x then z
+    heights: [[i32; 16]; 16],
+}
+
+impl ChunkMap {
+    /// Get the chunk at the given chunk coordinates
+    #[inline]
+    pub fn get(&self, coords: IVec3) -> Option<Entity> {
+        self.chunks.get(&coords).copied()
+    }
+
+    /// Get the coordinates of the chunk containing a block, and the block's index within it
+    #[inline]
+    pub fn chunk_coords(position: IVec3) -> (IVec3, [usize; 3]) {
+        let coords = position.div_euclid(IVec3::splat(CHUNK_SIZE));
+        let index = position.rem_euclid(IVec3::splat(CHUNK_SIZE));
+        (
+            coords,
+            [index.x as usize, index.y as usize, index.z as usize],
+        )
+    }
+
+    /// Get the world position of the block at index `[0, 0, 0]` of the chunk at the given chunk
+    /// coordinates
+    #[inline]
+    pub fn chunk_origin(coords: IVec3) -> IVec3 {
+        coords * CHUNK_SIZE
+    }
+
+    /// Get the translation of the chunk at the given chunk coordinates
+    #[inline]
+    pub fn chunk_translation(coords: IVec3) -> Vec3 {
+        (coords * CHUNK_SIZE).as_vec3() + 8.0
+    }
+
+    /// Get the world height of the highest loaded block covering a column of blocks from the sky,
+    /// or `i32::MIN` if nothing covers it
+    #[inline]
+    pub fn sky_height(&self, x: i32, z: i32) -> i32 {
+        let (coords, [x, _, z]) = Self::chunk_coords(IVec3::new(x, 0, z));
+        self.columns
+            .get(&coords.xz())
+            .map_or(i32::MIN, |column| column.heights[x][z])
+    }
+
+    /// Find the sky height of a column of blocks by searching the loaded chunks from the top
+    fn find_sky_height(&self, chunks: &Query<&Chunk>, blocks: &Blocks, x: i32, z: i32) -> i32 {
+        let (coords, [x, _, z]) = Self::chunk_coords(IVec3::new(x, 0, z));
+        let Some(column) = self.columns.get(&coords.xz()) else {
+            return i32::MIN;
+        };
+        for &y in column.chunks.iter().rev() {
+            let Some(chunk) = self
+                .get(IVec3::new(coords.x, y, coords.z))
+                .and_then(|entity| chunks.get(entity).ok())
+            else {
+                continue;
+            };
+            if let Some(top) = (0..16)
+                .rev()
+                .find(|&top| covers_sky(blocks, chunk.data.blocks[x][top][z]))
+            {
+                return y * CHUNK_SIZE + top as i32;
+            }
+        }
+        i32::MIN
+    }
+
+    /// Set the sky height of a column of blocks, collecting the chunks whose faces it moves into or
+    /// out of the sky's light: those spanning the heights between the old and new height, in the
+    /// column and the columns beside it
+    fn set_sky_height(&mut self, x: i32, z: i32, height: i32, remesh: &mut FxHashSet<IVec3>) {
+        let (coords, [ix, _, iz]) = Self::chunk_coords(IVec3::new(x, 0, z));
+        let Some(column) = self.columns.get_mut(&coords.xz()) else {
+            return;
+        };
+        let old = std::mem::replace(&mut column.heights[ix][iz], height);
+        if old == height {
+            return;
+        }
+        let (low, high) = (old.min(height), old.max(height));
+        for offset in [IVec2::ZERO, IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+            let (coords, _) = Self::chunk_coords(IVec3::new(x + offset.x, 0, z + offset.y));
+            let Some(column) = self.columns.get(&coords.xz()) else {
+                continue;
+            };
+            for &y in &column.chunks {
+                let bottom = y * CHUNK_SIZE;
+                if bottom <= high && bottom + CHUNK_SIZE > low {
+                    remesh.insert(IVec3::new(coords.x, y, coords.z));
+                }
+            }
+        }
+    }
+
+    /// Get a block in a chunk from a query
+    #[inline]
+    pub fn get_block(&self, chunks: &Query<&Chunk>, position: IVec3) -> Option<BlockId> {
+        let (coords, [x, y, z]) = Self::chunk_coords(position);
+        let chunk = chunks.get(self.get(coords)?).ok()?;
+        Some(chunk.data.blocks[x][y][z])
+    }
+
+    /// Get the block entity of a block from a query, if it has one
+    #[inline]
+    pub fn get_block_entity(&self, chunks: &Query<&Chunk>, position: IVec3) -> Option<Entity> {
+        let (coords, index) = Self::chunk_coords(position);
+        let chunk = chunks.get(self.get(coords)?).ok()?;
+        chunk.entities.get(index)
+    }
+}
+
+/// Get whether a block hides the blocks below it from the sky
+#[inline]
+fn covers_sky(blocks: &Blocks, block: BlockId) -> bool {
+    let data = blocks.get_meshing_data(block);
+    data.opacity == u32::MAX && blocks.is_full_cube(&data)
+}
+
+/// Keep the [`ChunkMap`] up to date as chunks are spawned and despawned, remeshing the neighbours
+/// of new chunks so that the faces between them are hidden.
+///
+/// The sky heights of the map are kept up to date as chunks are loaded and unloaded and blocks
+/// change, remeshing the chunks whose faces move into or out of the sky's light.
+pub fn index_chunks(
+    mut commands: Commands,
+    mut map: ResMut<ChunkMap>,
+    blocks: Res<Blocks>,
+    chunks: Query<&Chunk>,
+    added: Query<(Entity, &Transform), Added<Chunk>>,
+    mut removed: RemovedComponents<Chunk>,
+    mut changed: EventReader<BlockChanged>,
+) {
+    let mut remesh = FxHashSet::default();
+    for entity in removed.iter() {
+        let mut unloaded = Vec::new();
+        map.chunks.retain(|&coords, &mut chunk| {
+            if chunk == entity {
+                unloaded.push(coords);
+            }
+            chunk != entity
+        });
+        for coords in unloaded {
+            let Some(column) = map.columns.get_mut(&coords.xz()) else {
+                continue;
+            };
+            column.chunks.remove(&coords.y);
+            if column.chunks.is_empty() {
+                map.columns.remove(&coords.xz());
+                continue;
+            }
+            let origin = ChunkMap::chunk_origin(coords);
+            for x in origin.x..origin.x + CHUNK_SIZE {
+                for z in origin.z..origin.z + CHUNK_SIZE {
+                    let height = map.find_sky_height(&chunks, &blocks, x, z);
+                    map.set_sky_height(x, z, height, &mut remesh);
+                }
+            }
+        }
+    }
+    for (entity, transform) in added.iter() {
+        let origin = Chunk::block_origin(transform);
+        let (coords, index) = ChunkMap::chunk_coords(origin);
+        if index != [0; 3] {
+            warn!(
+                "chunk {:?} at {:?} is not aligned to the chunk grid",
+                entity, origin
+            );
+            continue;
+        }
+        map.chunks.insert(coords, entity);
+        for face in Face::ALL {
+            if let Some(neighbor) = map.get(coords + face.offset()) {
+                commands.entity(neighbor).insert(ChunkNeedsMeshing);
+            }
+        }
+        map.columns
+            .entry(coords.xz())
+            .or_insert_with(|| ChunkColumn {
+                chunks: BTreeSet::new(),
+                heights: [[i32::MIN; 16]; 16],
+            })
+            .chunks
+            .insert(coords.y);
+        let Ok(chunk) = chunks.get(entity) else {
+            continue;
+        };
+        for x in 0..16 {
+            for z in 0..16 {
+                let Some(top) = (0..16)
+                    .rev()
+                    .find(|&y| covers_sky(&blocks, chunk.data.blocks[x][y][z]))
+                else {
+                    continue;
+                };
+                let (x, z) = (origin.x + x as i32, origin.z + z as i32);
+                let height = origin.y + top as i32;
+                if height > map.sky_height(x, z) {
+                    map.set_sky_height(x, z, height, &mut remesh);
+                }
+            }
+        }
+    }
+    for event in changed.iter() {
+        let IVec3 { x, y, z } = event.position;
+        let height = map.sky_height(x, z);
+        if covers_sky(&blocks, event.new) {
+            if y > height {
+                map.set_sky_height(x, z, y, &mut remesh);
+            }
+        } else if y == height {
+            let height = map.find_sky_height(&chunks, &blocks, x, z);
+            map.set_sky_height(x, z, height, &mut remesh);
+        }
+    }
+    for coords in remesh {
+        if let Some(chunk) = map.get(coords) {
+            commands.entity(chunk).insert(ChunkNeedsMeshing);
+        }
+    }
+}
+
+/// Reads and writes blocks anywhere in the world by their world position, across chunk borders
+#[derive(SystemParam)]
+pub struct WorldBlocks<'w, 's> {
+    map: Res<'w, ChunkMap>,
+    chunks: Query<'w, 's, &'static mut Chunk>,
+    tick: Res<'w, WorldTick>,
+    changed: EventWriter<'w, BlockChanged>,
+    broken: EventWriter<'w, BlockBroken>,
+    commands: Commands<'w, 's>,
+}
+
+impl<'w, 's> WorldBlocks<'w, 's> {
+    /// Get the block at a position, or `None` if no chunk containing it is loaded
+    #[inline]
+    pub fn get(&self, position: IVec3) -> Option<BlockId> {
+        let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+        let chunk = self.chunks.get(self.map.get(coords)?).ok()?;
+        Some(chunk.data.blocks[x][y][z])
+    }
+
+    /// Get the block entity of the block at a position, if it has one
+    #[inline]
+    pub fn block_entity(&self, position: IVec3) -> Option<Entity> {
+        let (coords, index) = ChunkMap::chunk_coords(position);
+        let chunk = self.chunks.get(self.map.get(coords)?).ok()?;
+        chunk.entities.get(index)
+    }
+
+    /// Replace the block at a position, remeshing its chunk and updating it and its neighbours,
+    /// and returning the previous block, or `None` if no chunk containing it is loaded
+    pub fn set(&mut self, position: IVec3, block: BlockId) -> Option<BlockId> {
+        let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+        let entity = self.map.get(coords)?;
+        let mut chunk = self.chunks.get_mut(entity).ok()?;
+        let old = chunk.data.blocks[x][y][z];
+        if old != block {
+            chunk.data.blocks[x][y][z] = block;
+            self.commands.entity(entity).insert(ChunkNeedsMeshing);
+            self.changed.send(BlockChanged {
+                position,
+                old,
+                new: block,
+            });
+        }
+        Some(old)
+    }
+
+    /// Replace the block at a position with air, as if the player broke it so that it drops as an
+    /// item, and return the broken block, or `None` if it was air or no chunk containing it is
+    /// loaded
+    pub fn break_block(&mut self, position: IVec3) -> Option<BlockId> {
+        let block = self.set(position, BlockId::default())?;
+        if block == BlockId::default() {
+            return None;
+        }
+        let (coords, index) = ChunkMap::chunk_coords(position);
+        self.broken.send(BlockBroken {
+            chunk: self.map.get(coords)?,
+            index,
+            block,
+            position: position.as_vec3(),
+        });
+        Some(block)
+    }
+
+    /// Get the current world tick
+    #[inline]
+    pub fn tick(&self) -> u64 {
+        self.tick.0
+    }
+
+    /// Update the block at a position after `delay` ticks, or on the next tick if `delay` is zero,
+    /// returning `false` if no chunk containing it is loaded
+    pub fn schedule_tick(&mut self, position: IVec3, delay: u64) -> bool {
+        let (coords, index) = ChunkMap::chunk_coords(position);
+        let due = self.tick.0 + delay.max(1);
+        match self.chunk_mut(coords) {
+            Some(mut chunk) => {
+                chunk.ticks.schedule(due, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the coordinates of every loaded chunk
+    #[inline]
+    pub fn loaded_chunks(&self) -> Vec<IVec3> {
+        self.map.chunks.keys().copied().collect()
+    }
+
+    /// Get the commands used to remesh chunks, to spawn entities alongside changes to blocks
+    #[inline]
+    pub fn commands(&mut self) -> &mut Commands<'w, 's> {
+        &mut self.commands
+    }
+
+    /// Get the chunk at the given chunk coordinates, if it is loaded
+    #[inline]
+    pub fn chunk_mut(&mut self, coords: IVec3) -> Option<Mut<'_, Chunk>> {
+        self.chunks.get_mut(self.map.get(coords)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{state::StateValues, MeshingData};
+
+    fn solid(_: StateValues) -> MeshingData {
+        MeshingData {
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        }
+    }
+
+    /// Set a block directly in a chunk, sending the event [`WorldBlocks::set`] would
+    fn set(world: &mut World, position: IVec3, block: BlockId) {
+        let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+        let entity = world.resource::<ChunkMap>().get(coords).unwrap();
+        let mut chunk = world.get_mut::<Chunk>(entity).unwrap();
+        let old = std::mem::replace(&mut chunk.data.blocks[x][y][z], block);
+        world.send_event(BlockChanged {
+            position,
+            old,
+            new: block,
+        });
+    }
+
+    #[test]
+    fn sky_heights_follow_chunks_and_blocks() {
+        let mut world = World::new();
+        world.init_resource::<Blocks>();
+        world.init_resource::<ChunkMap>();
+        world.init_resource::<Events<BlockChanged>>();
+        let mut blocks = world.resource_mut::<Blocks>();
+        let stone = blocks.new_block_type(&[], solid).unwrap();
+        let stone = blocks.default_state(stone);
+
+        let mut ground = Chunk::default();
+        for slice in &mut ground.data.blocks {
+            slice[..4].fill([stone; 16]);
+        }
+        world.spawn((ground, Transform::from_translation(Vec3::splat(8.0))));
+        let mut index = Schedule::default();
+        index.add_systems(index_chunks);
+        let sky = world
+            .spawn((
+                Chunk::default(),
+                Transform::from_translation(Vec3::new(8.0, 24.0, 8.0)),
+            ))
+            .id();
+        index.run(&mut world);
+        let map = world.resource::<ChunkMap>();
+        assert_eq!(map.sky_height(5, 5), 3);
+        assert_eq!(map.sky_height(16, 5), i32::MIN);
+
+        set(&mut world, IVec3::new(5, 20, 5), stone);
+        index.run(&mut world);
+        assert_eq!(world.resource::<ChunkMap>().sky_height(5, 5), 20);
+
+        set(&mut world, IVec3::new(5, 3, 5), BlockId::default());
+        index.run(&mut world);
+        assert_eq!(world.resource::<ChunkMap>().sky_height(5, 5), 20);
+
+        world.despawn(sky);
+        index.run(&mut world);
+        assert_eq!(world.resource::<ChunkMap>().sky_height(5, 5), 2);
+        assert_eq!(world.resource::<ChunkMap>().sky_height(6, 5), 3);
+    }
+}
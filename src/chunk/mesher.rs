@@ -1,7 +1,11 @@
+use crate::biome::BiomeMap;
 use crate::block::{
-    texture::{BlockMaterials, SheetId},
-    Blocks,
+    face::{Corner, Face, FaceTransform},
+    model::ModelBox,
+    texture::{BlockMaterials, BlockTextureId, SheetId, DEFAULT_RESOLUTION},
+    Blocks, MeshingData,
 };
+use crate::sky::{ChunkMaterials, SkyLight};
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
@@ -9,44 +13,311 @@ use bevy::{
 use bevy_rapier3d::prelude::*;
 use fxhash::FxHashMap;
 
-use super::{Chunk, IsChunkMesh};
+use super::{map::ChunkMap, BlockChanged, Chunk, IsChunkMesh};
+
+/// How far overlay quads are pushed out from the faces they cover, so they are always drawn on top
+const OVERLAY_OFFSET: f32 = 1.0 / 512.0;
+
+/// The light a face is lit by, which is applied through the material of the mesh it is placed in,
+/// so that the day cycle changes the brightness of chunks without remeshing them
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Default)]
+pub enum MeshLight {
+    /// The light of the sky, which follows the time of day
+    #[default]
+    Sky,
+    /// The dim, constant light of places the sky does not reach
+    Cave,
+}
+
+/// How each face of a block is drawn, indexed by [`Face::index`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlockFaces {
+    pub textures: [(BlockTextureId, FaceTransform); 6],
+    /// The linear color each face's texture is multiplied by
+    pub colors: [[f32; 4]; 6],
+    /// The texture and color of the overlay covering each face, if any
+    pub overlays: [Option<(BlockTextureId, [f32; 4])>; 6],
+    pub lights: [MeshLight; 6],
+}
 
 #[derive(Default, Clone, PartialEq)]
 pub struct Mesher {
-    pub meshes: FxHashMap<SheetId, Premesh>,
+    pub meshes: FxHashMap<(SheetId, MeshLight), Premesh>,
     pub physics_vertices: Vec<Vec3>,
     pub physics_triangles: Vec<[u32; 3]>,
+    /// The texture resolution of each sheet, which sets the inset of its tiles' texture coordinates
+    pub resolutions: FxHashMap<SheetId, u32>,
 }
 
 impl Mesher {
+    /// Create a mesher for blocks textured from the current sheets of `materials`
+    pub fn new(materials: &BlockMaterials) -> Mesher {
+        Mesher {
+            resolutions: materials
+                .sheets()
+                .map(|sheet| (sheet, materials.get_sheet_settings(sheet).resolution))
+                .collect(),
+            ..default()
+        }
+    }
+
+    /// Get the texture resolution of a sheet
+    #[inline]
+    pub fn resolution(&self, sheet: SheetId) -> u32 {
+        self.resolutions
+            .get(&sheet)
+            .copied()
+            .unwrap_or(DEFAULT_RESOLUTION)
+    }
+
     pub fn clear(&mut self) {
         self.meshes.clear();
         self.physics_vertices.clear();
         self.physics_triangles.clear();
     }
+
+    /// Mesh one box of a block centered at `center`, culling faces against its neighbours.
+    ///
+    /// Faces with an overlay are covered by a second quad with the overlay's texture and color.
+    pub fn push_box(
+        &mut self,
+        me: &MeshingData,
+        faces: &BlockFaces,
+        model_box: &ModelBox,
+        center: Vec3,
+        neighbors: &[NeighborData; 6],
+        is_cube: bool,
+    ) {
+        for face in Face::ALL {
+            let neighbor = &neighbors[face.index()];
+            let culled = model_box.cull[face.index()];
+            let vertices = face.vertices().map(|v| model_box.map_vertex(Vec3::from(v)));
+
+            let visible = if !culled {
+                true
+            } else if is_cube {
+                me.opacity != neighbor.opacity
+            } else {
+                neighbor.opacity != u32::MAX && neighbor.opacity != me.opacity
+            };
+            //TODO: shared texture optimization?
+            if visible {
+                let (texture, transform) = faces.textures[face.index()];
+                let light = faces.lights[face.index()];
+                let color = faces.colors[face.index()];
+                self.push_face(face, texture, transform, vertices, center, color, light);
+                if let Some((overlay, color)) = faces.overlays[face.index()] {
+                    let center = center + OVERLAY_OFFSET * face.normal();
+                    self.push_face(face, overlay, transform, vertices, center, color, light);
+                }
+            }
+            if me.solid && !(culled && neighbor.solid) {
+                let v = self.physics_vertices.len() as u32;
+                self.physics_vertices.extend(vertices.map(|v| center + v));
+                let t = face.triangles().map(|t| v + t as u32);
+                self.physics_triangles
+                    .extend([[t[0], t[1], t[2]], [t[3], t[4], t[5]]]);
+            }
+        }
+    }
+
+    /// Mesh one face of a box, given the face's vertices relative to `center`
+    #[allow(clippy::too_many_arguments)]
+    fn push_face(
+        &mut self,
+        face: Face,
+        texture: BlockTextureId,
+        transform: FaceTransform,
+        vertices: [Vec3; 4],
+        center: Vec3,
+        color: [f32; 4],
+        light: MeshLight,
+    ) {
+        let resolution = self.resolution(texture.sheet());
+        let mesh = self.meshes.entry((texture.sheet(), light)).or_default();
+        let coords = texture.coords();
+        let v = mesh.vertices.len() as u32;
+        let uv = vertices.map(|v| face.texture_coords(transform, v));
+        mesh.vertices
+            .extend(vertices.map(|v| (center + v).to_array()));
+        mesh.normals.extend([face.normal().to_array(); 4]);
+        mesh.tangents
+            .extend([quad_tangent(vertices, uv, face.normal()); 4]);
+        mesh.uv.extend(uv.map(|uv| coords.lerp(uv, resolution)));
+        mesh.colors.extend([color; 4]);
+        mesh.triangles
+            .extend(face.triangles().map(|t| v + t as u32));
+    }
+
+    /// Mesh a pair of crossed, double-sided quads centered at `center`
+    pub fn push_cross(
+        &mut self,
+        texture: BlockTextureId,
+        transform: FaceTransform,
+        color: [f32; 4],
+        light: MeshLight,
+        center: Vec3,
+    ) {
+        let resolution = self.resolution(texture.sheet());
+        let mesh = self.meshes.entry((texture.sheet(), light)).or_default();
+        let coords = texture.coords();
+        let uv = [
+            Corner::BottomLeft,
+            Corner::BottomRight,
+            Corner::TopRight,
+            Corner::TopLeft,
+        ]
+        .map(|corner| corner.transform(transform).offset());
+        for diagonal in [Vec3::new(1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0)] {
+            let start = center - 0.5 * diagonal;
+            let end = center + 0.5 * diagonal;
+            let vertices = [
+                start - 0.5 * Vec3::Y,
+                end - 0.5 * Vec3::Y,
+                end + 0.5 * Vec3::Y,
+                start + 0.5 * Vec3::Y,
+            ];
+            let normal = diagonal.cross(Vec3::Y).normalize();
+            for (normal, triangles) in [(normal, [0, 2, 1, 0, 3, 2]), (-normal, [0, 1, 2, 0, 2, 3])]
+            {
+                let v = mesh.vertices.len() as u32;
+                mesh.vertices.extend(vertices.map(|v| v.to_array()));
+                mesh.normals.extend([normal.to_array(); 4]);
+                mesh.tangents
+                    .extend([quad_tangent(vertices, uv, normal); 4]);
+                mesh.uv.extend(uv.map(|uv| coords.lerp(uv, resolution)));
+                mesh.colors.extend([color; 4]);
+                mesh.triangles.extend(triangles.map(|t: u32| v + t));
+            }
+        }
+    }
+}
+
+/// Compute the tangent of a planar quad for normal mapping, pointing along increasing `u`, with the
+/// handedness of the bitangent in `w`
+fn quad_tangent(vertices: [Vec3; 4], uv: [Vec2; 4], normal: Vec3) -> [f32; 4] {
+    let (e1, e2) = (vertices[1] - vertices[0], vertices[2] - vertices[0]);
+    let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+    let det = d1.x * d2.y - d2.x * d1.y;
+    if det.abs() < 1e-8 {
+        return normal.any_orthonormal_vector().extend(1.0).to_array();
+    }
+    let tangent = (e1 * d2.y - e2 * d1.y) / det;
+    let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+    // Gram-Schmidt orthogonalize against the normal
+    let tangent = (tangent - normal * normal.dot(tangent)).normalize();
+    let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    tangent.extend(handedness).to_array()
+}
+
+/// The properties of a neighbouring block used to cull faces
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct NeighborData {
+    /// The opacity of the neighbour, or `0` if it does not fill its block
+    pub opacity: u32,
+    /// Whether the neighbour is solid and fills its block
+    pub solid: bool,
+}
+
+impl NeighborData {
+    #[inline]
+    pub fn new(blocks: &Blocks, data: &MeshingData) -> NeighborData {
+        if blocks.is_full_cube(data) {
+            NeighborData {
+                opacity: data.opacity,
+                solid: data.solid,
+            }
+        } else {
+            NeighborData::default()
+        }
+    }
 }
 
 #[derive(Default, Clone, PartialEq)]
 
 pub struct Premesh {
     pub vertices: Vec<[f32; 3]>,
-    pub triangles: Vec<u16>,
+    pub triangles: Vec<u32>,
     pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 4]>,
     pub uv: Vec<[f32; 2]>,
+    /// Linear vertex colors multiplying the texture, from the block's tint
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl Premesh {
+    /// Build a new mesh from this premesh
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.vertices);
+        mesh.set_indices(Some(Indices::U32(self.triangles)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, self.tangents);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uv);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh
+    }
+
+    /// Overwrite the contents of a mesh built by [`Premesh::into_mesh`]
+    pub fn write_mesh(self, mesh: &mut Mesh) {
+        *mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION).unwrap() = self.vertices.into();
+        mesh.set_indices(Some(Indices::U32(self.triangles)));
+        *mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL).unwrap() = self.normals.into();
+        *mesh.attribute_mut(Mesh::ATTRIBUTE_TANGENT).unwrap() = self.tangents.into();
+        *mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0).unwrap() = self.uv.into();
+        *mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR).unwrap() = self.colors.into();
+    }
 }
 
 #[derive(Component)]
 #[component(storage = "SparseSet")]
 pub struct ChunkNeedsMeshing;
 
+/// Remesh the neighbouring chunks of changed blocks on the border of their chunk, whose faces
+/// they may hide or reveal
+pub fn remesh_chunk_borders(
+    mut commands: Commands,
+    mut changed: EventReader<BlockChanged>,
+    map: Res<ChunkMap>,
+) {
+    for event in changed.iter() {
+        let (coords, _) = ChunkMap::chunk_coords(event.position);
+        for face in Face::ALL {
+            let (neighbor, _) = ChunkMap::chunk_coords(event.position + face.offset());
+            if neighbor != coords {
+                if let Some(neighbor) = map.get(neighbor) {
+                    commands.entity(neighbor).insert(ChunkNeedsMeshing);
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn chunk_mesher_system(
     mut commands: Commands,
     mut chunks: Query<
-        (Entity, &Chunk, Option<&mut Collider>, Option<&Children>),
+        (
+            Entity,
+            &Chunk,
+            &Transform,
+            Option<&mut Collider>,
+            Option<&Children>,
+        ),
         With<ChunkNeedsMeshing>,
     >,
+    all_chunks: Query<&Chunk>,
+    map: Res<ChunkMap>,
     blocks: Res<Blocks>,
+    biomes: Res<BiomeMap>,
+    sky_light: Res<SkyLight>,
     materials: Res<BlockMaterials>,
+    mut chunk_materials: ResMut<ChunkMaterials>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
     mut chunk_meshes: Query<
         (
             &Handle<Mesh>,
@@ -58,25 +329,38 @@ pub fn chunk_mesher_system(
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
     //TODO: multithread meshing
-    let mut mesher = Mesher::default();
+    let mut mesher = Mesher::new(&materials);
     let mut recycler = Vec::new();
 
-    for (chunk_entity, chunk, collider, children) in chunks.iter_mut() {
+    for (chunk_entity, chunk, transform, collider, children) in chunks.iter_mut() {
         mesher.clear();
-        //TODO: neighbors... have separate chunk-data/hyperchunk storage?
-        chunk.data.compute_mesh(&blocks, [None; 6], &mut mesher);
+        let origin = Chunk::block_origin(transform);
+        let (coords, _) = ChunkMap::chunk_coords(origin);
+        let neighbors = Face::ALL.map(|face| {
+            let neighbor = map.get(coords + face.offset())?;
+            all_chunks.get(neighbor).ok().map(|chunk| &chunk.data)
+        });
+        // The height of the sky's cover over each column of the chunk and the columns around it
+        let mut sky_heights = [[0; 18]; 18];
+        for (x, column) in sky_heights.iter_mut().enumerate() {
+            for (z, height) in column.iter_mut().enumerate() {
+                *height = map.sky_height(origin.x + x as i32 - 1, origin.z + z as i32 - 1);
+            }
+        }
+        chunk.data.compute_mesh(
+            &blocks,
+            &biomes,
+            origin,
+            neighbors,
+            &sky_heights,
+            &mut mesher,
+        );
 
         if let Some(children) = children {
             for child in children {
-                if let Ok((mesh, _material, sheet)) = chunk_meshes.get_mut(*child) {
-                    if let Some(premesh) = mesher.meshes.remove(&sheet.0) {
-                        let mesh = meshes.get_mut(&mesh).unwrap();
-                        *mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION).unwrap() =
-                            premesh.vertices.into();
-                        mesh.set_indices(Some(Indices::U16(premesh.triangles)));
-                        *mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL).unwrap() =
-                            premesh.normals.into();
-                        *mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0).unwrap() = premesh.uv.into();
+                if let Ok((mesh, _material, layer)) = chunk_meshes.get_mut(*child) {
+                    if let Some(premesh) = mesher.meshes.remove(&(layer.0, layer.1)) {
+                        premesh.write_mesh(meshes.get_mut(mesh).unwrap());
                     } else {
                         recycler.push(*child)
                     }
@@ -95,31 +379,29 @@ pub fn chunk_mesher_system(
         }
 
         commands.entity(chunk_entity).with_children(|chunk| {
-            for (sheet, premesh) in mesher.meshes.drain() {
+            for ((sheet, light), premesh) in mesher.meshes.drain() {
+                let material = chunk_materials.get_or_insert(
+                    sheet,
+                    light,
+                    &materials,
+                    &mut standard_materials,
+                    &sky_light,
+                );
                 if let Some(recycle) = recycler.pop() {
-                    let (mesh, mut material, mut sheet_ix) = chunk_meshes.get_mut(recycle).unwrap();
-                    let mesh = meshes.get_mut(&mesh).unwrap();
-                    *mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION).unwrap() =
-                        premesh.vertices.into();
-                    mesh.set_indices(Some(Indices::U16(premesh.triangles)));
-                    *mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL).unwrap() = premesh.normals.into();
-                    *mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0).unwrap() = premesh.uv.into();
-                    *material = materials.get_sheet_material(sheet);
-                    sheet_ix.0 = sheet;
+                    let (mesh, mut old_material, mut layer) =
+                        chunk_meshes.get_mut(recycle).unwrap();
+                    premesh.write_mesh(meshes.get_mut(mesh).unwrap());
+                    *old_material = material;
+                    *layer = IsChunkMesh(sheet, light);
                 } else {
-                    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, premesh.vertices);
-                    mesh.set_indices(Some(Indices::U16(premesh.triangles)));
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, premesh.normals);
-                    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, premesh.uv);
-                    let material = materials.get_sheet_material(sheet);
+                    let mesh = premesh.into_mesh();
                     chunk.spawn((
                         PbrBundle {
                             mesh: meshes.add(mesh),
                             material,
                             ..default()
                         },
-                        IsChunkMesh(sheet),
+                        IsChunkMesh(sheet, light),
                     ));
                 }
             }
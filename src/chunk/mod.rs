@@ -1,18 +1,81 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bytemuck::{Pod, Zeroable};
+use fxhash::FxHashSet;
 
-use crate::block::{texture::SheetId, BlockId, Blocks};
+use crate::biome::BiomeMap;
+use crate::block::{
+    connected::ConnectedTextures,
+    face::Face,
+    model::{BlockModel, ModelBox},
+    texture::SheetId,
+    BlockId, BlockTypeId, Blocks, Tint,
+};
+use crate::fluid::Fluids;
+use crate::item::ItemMeshes;
+use crate::tick::{BlockTickHandlers, ScheduledTicks};
+use crate::update::BlockUpdateHandlers;
 
-use self::mesher::Mesher;
+use self::entity::{BlockEntityTypes, ChunkEntities, SavedBlockEntities};
+use self::mesher::{BlockFaces, ChunkNeedsMeshing, MeshLight, Mesher, NeighborData};
 
+pub mod entity;
+pub mod map;
 pub mod mesher;
 
-#[derive(Component)]
-pub struct IsChunkMesh(pub SheetId);
+/// The edge length of a chunk, in blocks
+pub const CHUNK_SIZE: i32 = 16;
 
 #[derive(Component)]
+pub struct IsChunkMesh(pub SheetId, pub MeshLight);
+
+#[derive(Component, Debug, Clone, Default)]
 pub struct Chunk {
     pub data: ChunkData,
+    /// The block updates scheduled in this chunk
+    pub ticks: ScheduledTicks,
+    /// The block entities of the blocks in this chunk
+    pub entities: ChunkEntities,
+}
+
+impl Chunk {
+    /// Get the world coordinates of the block at index `[0, 0, 0]` of a chunk with the given
+    /// transform
+    #[inline]
+    pub fn block_origin(transform: &Transform) -> IVec3 {
+        (transform.translation - 8.0).round().as_ivec3()
+    }
+
+    /// Encode this chunk's blocks, scheduled updates and saved block entities, from
+    /// [`BlockEntityTypes::save`], given the current world tick.
+    ///
+    /// Blocks are saved by id, so the chunk must be loaded with the same blocks registered.
+    pub fn save(&self, tick: u64, entities: &SavedBlockEntities) -> Vec<u8> {
+        let ticks = self.ticks.to_bytes(tick);
+        let mut bytes = bytemuck::bytes_of(&self.data).to_vec();
+        bytes.extend_from_slice(&(ticks.len() as u32).to_le_bytes());
+        bytes.extend(ticks);
+        bytes.extend(entities.to_bytes());
+        bytes
+    }
+
+    /// Decode a chunk saved by [`Chunk::save`], given the current world tick, or `None` if the
+    /// bytes are malformed.
+    ///
+    /// The saved block entities are spawned along with the chunk, and restored as its block
+    /// entities are spawned.
+    pub fn load(bytes: &[u8], tick: u64) -> Option<(Chunk, SavedBlockEntities)> {
+        let data = bytes.get(..std::mem::size_of::<ChunkData>())?;
+        let bytes = &bytes[data.len()..];
+        let len = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let ticks = bytes.get(4..4 + len)?;
+        let chunk = Chunk {
+            data: bytemuck::pod_read_unaligned(data),
+            ticks: ScheduledTicks::from_bytes(ticks, tick)?,
+            ..default()
+        };
+        let entities = SavedBlockEntities::from_bytes(&bytes[4 + len..])?;
+        Some((chunk, entities))
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Pod, Zeroable)]
@@ -21,7 +84,137 @@ pub struct ChunkData {
     pub blocks: [[[BlockId; 16]; 16]; 16],
 }
 
+impl Default for ChunkData {
+    #[inline]
+    fn default() -> Self {
+        ChunkData {
+            blocks: [[[BlockId::default(); 16]; 16]; 16],
+        }
+    }
+}
+
+/// A block was broken and removed from a chunk
+#[derive(Event, Debug, Copy, Clone, PartialEq)]
+pub struct BlockBroken {
+    /// The chunk the block was removed from
+    pub chunk: Entity,
+    /// The index of the block within the chunk
+    pub index: [usize; 3],
+    /// The block which was removed
+    pub block: BlockId,
+    /// The center of the removed block, in world coordinates
+    pub position: Vec3,
+}
+
+/// A block in the world was replaced by another, so it and its neighbours must be updated
+#[derive(Event, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct BlockChanged {
+    /// The world position of the block
+    pub position: IVec3,
+    /// The block which was replaced
+    pub old: BlockId,
+    /// The block which replaced it
+    pub new: BlockId,
+}
+
+/// How far, in chunks, the world is shown around the player, beyond which it is hidden by fog
+#[derive(Resource, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ViewDistance(pub u32);
+
+impl Default for ViewDistance {
+    #[inline]
+    fn default() -> Self {
+        ViewDistance(8)
+    }
+}
+
+/// Blocks were unregistered, or changed appearance, so chunks containing them must be updated
+#[derive(Event, Debug, Clone, Default, PartialEq)]
+pub struct BlocksRemoved {
+    /// Blocks which no longer exist, and are replaced with air
+    pub removed: Vec<BlockId>,
+    /// Blocks which still exist but must be remeshed, such as blocks showing a removed texture
+    pub changed: Vec<BlockId>,
+}
+
+/// The block registry along with the registries keyed by block type, for unregistering block types
+/// without leaving behind behaviour which block types registered later would inherit
+#[derive(SystemParam)]
+pub struct BlockRegistries<'w> {
+    pub blocks: ResMut<'w, Blocks>,
+    pub ticks: ResMut<'w, BlockTickHandlers>,
+    pub updates: ResMut<'w, BlockUpdateHandlers>,
+    pub entities: ResMut<'w, BlockEntityTypes>,
+    pub fluids: ResMut<'w, Fluids>,
+    pub item_meshes: ResMut<'w, ItemMeshes>,
+    removed: EventWriter<'w, BlocksRemoved>,
+}
+
+impl BlockRegistries<'_> {
+    /// Unregister a block type, forgetting its tick, update, block entity and fluid behaviour and
+    /// its item meshes straight away, since its ids may be reused by the next block type
+    /// registered. Its blocks are then removed from chunks through [`BlocksRemoved`].
+    #[allow(clippy::result_unit_err)]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) -> Result<Vec<BlockId>, ()> {
+        let removed = self.blocks.remove_block_type(block_type)?;
+        self.ticks.remove_block_type(block_type);
+        self.updates.remove_block_type(block_type);
+        self.entities.remove_block_type(block_type);
+        self.fluids.remove_block_type(block_type);
+        for &block in &removed {
+            self.item_meshes.forget(block);
+        }
+        self.removed.send(BlocksRemoved {
+            removed: removed.clone(),
+            changed: Vec::new(),
+        });
+        Ok(removed)
+    }
+}
+
 impl ChunkData {
+    /// Replace every block in `removed` with air, returning whether any were found
+    pub fn remove_blocks(&mut self, removed: &FxHashSet<BlockId>) -> bool {
+        let mut found = false;
+        for block in self.blocks.iter_mut().flatten().flatten() {
+            if removed.contains(block) {
+                *block = BlockId::default();
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Get whether this chunk contains any of `blocks`
+    pub fn contains_any(&self, blocks: &FxHashSet<BlockId>) -> bool {
+        self.blocks
+            .iter()
+            .flatten()
+            .flatten()
+            .any(|block| blocks.contains(block))
+    }
+
+    /// Get the index of the block containing a point given in chunk-local coordinates
+    #[inline]
+    pub fn block_index(point: Vec3) -> Option<[usize; 3]> {
+        let ix = (point + 8.5).floor();
+        if ix.cmpge(Vec3::ZERO).all() && ix.cmplt(Vec3::splat(16.0)).all() {
+            Some([ix.x as usize, ix.y as usize, ix.z as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Get the center of a block in chunk-local coordinates
+    #[inline]
+    pub fn block_center(index: [usize; 3]) -> Vec3 {
+        Vec3::new(
+            index[0] as f32 - 8.0,
+            index[1] as f32 - 8.0,
+            index[2] as f32 - 8.0,
+        )
+    }
+
     // pub fn blocks(&self) -> &[u16] {
     //     bytemuck::cast_slice(&self.blocks)
     // }
@@ -30,10 +223,19 @@ impl ChunkData {
     //     bytemuck::cast_slice_mut(&mut self.blocks)
     // }
 
+    /// Mesh this chunk, given the world coordinates of its block at index `[0, 0, 0]`.
+    ///
+    /// `sky_heights` holds the world height of the highest block covering each column of the chunk
+    /// from the sky, from [`ChunkMap::sky_height`](map::ChunkMap::sky_height), including the
+    /// columns bordering the chunk. Faces facing cells above that height are lit by
+    /// [`MeshLight::Sky`], and the rest by [`MeshLight::Cave`].
     pub fn compute_mesh(
         &self,
         blocks: &Blocks,
+        biomes: &BiomeMap,
+        origin: IVec3,
         neighbors: [Option<&ChunkData>; 6],
+        sky_heights: &[[i32; 18]; 18],
         mesher: &mut Mesher,
     ) {
         let mut buffer = [[[BlockId::default(); 18]; 18]; 18];
@@ -73,7 +275,7 @@ impl ChunkData {
             // -y
             for y in 0..16 {
                 for z in 0..16 {
-                    buffer[0][y + 1][z + 1] = left.blocks[17][y][z]
+                    buffer[0][y + 1][z + 1] = left.blocks[15][y][z]
                 }
             }
         }
@@ -89,246 +291,385 @@ impl ChunkData {
             // -z
             for x in 0..16 {
                 for y in 0..16 {
-                    buffer[x + 1][y + 1][0] = front.blocks[x][y][17]
+                    buffer[x + 1][y + 1][0] = front.blocks[x][y][15]
                 }
             }
         }
 
+        let light = |cell: IVec3| {
+            if origin.y + cell.y - 1 > sky_heights[cell.x as usize][cell.z as usize] {
+                MeshLight::Sky
+            } else {
+                MeshLight::Cave
+            }
+        };
+
         for x in 0..16 {
             for y in 0..16 {
                 for z in 0..16 {
-                    // top face
                     let me = blocks.get_meshing_data(buffer[x + 1][y + 1][z + 1]);
 
                     if me.opacity == 0 {
                         continue;
                     }
 
-                    let top = blocks.get_meshing_data(buffer[x + 1][y + 2][z + 1]);
-                    let bottom = blocks.get_meshing_data(buffer[x + 1][y][z + 1]);
-                    let right = blocks.get_meshing_data(buffer[x + 2][y + 1][z + 1]);
-                    let left = blocks.get_meshing_data(buffer[x][y + 1][z + 1]);
-                    let back = blocks.get_meshing_data(buffer[x + 1][y + 1][z + 2]);
-                    let front = blocks.get_meshing_data(buffer[x + 1][y + 1][z]);
-
                     // Center of block coordinates
-                    let x = x as f32 - 8.0;
-                    let y = y as f32 - 8.0;
-                    let z = z as f32 - 8.0;
-
-                    //TODO: shared texture optimization?
-                    if me.opacity != top.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[0].sheet()).or_default();
-                        let coords = me.textures[0].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x - 0.5, y + 0.5, z - 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z - 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z + 0.5]);
-                        mesh.vertices.push([x - 0.5, y + 0.5, z + 0.5]);
-                        mesh.normals.push([0.0, 1.0, 0.0]);
-                        mesh.normals.push([0.0, 1.0, 0.0]);
-                        mesh.normals.push([0.0, 1.0, 0.0]);
-                        mesh.normals.push([0.0, 1.0, 0.0]);
-                        mesh.uv.push(coords.top_left());
-                        mesh.uv.push(coords.top_right());
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 2);
-                    }
-                    if me.solid > top.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x - 0.5, y + 0.5, z - 0.5]),
-                            [x + 0.5, y + 0.5, z - 0.5].into(),
-                            [x + 0.5, y + 0.5, z + 0.5].into(),
-                            [x - 0.5, y + 0.5, z + 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 3, v + 1], [v + 1, v + 3, v + 2]]);
-                    }
-                    if me.opacity != bottom.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[1].sheet()).or_default();
-                        let coords = me.textures[1].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x + 0.5, y - 0.5, z + 0.5]);
-                        mesh.vertices.push([x + 0.5, y - 0.5, z - 0.5]);
-                        mesh.vertices.push([x - 0.5, y - 0.5, z + 0.5]);
-                        mesh.vertices.push([x - 0.5, y - 0.5, z - 0.5]);
-                        mesh.normals.push([0.0, -1.0, 0.0]);
-                        mesh.normals.push([0.0, -1.0, 0.0]);
-                        mesh.normals.push([0.0, -1.0, 0.0]);
-                        mesh.normals.push([0.0, -1.0, 0.0]);
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.uv.push(coords.top_left());
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.uv.push(coords.top_right());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 2);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 2);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                    }
-                    if me.solid > bottom.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x + 0.5, y - 0.5, z + 0.5]),
-                            [x + 0.5, y - 0.5, z - 0.5].into(),
-                            [x - 0.5, y - 0.5, z + 0.5].into(),
-                            [x - 0.5, y - 0.5, z - 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 2, v + 1], [v + 2, v + 3, v + 1]]);
-                    }
-                    if me.opacity != right.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[2].sheet()).or_default();
-                        let coords = me.textures[2].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x + 0.5, y - 0.5, z - 0.5]);
-                        mesh.vertices.push([x + 0.5, y - 0.5, z + 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z + 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z - 0.5]);
-                        mesh.normals.push([1.0, 0.0, 0.0]);
-                        mesh.normals.push([1.0, 0.0, 0.0]);
-                        mesh.normals.push([1.0, 0.0, 0.0]);
-                        mesh.normals.push([1.0, 0.0, 0.0]);
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.uv.push(coords.top_right());
-                        mesh.uv.push(coords.top_left());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 2);
-                    }
-                    if me.solid > right.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x + 0.5, y - 0.5, z - 0.5]),
-                            [x + 0.5, y - 0.5, z + 0.5].into(),
-                            [x + 0.5, y + 0.5, z + 0.5].into(),
-                            [x + 0.5, y + 0.5, z - 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 3, v + 1], [v + 1, v + 3, v + 2]]);
-                    }
-                    if me.opacity != left.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[3].sheet()).or_default();
-                        let coords = me.textures[3].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x - 0.5, y - 0.5, z + 0.5]);
-                        mesh.vertices.push([x - 0.5, y - 0.5, z - 0.5]);
-                        mesh.vertices.push([x - 0.5, y + 0.5, z + 0.5]);
-                        mesh.vertices.push([x - 0.5, y + 0.5, z - 0.5]);
-                        mesh.normals.push([-1.0, 0.0, 0.0]);
-                        mesh.normals.push([-1.0, 0.0, 0.0]);
-                        mesh.normals.push([-1.0, 0.0, 0.0]);
-                        mesh.normals.push([-1.0, 0.0, 0.0]);
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.uv.push(coords.top_right());
-                        mesh.uv.push(coords.top_left());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 2);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 2);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                    }
-                    if me.solid > left.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x - 0.5, y - 0.5, z + 0.5]),
-                            [x - 0.5, y - 0.5, z - 0.5].into(),
-                            [x - 0.5, y + 0.5, z + 0.5].into(),
-                            [x - 0.5, y + 0.5, z - 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 2, v + 1], [v + 2, v + 3, v + 1]]);
-                    }
-                    if me.opacity != back.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[4].sheet()).or_default();
-                        let coords = me.textures[4].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x - 0.5, y - 0.5, z + 0.5]);
-                        mesh.vertices.push([x - 0.5, y + 0.5, z + 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z + 0.5]);
-                        mesh.vertices.push([x + 0.5, y - 0.5, z + 0.5]);
-                        mesh.normals.push([0.0, 0.0, 1.0]);
-                        mesh.normals.push([0.0, 0.0, 1.0]);
-                        mesh.normals.push([0.0, 0.0, 1.0]);
-                        mesh.normals.push([0.0, 0.0, 1.0]);
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.uv.push(coords.top_left());
-                        mesh.uv.push(coords.top_right());
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 2);
-                    }
-                    if me.solid > back.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x - 0.5, y - 0.5, z + 0.5]),
-                            [x - 0.5, y + 0.5, z + 0.5].into(),
-                            [x + 0.5, y + 0.5, z + 0.5].into(),
-                            [x + 0.5, y - 0.5, z + 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 3, v + 1], [v + 1, v + 3, v + 2]]);
-                    }
-                    if me.opacity != front.opacity {
-                        let mesh = mesher.meshes.entry(me.textures[5].sheet()).or_default();
-                        let coords = me.textures[5].coords();
-                        let v = mesh.vertices.len() as u16;
-                        mesh.vertices.push([x - 0.5, y - 0.5, z - 0.5]);
-                        mesh.vertices.push([x - 0.5, y + 0.5, z - 0.5]);
-                        mesh.vertices.push([x + 0.5, y + 0.5, z - 0.5]);
-                        mesh.vertices.push([x + 0.5, y - 0.5, z - 0.5]);
-                        mesh.normals.push([0.0, 0.0, -1.0]);
-                        mesh.normals.push([0.0, 0.0, -1.0]);
-                        mesh.normals.push([0.0, 0.0, -1.0]);
-                        mesh.normals.push([0.0, 0.0, -1.0]);
-                        mesh.uv.push(coords.top_left());
-                        mesh.uv.push(coords.bottom_left());
-                        mesh.uv.push(coords.bottom_right());
-                        mesh.uv.push(coords.top_right());
-                        mesh.triangles.push(v);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 3);
-                        mesh.triangles.push(v + 1);
-                        mesh.triangles.push(v + 2);
-                        mesh.triangles.push(v + 3);
+                    let center = Vec3::new(x as f32 - 8.0, y as f32 - 8.0, z as f32 - 8.0);
+
+                    let neighbors = Face::ALL.map(|face| {
+                        let offset = face.offset();
+                        let neighbor = blocks.get_meshing_data(
+                            buffer[(x as i32 + 1 + offset.x) as usize]
+                                [(y as i32 + 1 + offset.y) as usize]
+                                [(z as i32 + 1 + offset.z) as usize],
+                        );
+                        NeighborData::new(blocks, &neighbor)
+                    });
+
+                    let position = origin + IVec3::new(x as i32, y as i32, z as i32);
+                    let my_type = blocks.block_type(buffer[x + 1][y + 1][z + 1]);
+                    let ix = IVec3::new(x as i32 + 1, y as i32 + 1, z as i32 + 1);
+                    let textures = Face::ALL.map(|face| match me.connected[face.index()] {
+                        Some(connected) => {
+                            let transform = me.transforms[face.index()];
+                            let connections = ConnectedTextures::connections(
+                                face.texture_directions(transform),
+                                face.offset(),
+                                |offset| {
+                                    let p = ix + offset;
+                                    let neighbor = buffer[p.x as usize][p.y as usize][p.z as usize];
+                                    blocks.block_type(neighbor) == my_type
+                                },
+                            );
+                            (
+                                blocks.get_connected_textures(connected).tile(connections),
+                                transform,
+                            )
+                        }
+                        None => me.face_texture(blocks, face, position),
+                    });
+                    let tint = |tint: Tint| biomes.tint_color(tint, position).as_linear_rgba_f32();
+                    let faces = BlockFaces {
+                        textures,
+                        colors: me.tints.map(tint),
+                        overlays: me
+                            .overlays
+                            .map(|overlay| overlay.map(|(texture, t)| (texture, tint(t)))),
+                        // Faces are lit by the cell they face, but crosses by their own cell
+                        lights: Face::ALL.map(|face| {
+                            match me.model.map(|model| blocks.get_model(model)) {
+                                Some(BlockModel::Cross) => light(ix),
+                                _ => light(ix + face.offset()),
+                            }
+                        }),
+                    };
+
+                    if me.fluid.is_some() {
+                        // The surface of a fluid is level with any of the same fluid above it
+                        let surface = |p: IVec3| {
+                            let above = p + IVec3::Y;
+                            if above.y < 18
+                                && blocks.block_type(
+                                    buffer[above.x as usize][above.y as usize][above.z as usize],
+                                ) == my_type
+                            {
+                                16
+                            } else {
+                                let block = buffer[p.x as usize][p.y as usize][p.z as usize];
+                                blocks.get_meshing_data(block).fluid.unwrap_or(16)
+                            }
+                        };
+                        let my_surface = surface(ix);
+                        let neighbors = Face::ALL.map(|face| {
+                            let p = ix + face.offset();
+                            let neighbor = buffer[p.x as usize][p.y as usize][p.z as usize];
+                            let hidden = blocks.block_type(neighbor) == my_type
+                                && (face.offset().y != 0 || surface(p) >= my_surface);
+                            if hidden {
+                                NeighborData {
+                                    opacity: me.opacity,
+                                    solid: false,
+                                }
+                            } else {
+                                neighbors[face.index()]
+                            }
+                        });
+                        let model_box =
+                            ModelBox::from_pixels([0.0; 3], [16.0, my_surface as f32, 16.0]);
+                        mesher.push_box(&me, &faces, &model_box, center, &neighbors, false);
+                        continue;
                     }
-                    if me.solid > front.solid {
-                        let v = mesher.physics_vertices.len() as u32;
-                        mesher.physics_vertices.extend([
-                            Vec3::from([x - 0.5, y - 0.5, z - 0.5]),
-                            [x - 0.5, y + 0.5, z - 0.5].into(),
-                            [x + 0.5, y + 0.5, z - 0.5].into(),
-                            [x + 0.5, y - 0.5, z - 0.5].into(),
-                        ]);
-                        mesher
-                            .physics_triangles
-                            .extend([[v, v + 1, v + 3], [v + 1, v + 2, v + 3]]);
+
+                    match me.model.map(|model| blocks.get_model(model)) {
+                        None | Some(BlockModel::Cube) => {
+                            mesher.push_box(&me, &faces, &ModelBox::FULL, center, &neighbors, true)
+                        }
+                        Some(BlockModel::Boxes(boxes)) => {
+                            for model_box in boxes {
+                                mesher.push_box(&me, &faces, model_box, center, &neighbors, false)
+                            }
+                        }
+                        Some(BlockModel::Cross) => {
+                            let front = Face::Front.index();
+                            let (texture, transform) = faces.textures[front];
+                            mesher.push_cross(
+                                texture,
+                                transform,
+                                faces.colors[front],
+                                faces.lights[front],
+                                center,
+                            )
+                        }
                     }
                 }
             }
         }
     }
 }
+
+/// Remove unregistered blocks from every chunk, remeshing the chunks they or changed blocks are
+/// found in
+pub fn purge_removed_blocks(
+    mut commands: Commands,
+    mut events: EventReader<BlocksRemoved>,
+    mut chunks: Query<(Entity, &mut Chunk)>,
+) {
+    let mut removed = FxHashSet::default();
+    let mut changed = FxHashSet::default();
+    for event in events.iter() {
+        removed.extend(event.removed.iter().copied());
+        changed.extend(event.changed.iter().copied());
+    }
+    if removed.is_empty() && changed.is_empty() {
+        return;
+    }
+    for (entity, mut chunk) in chunks.iter_mut() {
+        // Only borrow the chunk mutably if it needs to change, to keep change detection useful
+        let contains_removed = chunk.data.contains_any(&removed);
+        if contains_removed {
+            chunk.data.remove_blocks(&removed);
+        }
+        if contains_removed || chunk.data.contains_any(&changed) {
+            commands.entity(entity).insert(ChunkNeedsMeshing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+    use crate::block::{state::StateValues, texture::BlockTextureId, MeshingData};
+    use crate::chunk::{entity::spawn_chunk_block_entities, map::index_chunks, map::ChunkMap};
+    use crate::fluid::Fluid;
+    use crate::item::Inventory;
+    use crate::tick::{tick_blocks, RandomTickSpeed, WorldTick};
+    use crate::update::{run_block_updates, BlockUpdates};
+
+    fn solid(_: StateValues) -> MeshingData {
+        MeshingData {
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Blocks>();
+        world.init_resource::<BlockTickHandlers>();
+        world.init_resource::<BlockUpdateHandlers>();
+        world.init_resource::<BlockEntityTypes>();
+        world.init_resource::<Fluids>();
+        world.init_resource::<ItemMeshes>();
+        world.init_resource::<ChunkMap>();
+        world.init_resource::<WorldTick>();
+        world.init_resource::<BlockUpdates>();
+        world.init_resource::<Events<BlocksRemoved>>();
+        world.init_resource::<Events<BlockChanged>>();
+        world.init_resource::<Events<BlockBroken>>();
+        world.insert_resource(RandomTickSpeed(64));
+        world
+    }
+
+    /// Spawn a chunk filled with `block`, with a scheduled update and queued updates for some of
+    /// its blocks, and run one world tick
+    fn tick_chunk(world: &mut World, coords: IVec3, block: BlockId) {
+        let mut chunk = Chunk::default();
+        chunk.data.blocks = [[[block; 16]; 16]; 16];
+        chunk.ticks.schedule(0, [1, 2, 3]);
+        let origin = coords * CHUNK_SIZE;
+        world.spawn((chunk, Transform::from_translation(origin.as_vec3() + 8.0)));
+        world.resource_mut::<BlockUpdates>().notify(origin + 8);
+        let mut index = Schedule::default();
+        index.add_systems(index_chunks);
+        index.run(world);
+        let mut tick = Schedule::default();
+        tick.add_systems((spawn_chunk_block_entities, tick_blocks, run_block_updates).chain());
+        tick.run(world);
+    }
+
+    #[test]
+    fn removed_block_types_leave_no_handlers() {
+        let mut world = world();
+        let fired = Arc::new([(); 4].map(|_| AtomicUsize::new(0)));
+        let counter = |ix: usize| {
+            let fired = fired.clone();
+            move || {
+                fired[ix].fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let mut state = SystemState::<BlockRegistries>::new(&mut world);
+        let mut registries = state.get_mut(&mut world);
+        let old_type = registries.blocks.new_block_type(&[], solid).unwrap();
+        let (random, scheduled, update, spawn) = (counter(0), counter(1), counter(2), counter(3));
+        registries
+            .ticks
+            .on_random_tick(old_type, move |_, _, _| random());
+        registries
+            .ticks
+            .on_scheduled_tick(old_type, move |_, _, _| scheduled());
+        registries
+            .updates
+            .on_update(old_type, move |_, _, _, _| update());
+        registries.entities.register(old_type, move |_, _| spawn());
+        let BlockRegistries {
+            blocks,
+            ticks,
+            updates,
+            fluids,
+            ..
+        } = &mut registries;
+        let fluid_type = fluids
+            .new_fluid(
+                blocks,
+                ticks,
+                updates,
+                BlockTextureId::default(),
+                [0; 3],
+                Fluid { flow_delay: 5 },
+            )
+            .unwrap();
+        let old_block = registries.blocks.default_state(old_type);
+
+        tick_chunk(&mut world, IVec3::ZERO, old_block);
+        assert!(fired.iter().all(|count| count.load(Ordering::Relaxed) > 0));
+
+        let mut registries = state.get_mut(&mut world);
+        registries.remove_block_type(old_type).unwrap();
+        registries.remove_block_type(fluid_type).unwrap();
+        let new_types = [(); 2].map(|_| registries.blocks.new_block_type(&[], solid).unwrap());
+        assert!(new_types.contains(&old_type));
+        assert!(new_types.contains(&fluid_type));
+        for new_type in new_types {
+            assert!(registries.fluids.get(new_type).is_none());
+            assert!(!registries.entities.has_entity(new_type));
+        }
+        let new_block = registries.blocks.default_state(old_type);
+        state.apply(&mut world);
+        assert_eq!(world.resource::<Events<BlocksRemoved>>().len(), 2);
+
+        for count in fired.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+        tick_chunk(&mut world, IVec3::X, new_block);
+        assert!(fired.iter().all(|count| count.load(Ordering::Relaxed) == 0));
+    }
+
+    #[test]
+    fn chunks_are_saved_with_their_scheduled_ticks() {
+        let mut chunk = Chunk::default();
+        chunk.data.blocks[0][15][2] = bytemuck::cast(7u32);
+        chunk.ticks.schedule(95, [1, 2, 3]);
+        chunk.ticks.schedule(100, [15, 0, 15]);
+        chunk.ticks.schedule(130, [4, 4, 4]);
+        let saved = chunk.save(100, &SavedBlockEntities::default());
+
+        // Updates keep their delays, and those overdue run as soon as the chunk is loaded
+        let (mut loaded, entities) = Chunk::load(&saved, 1000).unwrap();
+        assert!(entities.is_empty());
+        assert_eq!(loaded.data, chunk.data);
+        assert_eq!(loaded.ticks.take_due(999), Vec::<[usize; 3]>::new());
+        assert_eq!(loaded.ticks.take_due(1000), [[1, 2, 3], [15, 0, 15]]);
+        assert_eq!(loaded.ticks.take_due(1029), Vec::<[usize; 3]>::new());
+        assert_eq!(loaded.ticks.take_due(1030), [[4, 4, 4]]);
+        assert!(loaded.ticks.is_empty());
+
+        assert!(Chunk::load(&saved[..saved.len() - 1], 0).is_none());
+        assert!(Chunk::load(&saved[..100], 0).is_none());
+        let mut out_of_bounds = saved.clone();
+        *out_of_bounds.last_mut().unwrap() = 16;
+        assert!(Chunk::load(&out_of_bounds, 0).is_none());
+        let mut trailing = saved.clone();
+        trailing.push(0);
+        assert!(Chunk::load(&trailing, 0).is_none());
+    }
+
+    #[test]
+    fn block_entities_are_saved_with_their_chunk() {
+        let mut world = world();
+        let mut blocks = world.resource_mut::<Blocks>();
+        let [chest, marker] = [(); 2].map(|_| {
+            let block_type = blocks.new_block_type(&[], solid).unwrap();
+            (block_type, blocks.default_state(block_type))
+        });
+        let mut types = world.resource_mut::<BlockEntityTypes>();
+        for (block_type, _) in [chest, marker] {
+            types.register(block_type, |entity, _| {
+                entity.insert(Inventory::default());
+            });
+        }
+        // Only the chest's inventory is saved, so the marker's is spawned empty again
+        types.persist(
+            chest.0,
+            |entity| entity.get::<Inventory>().unwrap().to_bytes(),
+            |entity, bytes| {
+                entity.insert(Inventory::from_bytes(bytes).unwrap());
+            },
+        );
+        let mut spawn = Schedule::default();
+        spawn.add_systems(spawn_chunk_block_entities);
+        let transform = Transform::from_translation(Vec3::splat(8.0));
+
+        let mut chunk = Chunk::default();
+        chunk.data.blocks[1][2][3] = chest.1;
+        chunk.data.blocks[4][5][6] = marker.1;
+        let chunk_entity = world.spawn((chunk, transform)).id();
+        spawn.run(&mut world);
+        let chunk = world.get::<Chunk>(chunk_entity).unwrap().clone();
+        for index in [[1, 2, 3], [4, 5, 6]] {
+            let entity = chunk.entities.get(index).unwrap();
+            let mut inventory = world.get_mut::<Inventory>(entity).unwrap();
+            inventory.insert(chest.1, 5);
+            inventory.insert(marker.1, 2);
+            inventory.selected = 1;
+        }
+        let saved = world.resource::<BlockEntityTypes>().save(&world, &chunk);
+        let bytes = chunk.save(0, &saved);
+        world.entity_mut(chunk_entity).despawn_recursive();
+
+        let (chunk, saved) = Chunk::load(&bytes, 0).unwrap();
+        let chunk_entity = world.spawn((chunk, saved, transform)).id();
+        spawn.run(&mut world);
+        assert!(world.get::<SavedBlockEntities>(chunk_entity).is_none());
+        let chunk = world.get::<Chunk>(chunk_entity).unwrap();
+        let inventory = |index| {
+            let entity = chunk.entities.get(index).unwrap();
+            assert_eq!(world.get::<Parent>(entity).unwrap().get(), chunk_entity);
+            world.get::<Inventory>(entity).unwrap()
+        };
+        let chest_inventory = inventory([1, 2, 3]);
+        assert_eq!(chest_inventory.selected, 1);
+        assert_eq!(
+            chest_inventory.items.iter().collect::<Vec<_>>(),
+            [(&chest.1, &5), (&marker.1, &2)]
+        );
+        assert!(inventory([4, 5, 6]).items.is_empty());
+    }
+}
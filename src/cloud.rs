@@ -0,0 +1,206 @@
+use bevy::{
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+use crate::{
+    block::face::Face,
+    noise::fractal_noise,
+    player::PlayerCamera,
+    sky::{lerp_color, TimeOfDay},
+};
+
+/// The width, in blocks, of a cloud cell
+pub const CLOUD_CELL_WIDTH: f32 = 12.0;
+/// The thickness, in blocks, of the cloud layer
+pub const CLOUD_THICKNESS: f32 = 4.0;
+/// How many cells of clouds are meshed in each direction around the camera
+const CLOUD_RADIUS: i32 = 24;
+/// The approximate size, in cells, of a cloud
+const CLOUD_SCALE: f32 = 6.0;
+
+/// A layer of blocky clouds drifting with the wind, without any physics
+#[derive(Resource, Debug, Copy, Clone, PartialEq)]
+pub struct Clouds {
+    pub seed: u32,
+    /// The height of the bottom of the cloud layer
+    pub height: f32,
+    /// Roughly the fraction of the sky covered by clouds, from `0.0` to `1.0`
+    pub coverage: f32,
+    /// The velocity of the clouds, in blocks per second, along the x and z axes
+    pub wind: Vec2,
+    /// How far the clouds have drifted along the x and z axes
+    pub offset: Vec2,
+    /// The color of the clouds at noon, whose alpha is the clouds' opacity
+    pub day_color: Color,
+    /// The color of the clouds at midnight
+    pub night_color: Color,
+}
+
+impl Default for Clouds {
+    #[inline]
+    fn default() -> Self {
+        Clouds {
+            seed: 0xC10D,
+            height: 64.0,
+            coverage: 0.4,
+            wind: Vec2::new(1.5, 0.5),
+            offset: Vec2::ZERO,
+            day_color: Color::rgba(1.0, 1.0, 1.0, 0.8),
+            night_color: Color::rgba(0.08, 0.08, 0.12, 0.8),
+        }
+    }
+}
+
+impl Clouds {
+    /// Get whether a cell of the cloud layer, before drifting, is filled with cloud
+    #[inline]
+    pub fn is_cloud(&self, cell: IVec2) -> bool {
+        let noise = fractal_noise(self.seed, cell.as_vec2() / CLOUD_SCALE, 3);
+        noise > 0.75 - 0.5 * self.coverage
+    }
+}
+
+/// The mesh of the clouds around the camera
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct CloudLayer {
+    /// The cell the mesh is centered on, along with the seed and coverage it was built with, or
+    /// `None` if it must be rebuilt
+    meshed: Option<(IVec2, u32, f32)>,
+}
+
+/// Spawn the cloud layer, which is remeshed around the camera once it is known
+pub fn setup_clouds(
+    mut commands: Commands,
+    clouds: Res<Clouds>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        CloudLayer { meshed: None },
+        PbrBundle {
+            mesh: meshes.add(cloud_mesh(&clouds, IVec2::ZERO)),
+            material: materials.add(StandardMaterial {
+                base_color: clouds.day_color,
+                unlit: true,
+                cull_mode: None,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            ..default()
+        },
+        NotShadowCaster,
+        NotShadowReceiver,
+    ));
+}
+
+/// Drift the clouds with the wind, remeshing them as the camera or the clouds cross cell
+/// boundaries
+pub fn update_clouds(
+    time: Res<Time>,
+    mut clouds: ResMut<Clouds>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    camera: Query<&Transform, (With<PlayerCamera>, Without<CloudLayer>)>,
+    mut layers: Query<(&mut CloudLayer, &mut Transform, &Handle<Mesh>)>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let wind = clouds.wind;
+    clouds.offset += wind * time.delta_seconds();
+
+    for (mut layer, mut transform, mesh) in layers.iter_mut() {
+        transform.translation = Vec3::new(clouds.offset.x, clouds.height, clouds.offset.y);
+        let relative = (Vec2::new(camera.translation.x, camera.translation.z) - clouds.offset)
+            / CLOUD_CELL_WIDTH;
+        let center = relative.floor().as_ivec2();
+        let meshed = Some((center, clouds.seed, clouds.coverage));
+        if layer.meshed != meshed {
+            layer.meshed = meshed;
+            if let Some(mesh) = meshes.get_mut(mesh) {
+                *mesh = cloud_mesh(&clouds, center);
+            }
+        }
+    }
+}
+
+/// Color the clouds by the time of day, whenever it or the colors of the clouds change
+pub fn color_clouds(
+    time_of_day: Res<TimeOfDay>,
+    clouds: Res<Clouds>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    layers: Query<&Handle<StandardMaterial>, With<CloudLayer>>,
+    // The night and day colors the clouds were last colored with, since the clouds drift, and so
+    // change, every frame
+    mut colored: Local<Option<(Color, Color)>>,
+) {
+    let colors = Some((clouds.night_color, clouds.day_color));
+    if !time_of_day.is_changed() && *colored == colors {
+        return;
+    }
+    *colored = colors;
+    for material in layers.iter() {
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color =
+                lerp_color(clouds.night_color, clouds.day_color, time_of_day.daylight());
+        }
+    }
+}
+
+/// Mesh the cloud cells around `center` as boxes, shading their faces by direction and leaving out
+/// the faces between neighbouring cells
+fn cloud_mesh(clouds: &Clouds, center: IVec2) -> Mesh {
+    let width = 2 * CLOUD_RADIUS + 1;
+    let filled: Vec<bool> = (0..width * width)
+        .map(|ix| {
+            let cell = center + IVec2::new(ix % width, ix / width) - CLOUD_RADIUS;
+            clouds.is_cloud(cell)
+        })
+        .collect();
+    let is_cloud = |x: i32, z: i32| {
+        (0..width).contains(&x) && (0..width).contains(&z) && filled[(x + z * width) as usize]
+    };
+
+    let size = Vec3::new(CLOUD_CELL_WIDTH, CLOUD_THICKNESS, CLOUD_CELL_WIDTH);
+    let mut vertices = Vec::new();
+    let mut colors = Vec::new();
+    let mut normals = Vec::new();
+    let mut triangles = Vec::new();
+    for z in 0..width {
+        for x in 0..width {
+            if !is_cloud(x, z) {
+                continue;
+            }
+            let cell = center + IVec2::new(x, z) - CLOUD_RADIUS;
+            let corner = Vec3::new(cell.x as f32, 0.0, cell.y as f32) * size;
+            for face in Face::ALL {
+                let offset = face.offset();
+                if offset.y == 0 && is_cloud(x + offset.x, z + offset.z) {
+                    continue;
+                }
+                let shade = match face {
+                    Face::Top => 1.0,
+                    Face::Bottom => 0.7,
+                    _ => 0.85,
+                };
+                let v = vertices.len() as u32;
+                vertices.extend(
+                    face.vertices()
+                        .map(|v| (corner + (Vec3::from(v) + 0.5) * size).to_array()),
+                );
+                colors.extend([[shade, shade, shade, 1.0]; 4]);
+                normals.extend([face.normal().to_array(); 4]);
+                triangles.extend(face.triangles().map(|t| v + t as u32));
+            }
+        }
+    }
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    let uv = vec![[0.0f32; 2]; vertices.len()];
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.set_indices(Some(Indices::U32(triangles)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uv);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+
+use crate::{
+    biome::BiomeMap,
+    block::{texture::BlockMaterials, BlockId, BlockTypeId, Blocks},
+    chunk::{map::WorldBlocks, mesher::Mesher},
+    item::{spawn_dropped_item, DroppedItem, ItemMeshes},
+    player::PLAYER_GROUP,
+    update::BlockUpdateHandlers,
+};
+
+/// The collision group of falling block bodies
+pub const FALLING_BLOCK_GROUP: Group = Group::GROUP_4;
+/// The half-width of a falling block's collider, a little smaller than the block so that it slides
+/// freely past its neighbours
+const FALLING_BLOCK_HALF_WIDTH: f32 = 0.45;
+/// How long, in seconds, a falling block falls before it may settle
+const SETTLE_DELAY: f32 = 0.25;
+/// The speed, in blocks per second, below which a falling block settles
+const SETTLE_SPEED: f32 = 0.05;
+/// The height below which falling blocks have left the world, and are despawned
+const FALL_LIMIT: f32 = -256.0;
+
+/// A block which detached from the world and is falling as a physics body, until it comes to rest
+/// and becomes a block again
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct FallingBlock {
+    pub block: BlockId,
+    /// The time, in seconds, since this block detached
+    pub age: f32,
+}
+
+/// Make blocks of a type detach and fall whenever the block below them is not solid
+pub fn register_gravity_block(updates: &mut BlockUpdateHandlers, block_type: BlockTypeId) {
+    updates.on_update(block_type, |context, position, block, _| {
+        let unsupported = context
+            .world
+            .get(position - IVec3::Y)
+            .is_some_and(|below| !context.blocks.get_meshing_data(below).solid);
+        if unsupported {
+            context.world.set(position, BlockId::default());
+            context.world.commands().spawn((
+                FallingBlock { block, age: 0.0 },
+                SpatialBundle::from_transform(Transform::from_translation(position.as_vec3())),
+            ));
+        }
+    });
+}
+
+/// Give newly detached blocks their physics body and the model of their block
+pub fn spawn_falling_blocks(
+    mut commands: Commands,
+    blocks: Res<Blocks>,
+    biomes: Res<BiomeMap>,
+    materials: Res<BlockMaterials>,
+    mut item_meshes: ResMut<ItemMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    falling: Query<(Entity, &FallingBlock), Added<FallingBlock>>,
+) {
+    let mut mesher = Mesher::new(&materials);
+    let half = FALLING_BLOCK_HALF_WIDTH;
+    for (entity, falling) in falling.iter() {
+        let models =
+            item_meshes.get_or_insert(falling.block, &blocks, &biomes, &mut mesher, &mut meshes);
+        commands
+            .entity(entity)
+            .insert((
+                RigidBody::Dynamic,
+                Collider::cuboid(half, half, half),
+                CollisionGroups::new(
+                    FALLING_BLOCK_GROUP,
+                    Group::GROUP_1 | PLAYER_GROUP | FALLING_BLOCK_GROUP,
+                ),
+                LockedAxes::ROTATION_LOCKED,
+                Velocity::zero(),
+            ))
+            .with_children(|model| {
+                for (sheet, mesh) in models {
+                    model.spawn(PbrBundle {
+                        mesh: mesh.clone(),
+                        material: materials.get_sheet_material(*sheet),
+                        ..default()
+                    });
+                }
+            });
+    }
+}
+
+/// Turn falling blocks which have come to rest back into blocks, snapped to the nearest free cell,
+/// or into dropped items if there is no room for them
+#[allow(clippy::too_many_arguments)]
+pub fn settle_falling_blocks(
+    time: Res<Time>,
+    blocks: Res<Blocks>,
+    biomes: Res<BiomeMap>,
+    materials: Res<BlockMaterials>,
+    mut item_meshes: ResMut<ItemMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut falling: Query<(Entity, &mut FallingBlock, &Transform, &Velocity)>,
+    mut world: WorldBlocks,
+) {
+    let delta = time.delta_seconds();
+    let mut mesher = Mesher::new(&materials);
+    for (entity, mut falling, transform, velocity) in falling.iter_mut() {
+        falling.age += delta;
+        if transform.translation.y < FALL_LIMIT {
+            world.commands().entity(entity).despawn_recursive();
+            continue;
+        }
+        if falling.age < SETTLE_DELAY || velocity.linvel.length() > SETTLE_SPEED {
+            continue;
+        }
+
+        let position = transform.translation.round().as_ivec3();
+        let is_free = |block: BlockId| !blocks.get_meshing_data(block).solid;
+        let free = [position, position + IVec3::Y]
+            .into_iter()
+            .find(|&cell| world.get(cell).is_some_and(is_free));
+        match free {
+            Some(cell) => {
+                // Blocks such as crops are knocked off, while fluids are displaced
+                let displaced = world.get(cell).unwrap();
+                if blocks.get_meshing_data(displaced).fluid.is_none() {
+                    world.break_block(cell);
+                }
+                world.set(cell, falling.block);
+            }
+            None => {
+                let block = blocks
+                    .block_type(falling.block)
+                    .map_or(falling.block, |block_type| blocks.default_state(block_type));
+                let models =
+                    item_meshes.get_or_insert(block, &blocks, &biomes, &mut mesher, &mut meshes);
+                spawn_dropped_item(
+                    world.commands(),
+                    DroppedItem {
+                        block,
+                        count: 1,
+                        age: 0.0,
+                    },
+                    transform.translation,
+                    Vec3::ZERO,
+                    models,
+                    &materials,
+                );
+            }
+        }
+        world.commands().entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,385 @@
+use bevy::prelude::*;
+use fxhash::{FxHashMap, FxHashSet};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    biome::BiomeMap,
+    block::{variant::position_hash, BlockId},
+    chunk::{
+        map::{ChunkMap, WorldBlocks},
+        Chunk, ChunkData, CHUNK_SIZE,
+    },
+    worldgen::WorldGenerator,
+};
+
+const FEATURE_SALT: u32 = 0xFEA7_0000;
+
+/// The blocks written by a feature as it is placed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureBlocks {
+    blocks: Vec<(IVec3, BlockId)>,
+}
+
+impl FeatureBlocks {
+    /// Place a block at a world position, if it is air once the feature is written
+    #[inline]
+    pub fn set(&mut self, position: IVec3, block: BlockId) {
+        self.blocks.push((position, block));
+    }
+
+    /// Place a block at every position from `min` to `max`, inclusive
+    pub fn fill(&mut self, min: IVec3, max: IVec3, block: BlockId) {
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    self.set(IVec3::new(x, y, z), block);
+                }
+            }
+        }
+    }
+}
+
+/// Writes the blocks of a feature standing on the surface, given the position of the block just
+/// above the surface
+pub type Feature = Box<dyn Fn(&mut FeatureBlocks, IVec3, &mut StdRng) + Send + Sync>;
+
+/// How often a feature is placed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FeatureDensity {
+    /// On each surface block with the chance given by the [`Biome::tree_density`] of its biome
+    ///
+    /// [`Biome::tree_density`]: crate::biome::Biome::tree_density
+    Trees,
+    /// On each surface block with a fixed chance
+    Constant(f32),
+}
+
+struct PlacedFeature {
+    feature: Feature,
+    density: FeatureDensity,
+    /// The surface blocks the feature may stand on, or any if empty
+    surfaces: Vec<BlockId>,
+}
+
+/// Decorative features, such as trees, placed on the surface of the world after its terrain is
+/// generated.
+///
+/// Features are generated for a region, a column of chunks, at a time, deterministically from the
+/// world seed and the region's coordinates, and may spill over into neighbouring chunks. The parts
+/// of features falling in chunks which don't exist yet are kept until those chunks are spawned.
+#[derive(Resource, Default)]
+pub struct Features {
+    features: Vec<PlacedFeature>,
+    /// The regions whose features have been generated
+    placed: FxHashSet<IVec2>,
+    /// The blocks waiting to be written into each chunk once it is spawned
+    deferred: FxHashMap<IVec3, Vec<([usize; 3], BlockId)>>,
+}
+
+impl Features {
+    /// Add a feature placed with the given density on the given surface blocks, or on any surface
+    /// if `surfaces` is empty
+    pub fn add_feature(
+        &mut self,
+        density: FeatureDensity,
+        surfaces: &[BlockId],
+        feature: impl Fn(&mut FeatureBlocks, IVec3, &mut StdRng) + Send + Sync + 'static,
+    ) {
+        self.features.push(PlacedFeature {
+            feature: Box::new(feature),
+            density,
+            surfaces: surfaces.to_vec(),
+        });
+    }
+
+    /// Get the region containing the chunk at the given chunk coordinates
+    #[inline]
+    pub fn region(coords: IVec3) -> IVec2 {
+        IVec2::new(coords.x, coords.z)
+    }
+
+    /// Generate the features standing in a region, returning the blocks they write, or nothing if
+    /// the region's features were already generated
+    pub fn place(
+        &mut self,
+        generator: &WorldGenerator,
+        biomes: &BiomeMap,
+        region: IVec2,
+    ) -> Vec<(IVec3, BlockId)> {
+        if !self.placed.insert(region) {
+            return Vec::new();
+        }
+        self.generate(generator, biomes, region)
+    }
+
+    /// Get the blocks written by the features standing in a region, whether or not they were
+    /// already placed
+    pub fn generate(
+        &self,
+        generator: &WorldGenerator,
+        biomes: &BiomeMap,
+        region: IVec2,
+    ) -> Vec<(IVec3, BlockId)> {
+        let coords = IVec3::new(region.x, 0, region.y);
+        let salt = generator.seed ^ FEATURE_SALT;
+        let mut rng = StdRng::seed_from_u64(position_hash(coords, salt) as u64);
+        let mut blocks = FeatureBlocks::default();
+        // The chunks holding the region's surface, generated to find the block each feature
+        // would stand on
+        let mut terrain: FxHashMap<IVec3, ChunkData> = FxHashMap::default();
+        let origin = ChunkMap::chunk_origin(coords);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let (x, z) = (origin.x + x, origin.z + z);
+                let surface = IVec3::new(x, generator.surface_height(biomes, x, z), z);
+                let Some(biome) = biomes.biome(surface).map(|biome| biomes.get(biome)) else {
+                    continue;
+                };
+                let (chunk, [cx, cy, cz]) = ChunkMap::chunk_coords(surface);
+                let block = terrain
+                    .entry(chunk)
+                    .or_insert_with(|| generator.generate(biomes, chunk))
+                    .blocks[cx][cy][cz];
+                // Caves open up the surface, leaving nothing to stand on
+                if block == BlockId::default() {
+                    continue;
+                }
+                for placed in &self.features {
+                    // Features which can't stand here don't roll, so they don't shift where the
+                    // others are placed
+                    if !placed.surfaces.is_empty() && !placed.surfaces.contains(&block) {
+                        continue;
+                    }
+                    let chance = match placed.density {
+                        FeatureDensity::Trees => biome.tree_density,
+                        FeatureDensity::Constant(chance) => chance,
+                    };
+                    if rng.gen_bool(chance.clamp(0.0, 1.0) as f64) {
+                        (placed.feature)(&mut blocks, surface + IVec3::Y, &mut rng);
+                        break;
+                    }
+                }
+            }
+        }
+        blocks.blocks
+    }
+
+    /// Keep a block written by a feature until the chunk containing it is spawned
+    #[inline]
+    pub fn defer(&mut self, position: IVec3, block: BlockId) {
+        let (coords, index) = ChunkMap::chunk_coords(position);
+        self.deferred
+            .entry(coords)
+            .or_default()
+            .push((index, block));
+    }
+
+    /// Remove and return the blocks waiting to be written into the chunk at the given chunk
+    /// coordinates
+    #[inline]
+    pub fn take_deferred(&mut self, coords: IVec3) -> Vec<([usize; 3], BlockId)> {
+        self.deferred.remove(&coords).unwrap_or_default()
+    }
+}
+
+/// Generate the features of the regions of newly spawned chunks, and write the blocks of features
+/// falling in those chunks into their air.
+///
+/// Blocks falling in chunks which already exist are written straight away, and the rest are kept
+/// until their chunks are spawned. The blocks are placed as live edits, so that they get their
+/// block entities, notify their neighbours and remesh the neighbouring chunks they border like any
+/// other changed block.
+pub fn place_features(
+    mut features: ResMut<Features>,
+    generator: Res<WorldGenerator>,
+    biomes: Res<BiomeMap>,
+    mut params: ParamSet<(Query<&Transform, Added<Chunk>>, WorldBlocks)>,
+) {
+    let added: Vec<_> = params
+        .p0()
+        .iter()
+        .map(|transform| ChunkMap::chunk_coords(Chunk::block_origin(transform)).0)
+        .collect();
+    let mut world = params.p1();
+    for &coords in &added {
+        let region = Features::region(coords);
+        for (position, block) in features.place(&generator, &biomes, region) {
+            match world.get(position) {
+                Some(existing) => {
+                    if existing == BlockId::default() {
+                        world.set(position, block);
+                    }
+                }
+                None => features.defer(position, block),
+            }
+        }
+    }
+    for coords in added {
+        let origin = ChunkMap::chunk_origin(coords);
+        for ([x, y, z], block) in features.take_deferred(coords) {
+            let position = origin + IVec3::new(x as i32, y as i32, z as i32);
+            if world.get(position) == Some(BlockId::default()) {
+                world.set(position, block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biome::Biome;
+    use crate::block::{state::StateValues, Blocks, MeshingData};
+    use crate::chunk::{map::index_chunks, BlockBroken, BlockChanged};
+    use crate::tick::WorldTick;
+
+    fn solid(_: StateValues) -> MeshingData {
+        MeshingData {
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        }
+    }
+
+    /// Create a world of flat grass without caves, with its surface at `y = 4`, and its stone,
+    /// grass and log blocks
+    fn world() -> (World, [BlockId; 3]) {
+        let mut world = World::new();
+        world.init_resource::<Blocks>();
+        world.init_resource::<ChunkMap>();
+        world.init_resource::<WorldTick>();
+        world.init_resource::<Features>();
+        world.init_resource::<Events<BlockChanged>>();
+        world.init_resource::<Events<BlockBroken>>();
+        let mut blocks = world.resource_mut::<Blocks>();
+        let [stone, dirt, grass, log] = [(); 4].map(|_| {
+            let block_type = blocks.new_block_type(&[], solid).unwrap();
+            blocks.default_state(block_type)
+        });
+        let mut generator = WorldGenerator::new(9, stone, dirt, grass);
+        generator.caves.cheese_threshold = 1.0;
+        generator.caves.spaghetti_width = 0.0;
+        let mut biomes = BiomeMap::default();
+        biomes
+            .add_biome(Biome {
+                name: "Flat".into(),
+                climate: Vec2::splat(0.5),
+                surface: grass,
+                subsurface: dirt,
+                base_height: 4.0,
+                amplitude: 0.0,
+                tree_density: 0.0,
+                grass_color: [0x40; 3],
+            })
+            .unwrap();
+        world.insert_resource(generator);
+        world.insert_resource(biomes);
+        (world, [stone, grass, log])
+    }
+
+    /// Place a pole of logs reaching from the surface into the chunk above
+    fn pole(log: BlockId) -> impl Fn(&mut FeatureBlocks, IVec3, &mut StdRng) + Send + Sync {
+        move |feature, base, _| feature.fill(base, base + IVec3::Y * 15, log)
+    }
+
+    #[test]
+    fn deferred_features_are_placed_across_chunk_borders() {
+        let (mut world, [_, _, log]) = world();
+        let mut schedule = Schedule::default();
+        schedule.add_systems((index_chunks, place_features).chain());
+
+        // A tree on the eastern edge of the first chunk, its leaves reaching into the next
+        let mut tree = FeatureBlocks::default();
+        tree.fill(IVec3::new(15, 0, 8), IVec3::new(15, 4, 8), log);
+        tree.fill(IVec3::new(13, 4, 6), IVec3::new(17, 5, 10), log);
+        let mut first = Chunk::default();
+        let mut deferred = 0;
+        for &(position, block) in &tree.blocks {
+            let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+            if coords == IVec3::ZERO {
+                first.data.blocks[x][y][z] = block;
+            } else {
+                world.resource_mut::<Features>().defer(position, block);
+                deferred += 1;
+            }
+        }
+        world.spawn((first, Transform::from_translation(Vec3::splat(8.0))));
+        schedule.run(&mut world);
+        world.spawn((
+            Chunk::default(),
+            Transform::from_translation(Vec3::new(24.0, 8.0, 8.0)),
+        ));
+        schedule.run(&mut world);
+
+        let map = world.resource::<ChunkMap>();
+        for &(position, block) in &tree.blocks {
+            let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+            let chunk = world.get::<Chunk>(map.get(coords).unwrap()).unwrap();
+            assert_eq!(chunk.data.blocks[x][y][z], block);
+        }
+        assert_eq!(world.resource::<Events<BlockChanged>>().len(), deferred);
+    }
+
+    #[test]
+    fn features_reach_into_chunks_spawned_later() {
+        let (mut world, [_, grass, log]) = world();
+        world.resource_mut::<Features>().add_feature(
+            FeatureDensity::Constant(1.0),
+            &[grass],
+            pole(log),
+        );
+        let mut schedule = Schedule::default();
+        schedule.add_systems((index_chunks, place_features).chain());
+
+        let generated = world
+            .resource::<WorldGenerator>()
+            .generate(world.resource::<BiomeMap>(), IVec3::ZERO);
+        world.spawn((
+            Chunk {
+                data: generated,
+                ..default()
+            },
+            Transform::from_translation(ChunkMap::chunk_translation(IVec3::ZERO)),
+        ));
+        schedule.run(&mut world);
+        // The chunk above is spawned once the world is running, after its region was generated
+        world.spawn((
+            Chunk::default(),
+            Transform::from_translation(ChunkMap::chunk_translation(IVec3::Y)),
+        ));
+        schedule.run(&mut world);
+
+        let map = world.resource::<ChunkMap>();
+        let [below, above] = [IVec3::ZERO, IVec3::Y].map(|coords| {
+            let chunk = world.get::<Chunk>(map.get(coords).unwrap()).unwrap();
+            chunk.data.blocks
+        });
+        for x in 0..16 {
+            for z in 0..16 {
+                assert_eq!(below[x][4][z], grass);
+                assert!((5..16).all(|y| below[x][y][z] == log));
+                assert!((0..5).all(|y| above[x][y][z] == log));
+                assert_eq!(above[x][5][z], BlockId::default());
+            }
+        }
+        assert!(world.resource::<Features>().deferred.is_empty());
+    }
+
+    #[test]
+    fn features_only_roll_on_their_surfaces() {
+        let (world, [stone, _, log]) = world();
+        let generator = world.resource::<WorldGenerator>();
+        let biomes = world.resource::<BiomeMap>();
+        let mut alone = Features::default();
+        alone.add_feature(FeatureDensity::Constant(0.3), &[], pole(log));
+        // A feature standing only on stone never matches the grass surface
+        let mut behind = Features::default();
+        behind.add_feature(FeatureDensity::Constant(0.3), &[stone], pole(stone));
+        behind.add_feature(FeatureDensity::Constant(0.3), &[], pole(log));
+
+        let region = IVec2::new(2, -1);
+        let blocks = alone.generate(generator, biomes, region);
+        assert!(!blocks.is_empty());
+        assert_eq!(blocks, behind.generate(generator, biomes, region));
+    }
+}
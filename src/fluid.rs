@@ -0,0 +1,195 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use fxhash::FxHashMap;
+
+use crate::{
+    block::{
+        face::Face, state::BlockProperty, texture::BlockTextureId, BlockId, BlockTypeId, Blocks,
+        MeshingData,
+    },
+    chunk::{
+        map::{ChunkMap, WorldBlocks},
+        Chunk,
+    },
+    player::PlayerCamera,
+    tick::{BlockTickContext, BlockTickHandlers},
+    update::BlockUpdateHandlers,
+};
+
+/// The highest level of a flowing fluid, which flows no further
+pub const MAX_FLUID_LEVEL: u8 = 7;
+/// The opacity class of fluid blocks, so that faces between different fluids and glass are shown
+pub const FLUID_OPACITY: u32 = 2;
+/// How much the player's movement is slowed while inside a fluid
+const SWIM_SPEED: f32 = 0.5;
+/// How fast, in blocks per second, the player floats upwards while inside a fluid
+const BUOYANCY: f32 = 1.0;
+
+/// How a fluid flows
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Fluid {
+    /// The number of ticks between each step of the fluid's flow
+    pub flow_delay: u64,
+}
+
+/// The block types which are fluids.
+///
+/// Each fluid block type has a state for each [`BlockProperty::Level`], where `0` is a source
+/// block, which never dries up, and `1` to [`MAX_FLUID_LEVEL`] are flowing blocks, one level
+/// further from a source with each block they flow sideways.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Fluids {
+    fluids: FxHashMap<BlockTypeId, Fluid>,
+}
+
+impl Fluids {
+    /// Register a fluid block type, showing `texture` on every face, and tinting the view with
+    /// `fog` while the camera is inside it. The fluid flows whenever its blocks' scheduled updates
+    /// are due, which are scheduled whenever they or their neighbours change.
+    #[allow(clippy::result_unit_err)]
+    pub fn new_fluid(
+        &mut self,
+        blocks: &mut Blocks,
+        handlers: &mut BlockTickHandlers,
+        updates: &mut BlockUpdateHandlers,
+        texture: BlockTextureId,
+        fog: [u8; 3],
+        fluid: Fluid,
+    ) -> Result<BlockTypeId, ()> {
+        let block_type = blocks.new_block_type(&[BlockProperty::Level], |state| MeshingData {
+            textures: [texture; 6],
+            opacity: FLUID_OPACITY,
+            solid: false,
+            fluid: Some(fluid_height(state.level().unwrap())),
+            fog: Some(fog),
+            ..default()
+        })?;
+        self.fluids.insert(block_type, fluid);
+        handlers.on_scheduled_tick(block_type, move |context, position, block| {
+            flow(context, position, block, fluid)
+        });
+        updates.on_update(block_type, move |context, position, _, _| {
+            context.world.schedule_tick(position, fluid.flow_delay);
+        });
+        Ok(block_type)
+    }
+
+    /// Forget a fluid block type which is being unregistered
+    #[inline]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) {
+        self.fluids.remove(&block_type);
+    }
+
+    /// Get the fluid a block type is, if any
+    #[inline]
+    pub fn get(&self, block_type: BlockTypeId) -> Option<Fluid> {
+        self.fluids.get(&block_type).copied()
+    }
+}
+
+/// Get the height, in sixteenths of a block, of the surface of a fluid at a level
+#[inline]
+pub fn fluid_height(level: u8) -> u8 {
+    14 - 12 * level.min(MAX_FLUID_LEVEL) / MAX_FLUID_LEVEL
+}
+
+/// Step the flow of a fluid block whose scheduled update is due.
+///
+/// Flowing blocks take the level of their lowest neighbour plus one, drying up once they are too far
+/// from a source. Fluids then fall into air below them, or otherwise spread sideways into air.
+fn flow(context: &mut BlockTickContext, position: IVec3, block: BlockId, fluid: Fluid) {
+    let BlockTickContext { world, blocks, .. } = context;
+    let air = BlockId::default();
+    let block_type = blocks.block_type(block);
+    let with_level = |level: u8| {
+        blocks
+            .with_property(block, BlockProperty::Level, level as u16)
+            .unwrap()
+    };
+    let level_at = |world: &WorldBlocks, position: IVec3| {
+        let block = world.get(position)?;
+        if blocks.block_type(block) != block_type {
+            return None;
+        }
+        blocks.state(block)?.level()
+    };
+    let horizontal = [Face::Right, Face::Left, Face::Back, Face::Front].map(Face::offset);
+
+    let level = blocks.state(block).and_then(|state| state.level()).unwrap();
+    let new_level = if level == 0 {
+        Some(0)
+    } else if level_at(world, position + IVec3::Y).is_some() {
+        Some(1)
+    } else {
+        horizontal
+            .iter()
+            .filter_map(|&offset| level_at(world, position + offset))
+            .min()
+            .map(|level| level + 1)
+            .filter(|&level| level <= MAX_FLUID_LEVEL)
+    };
+    if new_level != Some(level) {
+        world.set(position, new_level.map_or(air, with_level));
+        for face in Face::ALL {
+            world.schedule_tick(position + face.offset(), fluid.flow_delay);
+        }
+        if new_level.is_some() {
+            world.schedule_tick(position, fluid.flow_delay);
+        }
+        return;
+    }
+
+    let below = position - IVec3::Y;
+    match world.get(below) {
+        Some(block) if block == air => {
+            world.set(below, with_level(1));
+            world.schedule_tick(below, fluid.flow_delay);
+        }
+        Some(block) if blocks.block_type(block) != block_type && level < MAX_FLUID_LEVEL => {
+            for offset in horizontal {
+                if world.get(position + offset) == Some(air) {
+                    world.set(position + offset, with_level(level + 1));
+                    world.schedule_tick(position + offset, fluid.flow_delay);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Slow the player down while any block their collider overlaps is a fluid, and float them gently
+/// upwards
+pub fn player_swim(
+    time: Res<Time>,
+    blocks: Res<Blocks>,
+    map: Res<ChunkMap>,
+    chunks: Query<&Chunk>,
+    mut player: Query<
+        (&Transform, &Collider, &mut KinematicCharacterController),
+        With<PlayerCamera>,
+    >,
+) {
+    let Ok((transform, collider, mut controller)) = player.get_single_mut() else {
+        return;
+    };
+    let aabb = collider.raw.compute_local_aabb();
+    let min = (transform.translation + Vec3::new(aabb.mins.x, aabb.mins.y, aabb.mins.z))
+        .round()
+        .as_ivec3();
+    let max = (transform.translation + Vec3::new(aabb.maxs.x, aabb.maxs.y, aabb.maxs.z))
+        .round()
+        .as_ivec3();
+    let in_fluid = (min.x..=max.x).any(|x| {
+        (min.y..=max.y).any(|y| {
+            (min.z..=max.z).any(|z| {
+                map.get_block(&chunks, IVec3::new(x, y, z))
+                    .is_some_and(|block| blocks.get_meshing_data(block).fluid.is_some())
+            })
+        })
+    });
+    if in_fluid {
+        let translation = controller.translation.unwrap_or_default();
+        controller.translation =
+            Some(translation * SWIM_SPEED + Vec3::Y * BUOYANCY * time.delta_seconds());
+    }
+}
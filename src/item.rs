@@ -0,0 +1,386 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use fxhash::{FxHashMap, FxHashSet};
+use indexmap::IndexMap;
+use rand::Rng;
+
+use crate::{
+    biome::BiomeMap,
+    block::{
+        texture::{BlockMaterials, SheetId},
+        BlockId, Blocks,
+    },
+    chunk::{mesher::Mesher, BlockBroken, BlocksRemoved, ChunkData},
+    player::{PlayerCamera, PLAYER_GROUP},
+};
+
+/// The edge length of a dropped item cube
+pub const ITEM_SIZE: f32 = 0.25;
+/// How long, in seconds, a dropped item lives before despawning
+pub const ITEM_DESPAWN_TIME: f32 = 300.0;
+/// How long, in seconds, before a freshly dropped item can be picked up
+pub const ITEM_PICKUP_DELAY: f32 = 0.5;
+/// The radius around the player in which dropped items are collected
+pub const ITEM_PICKUP_RADIUS: f32 = 1.0;
+/// The distance within which identical dropped items merge
+pub const ITEM_MERGE_RADIUS: f32 = 1.0;
+
+const BOB_HEIGHT: f32 = 0.1;
+const BOB_SPEED: f32 = 2.0;
+const SPIN_SPEED: f32 = PI / 2.0;
+
+/// The collision group of dropped item bodies
+pub const ITEM_GROUP: Group = Group::GROUP_2;
+
+/// A stack of blocks lying in the world
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct DroppedItem {
+    pub block: BlockId,
+    pub count: u32,
+    /// The time, in seconds, since this item was dropped
+    pub age: f32,
+}
+
+/// The visual model of a dropped item, which bobs and spins independently of the physics body
+#[derive(Component)]
+pub struct ItemModel;
+
+/// The sensor used to detect the player walking over a dropped item
+#[derive(Component)]
+pub struct ItemPickup;
+
+/// Marks a dropped item whose pickup sensor is currently touching the player
+#[derive(Component)]
+#[component(storage = "SparseSet")]
+pub struct ItemTouchingPlayer;
+
+/// The blocks collected by a player
+#[derive(Component, Debug, Clone, Default)]
+pub struct Inventory {
+    pub items: IndexMap<BlockId, u32>,
+    /// The index of the selected stack in `items`
+    pub selected: usize,
+}
+
+impl Inventory {
+    /// Add `count` copies of `block` to this inventory
+    #[inline]
+    pub fn insert(&mut self, block: BlockId, count: u32) {
+        *self.items.entry(block).or_default() += count;
+    }
+
+    /// Get the currently selected block, if any
+    #[inline]
+    pub fn selected_block(&self) -> Option<BlockId> {
+        self.items.get_index(self.selected).map(|(block, _)| *block)
+    }
+
+    /// Remove a single copy of the currently selected block, returning it
+    #[inline]
+    pub fn take_selected(&mut self) -> Option<BlockId> {
+        let (block, count) = self.items.get_index_mut(self.selected)?;
+        let block = *block;
+        *count -= 1;
+        if *count == 0 {
+            // Preserve the order of the remaining stacks
+            self.items.shift_remove_index(self.selected);
+        }
+        Some(block)
+    }
+
+    /// Encode this inventory to be saved, such as with the block entity of a chest
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.selected as u32).to_le_bytes().to_vec();
+        for (&block, &count) in &self.items {
+            bytes.extend_from_slice(bytemuck::bytes_of(&block));
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode an inventory saved by [`Inventory::to_bytes`], or `None` if the bytes are malformed
+    pub fn from_bytes(bytes: &[u8]) -> Option<Inventory> {
+        let selected = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+        let stacks = bytes[4..].chunks_exact(8);
+        if !stacks.remainder().is_empty() {
+            return None;
+        }
+        let items = stacks
+            .map(|stack| {
+                let block = bytemuck::pod_read_unaligned(&stack[..4]);
+                let count = u32::from_le_bytes(stack[4..].try_into().unwrap());
+                (block, count)
+            })
+            .collect();
+        Some(Inventory { items, selected })
+    }
+}
+
+/// Cached item meshes for each block type
+#[derive(Resource, Default)]
+pub struct ItemMeshes {
+    meshes: FxHashMap<BlockId, Vec<(SheetId, Handle<Mesh>)>>,
+}
+
+impl ItemMeshes {
+    /// Forget the cached meshes for a block, so they are rebuilt the next time they are needed
+    #[inline]
+    pub fn forget(&mut self, block: BlockId) {
+        self.meshes.remove(&block);
+    }
+
+    /// Get the meshes of the item model for a block, with one mesh per texture sheet.
+    ///
+    /// Tinted faces take the colors of the biome at the world origin, and items are always fully lit.
+    pub fn get_or_insert(
+        &mut self,
+        block: BlockId,
+        blocks: &Blocks,
+        biomes: &BiomeMap,
+        mesher: &mut Mesher,
+        meshes: &mut Assets<Mesh>,
+    ) -> &[(SheetId, Handle<Mesh>)] {
+        self.meshes.entry(block).or_insert_with(|| {
+            let mut data = ChunkData::default();
+            data.blocks[8][8][8] = block;
+            mesher.clear();
+            // Without any cover, every face is lit by the sky
+            let sky_heights = [[i32::MIN; 18]; 18];
+            data.compute_mesh(blocks, biomes, IVec3::ZERO, [None; 6], &sky_heights, mesher);
+            mesher
+                .meshes
+                .drain()
+                .map(|((sheet, _), premesh)| (sheet, meshes.add(premesh.into_mesh())))
+                .collect()
+        })
+    }
+}
+
+/// Spawn a dropped item at the given position
+pub fn spawn_dropped_item(
+    commands: &mut Commands,
+    item: DroppedItem,
+    position: Vec3,
+    velocity: Vec3,
+    models: &[(SheetId, Handle<Mesh>)],
+    materials: &BlockMaterials,
+) -> Entity {
+    let half = ITEM_SIZE / 2.0;
+    commands
+        .spawn((
+            item,
+            RigidBody::Dynamic,
+            Collider::cuboid(half, half, half),
+            CollisionGroups::new(ITEM_GROUP, Group::GROUP_1),
+            LockedAxes::ROTATION_LOCKED,
+            Velocity::linear(velocity),
+            Damping {
+                linear_damping: 1.0,
+                angular_damping: 0.0,
+            },
+            SpatialBundle::from_transform(Transform::from_translation(position)),
+        ))
+        .with_children(|item| {
+            item.spawn((
+                ItemModel,
+                SpatialBundle::from_transform(Transform::from_scale(Vec3::splat(ITEM_SIZE))),
+            ))
+            .with_children(|model| {
+                for (sheet, mesh) in models {
+                    model.spawn(PbrBundle {
+                        mesh: mesh.clone(),
+                        material: materials.get_sheet_material(*sheet),
+                        ..default()
+                    });
+                }
+            });
+            item.spawn((
+                ItemPickup,
+                Collider::ball(ITEM_PICKUP_RADIUS),
+                Sensor,
+                CollisionGroups::new(ITEM_GROUP, PLAYER_GROUP),
+                ActiveEvents::COLLISION_EVENTS,
+                TransformBundle::default(),
+            ));
+        })
+        .id()
+}
+
+/// Forget the item meshes of removed or changed blocks, and delete removed blocks from dropped
+/// items and inventories
+pub fn purge_removed_items(
+    mut commands: Commands,
+    mut events: EventReader<BlocksRemoved>,
+    mut item_meshes: ResMut<ItemMeshes>,
+    items: Query<(Entity, &DroppedItem)>,
+    mut inventories: Query<&mut Inventory>,
+) {
+    for event in events.iter() {
+        for &block in event.removed.iter().chain(&event.changed) {
+            item_meshes.forget(block);
+        }
+        for (entity, item) in items.iter() {
+            if event.removed.contains(&item.block) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        for mut inventory in inventories.iter_mut() {
+            inventory
+                .items
+                .retain(|block, _| !event.removed.contains(block));
+            inventory.selected = inventory
+                .selected
+                .min(inventory.items.len().saturating_sub(1));
+        }
+    }
+}
+
+/// Spawn a dropped item for every broken block
+pub fn drop_broken_blocks(
+    mut commands: Commands,
+    mut broken: EventReader<BlockBroken>,
+    blocks: Res<Blocks>,
+    biomes: Res<BiomeMap>,
+    materials: Res<BlockMaterials>,
+    mut item_meshes: ResMut<ItemMeshes>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let mut mesher = Mesher::new(&materials);
+    let mut rng = rand::thread_rng();
+    for event in broken.iter() {
+        // Items forget the state of the block they were broken from
+        let Some(block) = blocks
+            .block_type(event.block)
+            .map(|block_type| blocks.default_state(block_type))
+        else {
+            continue;
+        };
+        let models = item_meshes.get_or_insert(block, &blocks, &biomes, &mut mesher, &mut meshes);
+        let velocity = Vec3::new(rng.gen_range(-1.0..1.0), 2.0, rng.gen_range(-1.0..1.0));
+        spawn_dropped_item(
+            &mut commands,
+            DroppedItem {
+                block,
+                count: 1,
+                age: 0.0,
+            },
+            event.position,
+            velocity,
+            models,
+            &materials,
+        );
+    }
+}
+
+/// Age dropped items, despawning them once they time out
+pub fn age_dropped_items(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut items: Query<(Entity, &mut DroppedItem)>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut item) in items.iter_mut() {
+        item.age += delta;
+        if item.age >= ITEM_DESPAWN_TIME {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Bob and spin the models of dropped items
+pub fn animate_dropped_items(
+    items: Query<&DroppedItem>,
+    mut models: Query<(&Parent, &mut Transform), With<ItemModel>>,
+) {
+    for (parent, mut transform) in models.iter_mut() {
+        if let Ok(item) = items.get(parent.get()) {
+            transform.translation.y = BOB_HEIGHT * (item.age * BOB_SPEED).sin();
+            transform.rotation = Quat::from_rotation_y(item.age * SPIN_SPEED);
+        }
+    }
+}
+
+/// Merge nearby dropped items of the same block into a single stack
+pub fn merge_dropped_items(
+    mut commands: Commands,
+    mut items: Query<(Entity, &mut DroppedItem, &Transform)>,
+) {
+    let mut merged = FxHashSet::default();
+    let mut combinations = items.iter_combinations_mut();
+    while let Some([(a, mut a_item, a_transform), (b, mut b_item, b_transform)]) =
+        combinations.fetch_next()
+    {
+        if a_item.block != b_item.block
+            || merged.contains(&a)
+            || merged.contains(&b)
+            || a_transform.translation.distance(b_transform.translation) > ITEM_MERGE_RADIUS
+        {
+            continue;
+        }
+        // Keep the larger stack, so that items flow towards existing piles
+        let (into, from, from_entity) = if a_item.count >= b_item.count {
+            (&mut a_item, &b_item, b)
+        } else {
+            (&mut b_item, &a_item, a)
+        };
+        into.count += from.count;
+        into.age = into.age.min(from.age);
+        merged.insert(from_entity);
+        commands.entity(from_entity).despawn_recursive();
+    }
+}
+
+/// Track which dropped items are touching the player using the collision events of their pickup
+/// sensors
+pub fn track_item_pickups(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    pickups: Query<&Parent, With<ItemPickup>>,
+    player: Query<Entity, With<PlayerCamera>>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    for event in collisions.iter() {
+        let (a, b, started) = match *event {
+            CollisionEvent::Started(a, b, _) => (a, b, true),
+            CollisionEvent::Stopped(a, b, _) => (a, b, false),
+        };
+        let sensor = if a == player {
+            b
+        } else if b == player {
+            a
+        } else {
+            continue;
+        };
+        let Ok(item) = pickups.get(sensor) else {
+            continue;
+        };
+        if let Some(mut item) = commands.get_entity(item.get()) {
+            if started {
+                item.insert(ItemTouchingPlayer);
+            } else {
+                item.remove::<ItemTouchingPlayer>();
+            }
+        }
+    }
+}
+
+/// Move dropped items touching the player into the player's inventory
+pub fn collect_dropped_items(
+    mut commands: Commands,
+    items: Query<(Entity, &DroppedItem), With<ItemTouchingPlayer>>,
+    mut player: Query<&mut Inventory, With<PlayerCamera>>,
+) {
+    let Ok(mut inventory) = player.get_single_mut() else {
+        return;
+    };
+    for (entity, item) in items.iter() {
+        if item.age >= ITEM_PICKUP_DELAY {
+            inventory.insert(item.block, item.count);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
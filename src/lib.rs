@@ -1,4 +1,16 @@
+pub mod biome;
 pub mod block;
 pub mod chunk;
+pub mod cloud;
+pub mod falling;
+pub mod feature;
+pub mod fluid;
+pub mod item;
+pub mod noise;
+pub mod pack;
 pub mod player;
-pub mod ui;
\ No newline at end of file
+pub mod sky;
+pub mod tick;
+pub mod ui;
+pub mod update;
+pub mod worldgen;
@@ -1,48 +1,201 @@
 use std::time::Duration;
 
-use bevy::{asset::ChangeWatcher, diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
+use bevy::{
+    asset::ChangeWatcher,
+    diagnostic::FrameTimeDiagnosticsPlugin,
+    prelude::*,
+    render::{render_asset::PrepareAssetSet, Render, RenderApp},
+};
 use bevy_egui::{EguiPlugin, EguiSettings};
 use bevy_rapier3d::prelude::*;
+use fxhash::{FxHashMap, FxHashSet};
+use mellanite::biome::{Biome, BiomeMap};
 use mellanite::block::{
-    texture::{blit_loaded_textures, BlockMaterials},
-    BlockId, Blocks,
+    connected::ConnectedTextures,
+    face::Face,
+    load_block_models,
+    model::{BlockModel, BlockModelLoader, ModelBox},
+    state::BlockProperty,
+    texture::{
+        animate_block_textures, blit_loaded_textures, extract_animated_tiles, write_animated_tiles,
+        AnimatedTileWrites, BlockMaterials, MaterialSettings, TextureAnimation, TextureMap,
+        TextureResampling,
+    },
+    variant::TextureVariant,
+    BlockId, Blocks, MeshingData, Tint,
 };
 use mellanite::chunk::{
-    mesher::{chunk_mesher_system, ChunkNeedsMeshing},
-    Chunk, ChunkData,
+    entity::{spawn_chunk_block_entities, update_block_entities, BlockEntityTypes},
+    map::{index_chunks, ChunkMap, WorldBlocks},
+    mesher::{chunk_mesher_system, remesh_chunk_borders, ChunkNeedsMeshing},
+    purge_removed_blocks, BlockBroken, BlockChanged, BlocksRemoved, Chunk, ViewDistance,
+};
+use mellanite::cloud::{color_clouds, setup_clouds, update_clouds, Clouds};
+use mellanite::falling::{register_gravity_block, settle_falling_blocks, spawn_falling_blocks};
+use mellanite::feature::{place_features, FeatureDensity, Features};
+use mellanite::fluid::{player_swim, Fluid, Fluids};
+use mellanite::item::{
+    age_dropped_items, animate_dropped_items, collect_dropped_items, drop_broken_blocks,
+    merge_dropped_items, purge_removed_items, track_item_pickups, Inventory, ItemMeshes,
+};
+use mellanite::pack::{apply_resource_packs, ResourcePacks};
+use mellanite::sky::{
+    advance_time_of_day, follow_camera_with_sky, setup_sky, update_chunk_materials, update_fog,
+    update_sky_dome, update_sky_lighting, ChunkMaterials, SkyColors, SkyLight, TimeOfDay,
 };
+use mellanite::tick::{
+    advance_world_tick, tick_blocks, BlockTickContext, BlockTickHandlers, RandomTickSpeed,
+    ScheduledTicks, WorldTick, TICKS_PER_SECOND,
+};
+use mellanite::update::{
+    queue_block_updates, run_block_updates, BlockUpdateHandlers, BlockUpdates,
+};
+use mellanite::worldgen::{OreVein, WorldGenerator};
 use rand::Rng;
 
+/// The seed the world is generated from
+const WORLD_SEED: u32 = 0x5EED;
+
 fn main() {
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(AssetPlugin {
-                    watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
-                    ..default()
-                }),
+    let mut app = App::new();
+    app.add_plugins(
+        DefaultPlugins
+            .set(ImagePlugin::default_nearest())
+            .set(AssetPlugin {
+                watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
+                ..default()
+            }),
+    )
+    .add_plugins(EguiPlugin)
+    .add_plugins(FrameTimeDiagnosticsPlugin)
+    .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
+    // .add_plugins(RapierDebugRenderPlugin::default())
+    .insert_resource(EguiSettings {
+        scale_factor: 1.0,
+        ..EguiSettings::default()
+    })
+    .insert_resource(Blocks::default())
+    .insert_resource(ClearColor(Color::ALICE_BLUE))
+    .insert_resource(TimeOfDay::default())
+    .insert_resource(SkyColors::default())
+    .insert_resource(SkyLight::default())
+    .insert_resource(ChunkMaterials::default())
+    .insert_resource(ViewDistance::default())
+    .insert_resource(Clouds::default())
+    .insert_resource(ChunkMap::default())
+    .insert_resource(WorldTick::default())
+    .insert_resource(RandomTickSpeed::default())
+    .insert_resource(BlockTickHandlers::default())
+    .insert_resource(BlockUpdates::default())
+    .insert_resource(BlockUpdateHandlers::default())
+    .insert_resource(BlockEntityTypes::default())
+    .insert_resource(Fluids::default())
+    .insert_resource(FixedTime::new_from_secs(1.0 / TICKS_PER_SECOND))
+    .insert_resource(BlockMaterials::default())
+    .insert_resource(AnimatedTileWrites::default())
+    .insert_resource(ItemMeshes::default())
+    .insert_resource(BiomeMap::default())
+    .insert_resource(ResourcePacks::default())
+    .add_asset::<BlockModel>()
+    .init_asset_loader::<BlockModelLoader>()
+    .add_event::<BlockBroken>()
+    .add_event::<BlockChanged>()
+    .add_event::<BlocksRemoved>()
+    .add_systems(Startup, mellanite::player::setup_player)
+    .add_systems(Startup, setup_environment)
+    .add_systems(Startup, setup_sky)
+    .add_systems(Startup, setup_clouds)
+    .add_systems(
+        Update,
+        update_clouds
+            .after(advance_time_of_day)
+            .after(mellanite::player::player_control),
+    )
+    .add_systems(Update, color_clouds.after(advance_time_of_day))
+    .add_systems(Update, advance_time_of_day)
+    .add_systems(
+        Update,
+        update_sky_lighting
+            .after(advance_time_of_day)
+            .before(update_chunk_materials),
+    )
+    .add_systems(Update, update_chunk_materials.after(chunk_mesher_system))
+    .add_systems(Update, update_sky_dome.after(advance_time_of_day))
+    .add_systems(
+        Update,
+        update_fog
+            .after(advance_time_of_day)
+            .after(mellanite::player::player_control),
+    )
+    .add_systems(
+        Update,
+        follow_camera_with_sky.after(mellanite::player::player_control),
+    )
+    .add_systems(Update, mellanite::ui::ui_system)
+    .add_systems(Update, mellanite::ui::resource_pack_ui)
+    .add_systems(Update, mellanite::ui::texture_atlas_ui)
+    .add_systems(Update, mellanite::player::player_control)
+    .add_systems(Update, player_swim.after(mellanite::player::player_control))
+    .add_systems(PreUpdate, index_chunks)
+    .add_systems(
+        FixedUpdate,
+        (advance_world_tick, tick_blocks, run_block_updates).chain(),
+    )
+    .add_systems(Update, queue_block_updates)
+    .add_systems(Update, (spawn_chunk_block_entities, update_block_entities))
+    .add_systems(
+        Update,
+        (spawn_falling_blocks, settle_falling_blocks)
+            .chain()
+            .after(purge_removed_items),
+    )
+    .add_systems(
+        Update,
+        (
+            mellanite::player::player_select_item,
+            mellanite::player::player_break_blocks,
+            mellanite::player::player_place_blocks,
         )
-        .add_plugins(EguiPlugin)
-        .add_plugins(FrameTimeDiagnosticsPlugin)
-        .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        // .add_plugins(RapierDebugRenderPlugin::default())
-        .insert_resource(EguiSettings {
-            scale_factor: 1.0,
-            ..EguiSettings::default()
-        })
-        .insert_resource(Blocks::default())
-        .insert_resource(ClearColor(Color::ALICE_BLUE))
-        .insert_resource(BlockMaterials::default())
-        .add_systems(Startup, mellanite::player::setup_player)
-        .add_systems(Startup, setup_environment)
-        .add_systems(Update, mellanite::ui::ui_system)
-        .add_systems(Update, mellanite::player::player_control)
-        .add_systems(Update, blit_loaded_textures)
-        .add_systems(Update, chunk_mesher_system)
-        .run()
+            .before(mellanite::player::player_control),
+    )
+    .add_systems(Update, apply_resource_packs.before(blit_loaded_textures))
+    .add_systems(Update, blit_loaded_textures)
+    .add_systems(Update, animate_block_textures.after(blit_loaded_textures))
+    .add_systems(Update, load_block_models)
+    .add_systems(Update, purge_removed_blocks.before(chunk_mesher_system))
+    .add_systems(Update, remesh_chunk_borders.before(chunk_mesher_system))
+    .add_systems(
+        Update,
+        place_features
+            .before(queue_block_updates)
+            .before(update_block_entities)
+            .before(remesh_chunk_borders),
+    )
+    .add_systems(Update, chunk_mesher_system)
+    .add_systems(
+        Update,
+        (
+            purge_removed_items,
+            drop_broken_blocks,
+            age_dropped_items,
+            animate_dropped_items,
+            merge_dropped_items,
+            track_item_pickups,
+            collect_dropped_items,
+        )
+            .chain(),
+    );
+    app.sub_app_mut(RenderApp)
+        .init_resource::<AnimatedTileWrites>()
+        .add_systems(ExtractSchedule, extract_animated_tiles)
+        .add_systems(
+            Render,
+            write_animated_tiles.in_set(PrepareAssetSet::PostAssetPrepare),
+        );
+    app.run()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn setup_environment(
     mut commands: Commands,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -50,12 +203,13 @@ fn setup_environment(
     mut blocks: ResMut<Blocks>,
     mut block_materials: ResMut<BlockMaterials>,
     mut images: ResMut<Assets<Image>>,
-    asset_server: Res<AssetServer>,
+    mut packs: ResMut<ResourcePacks>,
+    mut fluids: ResMut<Fluids>,
+    mut handlers: ResMut<BlockTickHandlers>,
+    mut updates: ResMut<BlockUpdateHandlers>,
+    mut block_entities: ResMut<BlockEntityTypes>,
+    mut biomes: ResMut<BiomeMap>,
 ) {
-    let mut chunk = ChunkData {
-        blocks: [[[BlockId::default(); 16]; 16]; 16],
-    };
-
     let solid_material = materials.add(StandardMaterial {
         base_color_texture: None,
         perceptual_roughness: 1.0,
@@ -65,7 +219,13 @@ fn setup_environment(
         ..default()
     });
     let solid_block_material = block_materials
-        .new_material(solid_material.clone())
+        .new_material_with(
+            solid_material.clone(),
+            MaterialSettings {
+                resampling: TextureResampling::Nearest,
+                ..default()
+            },
+        )
         .unwrap();
 
     let glassy_material = block_materials
@@ -79,6 +239,17 @@ fn setup_environment(
         }))
         .unwrap();
 
+    let fluid_material = block_materials
+        .new_material(materials.add(StandardMaterial {
+            base_color_texture: None,
+            perceptual_roughness: 0.2,
+            reflectance: 0.5,
+            unlit: false,
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        }))
+        .unwrap();
+
     let coords_texture = block_materials
         .new_texture(solid_block_material, &mut materials)
         .unwrap();
@@ -91,8 +262,13 @@ fn setup_environment(
     let white_ore_texture = block_materials
         .new_texture(solid_block_material, &mut materials)
         .unwrap();
-    let glass_texture = block_materials
-        .new_texture(glassy_material, &mut materials)
+    let glass_textures = [(); 16].map(|_| {
+        block_materials
+            .new_texture(glassy_material, &mut materials)
+            .unwrap()
+    });
+    let magma_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
         .unwrap();
     let grass_top_texture = block_materials
         .new_texture(solid_block_material, &mut materials)
@@ -100,103 +276,619 @@ fn setup_environment(
     let grass_side_texture = block_materials
         .new_texture(solid_block_material, &mut materials)
         .unwrap();
+    let water_texture = block_materials
+        .new_texture(fluid_material, &mut materials)
+        .unwrap();
+    let lava_texture = block_materials
+        .new_texture(fluid_material, &mut materials)
+        .unwrap();
+    let sand_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let gravel_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let log_top_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let log_side_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let leaves_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let chest_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let torch_texture = block_materials
+        .new_texture(solid_block_material, &mut materials)
+        .unwrap();
+    let crop_textures = [(); 8].map(|_| {
+        block_materials
+            .new_texture(solid_block_material, &mut materials)
+            .unwrap()
+    });
     let coords = blocks
-        .new_block([coords_texture; 6], u32::MAX, true)
+        .new_block_type(&[BlockProperty::Facing], |state| {
+            MeshingData {
+                textures: [coords_texture; 6],
+                opacity: u32::MAX,
+                solid: true,
+                ..default()
+            }
+            .facing(state.facing().unwrap())
+        })
         .unwrap();
+    let coords = blocks.default_state(coords);
     let dirt = blocks.new_block([dirt_texture; 6], u32::MAX, true).unwrap();
+    let stone_variants = blocks
+        .new_texture_variants(TextureVariant::transforms(stone_texture))
+        .unwrap();
     let stone = blocks
-        .new_block([stone_texture; 6], u32::MAX, true)
+        .new_block_type(&[], |_| MeshingData {
+            textures: [stone_texture; 6],
+            variants: [Some(stone_variants); 6],
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        })
         .unwrap();
+    let stone = blocks.default_state(stone);
     let white_ore = blocks
         .new_block([white_ore_texture; 6], u32::MAX, true)
         .unwrap();
-    let glass = blocks.new_block([glass_texture; 6], 1, true).unwrap();
+    let glass_connected = blocks
+        .new_connected_textures(ConnectedTextures::new(glass_textures))
+        .unwrap();
+    let glass = blocks
+        .new_block_type(&[], |_| MeshingData {
+            textures: [glass_textures[0]; 6],
+            connected: [Some(glass_connected); 6],
+            opacity: 1,
+            solid: true,
+            ..default()
+        })
+        .unwrap();
+    let glass = blocks.default_state(glass);
+    let magma = blocks
+        .new_block([magma_texture; 6], u32::MAX, true)
+        .unwrap();
+    let grass_top_variants = blocks
+        .new_texture_variants(TextureVariant::rotations(grass_top_texture))
+        .unwrap();
     let grass = blocks
-        .new_block(
-            [
+        .new_block_type(&[], |_| MeshingData {
+            textures: [
                 grass_top_texture,
                 dirt_texture,
-                grass_side_texture,
-                grass_side_texture,
-                grass_side_texture,
-                grass_side_texture,
+                dirt_texture,
+                dirt_texture,
+                dirt_texture,
+                dirt_texture,
             ],
-            u32::MAX,
-            true,
+            variants: [Some(grass_top_variants), None, None, None, None, None],
+            tints: [
+                Tint::Grass,
+                Tint::None,
+                Tint::None,
+                Tint::None,
+                Tint::None,
+                Tint::None,
+            ],
+            overlays: [
+                None,
+                None,
+                Some((grass_side_texture, Tint::Grass)),
+                Some((grass_side_texture, Tint::Grass)),
+                Some((grass_side_texture, Tint::Grass)),
+                Some((grass_side_texture, Tint::Grass)),
+            ],
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        })
+        .unwrap();
+    let grass = blocks.default_state(grass);
+
+    let slab_model = blocks.new_model(BlockModel::Cube).unwrap();
+    packs.set_model_path(slab_model, "slab.blockmodel");
+    let stone_slab = blocks
+        .new_block_type(&[], |_| MeshingData {
+            textures: [stone_texture; 6],
+            opacity: u32::MAX,
+            solid: true,
+            model: Some(slab_model),
+            ..default()
+        })
+        .unwrap();
+    let stone_slab = blocks.default_state(stone_slab);
+
+    let stairs_model = BlockModel::Boxes(vec![
+        ModelBox::from_pixels([0.0, 0.0, 0.0], [16.0, 8.0, 16.0]),
+        ModelBox::from_pixels([0.0, 8.0, 8.0], [16.0, 16.0, 16.0]),
+    ]);
+    let stairs_models = Face::ALL.map(|facing| {
+        blocks
+            .new_model(stairs_model.rotated(Face::Front.rotation_to(facing)))
+            .unwrap()
+    });
+    let stone_stairs = blocks
+        .new_block_type(&[BlockProperty::Facing], |state| MeshingData {
+            textures: [stone_texture; 6],
+            opacity: u32::MAX,
+            solid: true,
+            model: Some(stairs_models[state.facing().unwrap().index()]),
+            ..default()
+        })
+        .unwrap();
+
+    let water = fluids
+        .new_fluid(
+            &mut blocks,
+            &mut handlers,
+            &mut updates,
+            water_texture,
+            [40, 70, 150],
+            Fluid { flow_delay: 5 },
+        )
+        .unwrap();
+    let lava = fluids
+        .new_fluid(
+            &mut blocks,
+            &mut handlers,
+            &mut updates,
+            lava_texture,
+            [200, 70, 10],
+            Fluid { flow_delay: 30 },
         )
         .unwrap();
 
-    let mut rng = rand::thread_rng();
-    for x in 0..16 {
-        for z in 0..16 {
-            let y = rng.gen_range(7..=9);
-            chunk.blocks[x][y][z] = grass;
-            chunk.blocks[x][y - 1][z] = dirt;
-            for y in 0..y - 1 {
-                if rng.gen_bool(0.1) {
-                    chunk.blocks[x][y][z] = white_ore;
-                } else if rng.gen_bool(0.1) {
-                    chunk.blocks[x][y][z] = coords;
-                } else if rng.gen_bool(0.5) {
-                    chunk.blocks[x][y][z] = stone;
-                } else {
-                    chunk.blocks[x][y][z] = glass;
-                }
-            }
+    // Grass dies under opaque blocks, and otherwise spreads to nearby uncovered dirt
+    let grass_type = blocks.block_type(grass).unwrap();
+    handlers.on_random_tick(grass_type, move |context, position, _| {
+        let BlockTickContext { world, blocks, rng } = context;
+        let covered = |world: &WorldBlocks, position: IVec3| {
+            world
+                .get(position + IVec3::Y)
+                .is_some_and(|block| blocks.get_meshing_data(block).opacity == u32::MAX)
+        };
+        if covered(world, position) {
+            world.set(position, dirt);
+            return;
         }
-    }
+        let target = position
+            + IVec3::new(
+                rng.gen_range(-1..=1),
+                rng.gen_range(-3..=1),
+                rng.gen_range(-1..=1),
+            );
+        if world.get(target) == Some(dirt) && !covered(world, target) {
+            world.set(target, grass);
+        }
+    });
 
-    let dirt_image: Handle<Image> = asset_server.load("dirt.png");
-    block_materials
-        .set_block_texture(dirt_texture, dirt_image, &mut images, &mut materials)
+    let crop_model = blocks.new_model(BlockModel::Cross).unwrap();
+    let crops = blocks
+        .new_block_type(&[BlockProperty::Level], |state| MeshingData {
+            textures: [crop_textures[state.level().unwrap() as usize]; 6],
+            opacity: 1,
+            solid: false,
+            model: Some(crop_model),
+            ..default()
+        })
         .unwrap();
+    // Crops grow a stage at a time until they are ripe
+    handlers.on_random_tick(crops, |context, position, block| {
+        let level = context.blocks.state(block).and_then(|state| state.level());
+        if let Some(level @ 0..=6) = level {
+            if context.rng.gen_bool(0.25) {
+                let grown = context
+                    .blocks
+                    .with_property(block, BlockProperty::Level, level as u16 + 1)
+                    .unwrap();
+                context.world.set(position, grown);
+            }
+        }
+    });
+    // Crops break once the soil they grow in is gone
+    updates.on_update(crops, move |context, position, _, _| {
+        let soil = context.world.get(position - IVec3::Y);
+        if soil.is_some_and(|soil| soil != dirt && soil != grass) {
+            context.world.break_block(position);
+        }
+    });
 
-    let stone_image: Handle<Image> = asset_server.load("stone.png");
-    block_materials
-        .set_block_texture(stone_texture, stone_image, &mut images, &mut materials)
+    let sand = blocks.new_block([sand_texture; 6], u32::MAX, true).unwrap();
+    let gravel = blocks
+        .new_block([gravel_texture; 6], u32::MAX, true)
         .unwrap();
+    // Sand and gravel fall whenever there is nothing solid below them
+    for block in [sand, gravel] {
+        register_gravity_block(&mut updates, blocks.block_type(block).unwrap());
+    }
 
-    let white_ore_image: Handle<Image> = asset_server.load("white_ore.png");
-    block_materials
-        .set_block_texture(
-            white_ore_texture,
-            white_ore_image,
-            &mut images,
-            &mut materials,
+    let log = blocks
+        .new_block(
+            [
+                log_top_texture,
+                log_top_texture,
+                log_side_texture,
+                log_side_texture,
+                log_side_texture,
+                log_side_texture,
+            ],
+            u32::MAX,
+            true,
         )
         .unwrap();
-
-    let glass_image: Handle<Image> = asset_server.load("glass.png");
-    block_materials
-        .set_block_texture(glass_texture, glass_image, &mut images, &mut materials)
+    let leaves = blocks
+        .new_block_type(&[], |_| MeshingData {
+            textures: [leaves_texture; 6],
+            tints: [Tint::Foliage; 6],
+            opacity: 1,
+            solid: true,
+            ..default()
+        })
         .unwrap();
+    let leaves = blocks.default_state(leaves);
 
-    let grass_side: Handle<Image> = asset_server.load("grass_side.png");
-    block_materials
-        .set_block_texture(grass_side_texture, grass_side, &mut images, &mut materials)
+    let chest = blocks
+        .new_block([chest_texture; 6], u32::MAX, true)
         .unwrap();
+    // Each chest holds its own stacks of blocks, which are saved with its chunk
+    let chest_type = blocks.block_type(chest).unwrap();
+    block_entities.register(chest_type, |entity, _| {
+        entity.insert(Inventory::default());
+    });
+    block_entities.persist(
+        chest_type,
+        |entity| {
+            entity
+                .get::<Inventory>()
+                .map(Inventory::to_bytes)
+                .unwrap_or_default()
+        },
+        |entity, bytes| {
+            if let Some(inventory) = Inventory::from_bytes(bytes) {
+                entity.insert(inventory);
+            }
+        },
+    );
 
-    let grass_top_image: Handle<Image> = asset_server.load("grass_top.png");
-    block_materials
-        .set_block_texture(
-            grass_top_texture,
-            grass_top_image,
-            &mut images,
-            &mut materials,
+    let torch_post = ModelBox::from_pixels([7.0, 0.0, 7.0], [9.0, 10.0, 9.0]);
+    let torch_models = Face::ALL.map(|attached| {
+        let model = match attached {
+            Face::Bottom => BlockModel::Boxes(vec![torch_post]),
+            Face::Top => BlockModel::Boxes(vec![ModelBox::from_pixels(
+                [7.0, 6.0, 7.0],
+                [9.0, 16.0, 9.0],
+            )]),
+            _ => BlockModel::Boxes(vec![ModelBox::from_pixels(
+                [7.0, 3.0, 0.0],
+                [9.0, 13.0, 2.0],
+            )])
+            .rotated(Face::Front.rotation_to(attached)),
+        };
+        blocks.new_model(model).unwrap()
+    });
+    let torch = blocks
+        .new_block_type(&[BlockProperty::Attached], |state| MeshingData {
+            textures: [torch_texture; 6],
+            opacity: 1,
+            solid: false,
+            model: Some(torch_models[state.attached().unwrap().index()]),
+            ..default()
+        })
+        .unwrap();
+    let torch_on_floor = blocks
+        .with_property(
+            blocks.default_state(torch),
+            BlockProperty::Attached,
+            Face::Bottom as u16,
         )
         .unwrap();
+    // Torches break once the block they are attached to is no longer a solid cube
+    updates.on_update(torch, |context, position, block, _| {
+        let attached = context
+            .blocks
+            .state(block)
+            .and_then(|state| state.attached());
+        let Some(attached) = attached else {
+            return;
+        };
+        let unsupported = context
+            .world
+            .get(position + attached.offset())
+            .is_some_and(|support| {
+                let data = context.blocks.get_meshing_data(support);
+                !data.solid || !context.blocks.is_full_cube(&data)
+            });
+        if unsupported {
+            context.world.break_block(position);
+        }
+    });
 
-    let coords_image: Handle<Image> = asset_server.load("coords.png");
+    biomes.seed = WORLD_SEED;
+    biomes.climate_scale = 48.0;
+    for biome in [
+        Biome {
+            name: "Plains".into(),
+            climate: Vec2::new(0.5, 0.4),
+            surface: grass,
+            subsurface: dirt,
+            base_height: -8.0,
+            amplitude: 3.0,
+            tree_density: 0.005,
+            grass_color: [0x79, 0xC0, 0x5A],
+        },
+        Biome {
+            name: "Forest".into(),
+            climate: Vec2::new(0.45, 0.65),
+            surface: grass,
+            subsurface: dirt,
+            base_height: -7.0,
+            amplitude: 5.0,
+            tree_density: 0.04,
+            grass_color: [0x4F, 0x9A, 0x3C],
+        },
+        Biome {
+            name: "Desert".into(),
+            climate: Vec2::new(0.7, 0.3),
+            surface: sand,
+            subsurface: sand,
+            base_height: -9.0,
+            amplitude: 2.0,
+            tree_density: 0.0,
+            grass_color: [0xBF, 0xB7, 0x55],
+        },
+        Biome {
+            name: "Hills".into(),
+            climate: Vec2::new(0.3, 0.5),
+            surface: grass,
+            subsurface: dirt,
+            base_height: -4.0,
+            amplitude: 10.0,
+            tree_density: 0.01,
+            grass_color: [0x80, 0xB4, 0x97],
+        },
+    ] {
+        biomes.add_biome(biome).unwrap();
+    }
+
+    let mut generator = WorldGenerator::new(WORLD_SEED, stone, dirt, grass);
+    for ore in [
+        OreVein {
+            block: white_ore,
+            size: 10,
+            heights: -64..-4,
+            frequency: 4.0,
+        },
+        OreVein {
+            block: magma,
+            size: 8,
+            heights: -64..-18,
+            frequency: 1.5,
+        },
+        OreVein {
+            block: gravel,
+            size: 24,
+            heights: -64..0,
+            frequency: 1.0,
+        },
+        OreVein {
+            block: glass,
+            size: 16,
+            heights: -64..-8,
+            frequency: 0.5,
+        },
+        OreVein {
+            block: coords,
+            size: 4,
+            heights: -64..0,
+            frequency: 1.0,
+        },
+    ] {
+        generator.add_ore(ore).unwrap();
+    }
+
+    let mut chunks = FxHashMap::default();
+    for x in -2..2 {
+        for y in -2..0 {
+            for z in -2..2 {
+                let coords = IVec3::new(x, y, z);
+                chunks.insert(
+                    coords,
+                    (
+                        generator.generate(&biomes, coords),
+                        ScheduledTicks::default(),
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut features = Features::default();
+    // Trees are a trunk of logs topped with a rough ball of leaves
+    features.add_feature(
+        FeatureDensity::Trees,
+        &[grass],
+        move |feature, base, rng| {
+            let top = base + IVec3::Y * rng.gen_range(3..=5);
+            feature.fill(base, top, log);
+            for dy in -2..=1 {
+                let radius: i32 = if dy < 0 { 2 } else { 1 };
+                for dx in -radius..=radius {
+                    for dz in -radius..=radius {
+                        let corner = dx.abs() == radius && dz.abs() == radius;
+                        if !corner || (dy < 0 && rng.gen_bool(0.5)) {
+                            feature.set(top + IVec3::new(dx, dy, dz), leaves);
+                        }
+                    }
+                }
+            }
+        },
+    );
+    // Boulders are lumps of stone, flecked with ore, half sunk into the ground
+    features.add_feature(
+        FeatureDensity::Constant(0.002),
+        &[],
+        move |feature, base, rng| {
+            let radius = rng.gen_range(1..=2);
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    for dz in -radius..=radius {
+                        let offset = IVec3::new(dx, dy, dz);
+                        if offset.length_squared() <= radius * radius + 1 {
+                            let block = if rng.gen_bool(0.1) { white_ore } else { stone };
+                            feature.set(base + offset, block);
+                        }
+                    }
+                }
+            }
+        },
+    );
+    // Ruins are the crumbling walls of a small stone hut, propped up on foundations
+    features.add_feature(
+        FeatureDensity::Constant(0.0005),
+        &[grass],
+        move |feature, base, rng| {
+            let size: i32 = rng.gen_range(2..=3);
+            for dx in -size..=size {
+                for dz in -size..=size {
+                    if dx.abs() != size && dz.abs() != size {
+                        continue;
+                    }
+                    let height = rng.gen_range(-1..=3);
+                    let column = base + IVec3::new(dx, 0, dz);
+                    feature.fill(column - IVec3::Y * 3, column - IVec3::Y, stone);
+                    if height >= 0 {
+                        feature.fill(column, column + IVec3::Y * height, stone);
+                        if rng.gen_bool(0.3) {
+                            feature.set(column + IVec3::Y * (height + 1), stone_slab);
+                        }
+                    }
+                }
+            }
+        },
+    );
+    // Features are placed as their chunks are spawned, so decorations keep clear of them
+    let covered: FxHashSet<IVec3> = (-2..2)
+        .flat_map(|x| (-2..2).map(move |z| IVec2::new(x, z)))
+        .flat_map(|region| features.generate(&generator, &biomes, region))
+        .map(|(position, _)| position)
+        .collect();
+
+    // Scatter decorations over the grass left uncovered by caves and features
+    let mut rng = rand::thread_rng();
+    for (&coords, (chunk, _)) in chunks.iter_mut() {
+        let origin = ChunkMap::chunk_origin(coords);
+        for x in 0..16 {
+            for z in 0..16 {
+                let height =
+                    generator.surface_height(&biomes, origin.x + x as i32, origin.z + z as i32);
+                let y = (height - origin.y) as usize;
+                if !(0..15).contains(&y)
+                    || covered.contains(&IVec3::new(
+                        origin.x + x as i32,
+                        height + 1,
+                        origin.z + z as i32,
+                    ))
+                    || chunk.blocks[x][y][z] != grass
+                    || chunk.blocks[x][y + 1][z] != BlockId::default()
+                {
+                    continue;
+                }
+                if rng.gen_bool(0.05) {
+                    chunk.blocks[x][y + 1][z] = stone_slab;
+                } else if rng.gen_bool(0.05) {
+                    let facing = Face::ALL[rng.gen_range(2..6)];
+                    chunk.blocks[x][y + 1][z] = blocks
+                        .with_property(
+                            blocks.default_state(stone_stairs),
+                            BlockProperty::Facing,
+                            facing as u16,
+                        )
+                        .unwrap();
+                } else if rng.gen_bool(0.1) {
+                    chunk.blocks[x][y][z] = dirt;
+                    chunk.blocks[x][y + 1][z] = blocks.default_state(crops);
+                } else if rng.gen_bool(0.03) {
+                    chunk.blocks[x][y + 1][z] = torch_on_floor;
+                } else if rng.gen_bool(0.01) {
+                    chunk.blocks[x][y + 1][z] = chest;
+                } else if rng.gen_bool(0.1) {
+                    chunk.blocks[x][y][z] = sand;
+                }
+            }
+        }
+    }
+
+    packs.set_texture_path(dirt_texture, "dirt.png");
+    packs.set_texture_path(stone_texture, "stone.png");
+    packs.set_texture_path(white_ore_texture, "white_ore.png");
+    packs.set_texture_path(magma_texture, "magma.png");
+    packs.set_map_path(magma_texture, TextureMap::Emissive, "magma.png");
     block_materials
-        .set_block_texture(coords_texture, coords_image, &mut images, &mut materials)
+        .set_block_animation(magma_texture, Some(TextureAnimation::uniform(0.25)))
         .unwrap();
+    // Each connected glass tile is a frame of one strip, indexed by its connections
+    for (frame, &texture) in glass_textures.iter().enumerate() {
+        packs.set_texture_path(texture, "glass_connected.png");
+        block_materials
+            .set_block_frame(texture, frame as u32, &mut images, &mut materials)
+            .unwrap();
+    }
+    packs.set_texture_path(grass_side_texture, "grass_side_overlay.png");
+    packs.set_texture_path(grass_top_texture, "grass_top.png");
+    packs.set_texture_path(coords_texture, "coords.png");
+    packs.set_texture_path(sand_texture, "sand.png");
+    packs.set_texture_path(gravel_texture, "gravel.png");
+    packs.set_texture_path(torch_texture, "torch.png");
+    packs.set_texture_path(chest_texture, "chest.png");
+    packs.set_texture_path(log_top_texture, "log_top.png");
+    packs.set_texture_path(log_side_texture, "log_side.png");
+    packs.set_texture_path(leaves_texture, "leaves.png");
+    for (texture, path) in [(water_texture, "water.png"), (lava_texture, "lava.png")] {
+        packs.set_texture_path(texture, path);
+        block_materials
+            .set_block_animation(texture, Some(TextureAnimation::uniform(0.25)))
+            .unwrap();
+    }
+    packs.set_map_path(lava_texture, TextureMap::Emissive, "lava.png");
+    // Each stage of the crops' growth is a frame of one strip
+    for (frame, &texture) in crop_textures.iter().enumerate() {
+        packs.set_texture_path(texture, "crops.png");
+        block_materials
+            .set_block_frame(texture, frame as u32, &mut images, &mut materials)
+            .unwrap();
+    }
 
-    commands.spawn((
-        Chunk { data: chunk },
-        Collider::default(),
-        SpatialBundle::from_transform(Transform::from_translation(Vec3::new(0.0, 0.0, -10.0))),
-        ChunkNeedsMeshing,
-    ));
+    // Fluid sources above the surface, which flow down and spread once the world starts ticking
+    for (fluid, x, z) in [(water, 4, -12), (lava, 12, -4)] {
+        let position = IVec3::new(x, generator.surface_height(&biomes, x, z) + 3, z);
+        let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+        if let Some((chunk, ticks)) = chunks.get_mut(&coords) {
+            chunk.blocks[x][y][z] = blocks.default_state(fluid);
+            ticks.schedule(1, [x, y, z]);
+        }
+    }
+
+    for (coords, (chunk, ticks)) in chunks {
+        commands.spawn((
+            Chunk {
+                data: chunk,
+                ticks,
+                ..default()
+            },
+            Collider::default(),
+            SpatialBundle::from_transform(Transform::from_translation(
+                ChunkMap::chunk_translation(coords),
+            )),
+            ChunkNeedsMeshing,
+        ));
+    }
+    commands.insert_resource(generator);
+    commands.insert_resource(features);
 
     commands.spawn((
         RigidBody::Dynamic,
@@ -227,13 +919,4 @@ fn setup_environment(
         transform: Transform::from_translation(Vec3::new(0.0, 5.0, 0.0)),
         ..default()
     });
-
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            illuminance: 10000.0,
-            shadows_enabled: true,
-            ..default()
-        },
-        ..default()
-    });
 }
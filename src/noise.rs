@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+
+use crate::block::variant::position_hash;
+
+/// Smooth 2D value noise in `[0, 1]`, varying over roughly one unit
+pub fn value_noise(seed: u32, point: Vec2) -> f32 {
+    let cell = point.floor();
+    let t = point - cell;
+    // Smoothstep, so the noise has no creases at cell boundaries
+    let s = t * t * (3.0 - 2.0 * t);
+    let (x, z) = (cell.x as i32, cell.y as i32);
+    let corner =
+        |dx, dz| position_hash(IVec3::new(x + dx, 0, z + dz), seed) as f32 / u32::MAX as f32;
+    let near = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * s.x;
+    let far = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * s.x;
+    near + (far - near) * s.y
+}
+
+/// Sum octaves of [`value_noise`], each at twice the frequency and half the amplitude of the
+/// last, normalized to `[0, 1]`
+pub fn fractal_noise(seed: u32, point: Vec2, octaves: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut norm = 0.0;
+    let mut point = point;
+    for octave in 0..octaves {
+        total += amplitude * value_noise(seed.wrapping_add(octave), point);
+        norm += amplitude;
+        amplitude *= 0.5;
+        point *= 2.0;
+    }
+    if norm > 0.0 {
+        total / norm
+    } else {
+        0.0
+    }
+}
+
+/// Smooth 3D value noise in `[0, 1]`, varying over roughly one unit
+pub fn value_noise_3d(seed: u32, point: Vec3) -> f32 {
+    let cell = point.floor();
+    let t = point - cell;
+    let s = t * t * (3.0 - 2.0 * t);
+    let cell = cell.as_ivec3();
+    let corner =
+        |dx, dy, dz| position_hash(cell + IVec3::new(dx, dy, dz), seed) as f32 / u32::MAX as f32;
+    let plane = |dy| {
+        let near = corner(0, dy, 0) + (corner(1, dy, 0) - corner(0, dy, 0)) * s.x;
+        let far = corner(0, dy, 1) + (corner(1, dy, 1) - corner(0, dy, 1)) * s.x;
+        near + (far - near) * s.z
+    };
+    let (bottom, top) = (plane(0), plane(1));
+    bottom + (top - bottom) * s.y
+}
+
+/// Sum octaves of [`value_noise_3d`], each at twice the frequency and half the amplitude of the
+/// last, normalized to `[0, 1]`
+pub fn fractal_noise_3d(seed: u32, point: Vec3, octaves: u32) -> f32 {
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut norm = 0.0;
+    let mut point = point;
+    for octave in 0..octaves {
+        total += amplitude * value_noise_3d(seed.wrapping_add(octave), point);
+        norm += amplitude;
+        amplitude *= 0.5;
+        point *= 2.0;
+    }
+    if norm > 0.0 {
+        total / norm
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Vec2> {
+        points_3d().map(|point| Vec2::new(point.x, point.z))
+    }
+
+    fn points_3d() -> impl Iterator<Item = Vec3> {
+        (0..2000).map(|i| {
+            let i = i as f32;
+            Vec3::new(i * 0.173 - 150.0, i * 0.091 - 80.0, i * -0.237 + 200.0)
+        })
+    }
+
+    #[test]
+    fn noise_stays_in_unit_range() {
+        for seed in [0, 1, 0xDEAD_BEEF] {
+            for point in points() {
+                for octaves in 1..5 {
+                    let noise = [
+                        value_noise(seed, point),
+                        fractal_noise(seed, point, octaves),
+                    ];
+                    assert!(noise.iter().all(|n| (0.0..=1.0).contains(n)), "{noise:?}");
+                }
+            }
+        }
+        assert_eq!(fractal_noise(0, Vec2::ZERO, 0), 0.0);
+    }
+
+    #[test]
+    fn noise_is_continuous_across_cells() {
+        let epsilon = 1e-3;
+        for point in points() {
+            let cell = point.floor();
+            let (below, above) = (cell - epsilon, cell + epsilon);
+            assert!((value_noise(5, below) - value_noise(5, above)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn noise_depends_on_the_seed() {
+        assert!(points().any(|point| value_noise(1, point) != value_noise(2, point)));
+    }
+
+    #[test]
+    fn noise_3d_stays_in_unit_range() {
+        for seed in [0, 1, 0xDEAD_BEEF] {
+            for point in points_3d() {
+                for octaves in 1..5 {
+                    let noise = [
+                        value_noise_3d(seed, point),
+                        fractal_noise_3d(seed, point, octaves),
+                    ];
+                    assert!(noise.iter().all(|n| (0.0..=1.0).contains(n)), "{noise:?}");
+                }
+            }
+        }
+        assert_eq!(fractal_noise_3d(0, Vec3::ZERO, 0), 0.0);
+    }
+
+    #[test]
+    fn noise_3d_is_continuous_across_cells() {
+        let epsilon = 1e-3;
+        for point in points_3d() {
+            let cell = point.floor();
+            let (below, above) = (cell - epsilon, cell + epsilon);
+            assert!((value_noise_3d(5, below) - value_noise_3d(5, above)).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn noise_3d_depends_on_the_seed() {
+        assert!(points_3d().any(|point| value_noise_3d(1, point) != value_noise_3d(2, point)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{asset::AssetIo, prelude::*};
+
+use crate::{
+    block::{
+        model::BlockModel,
+        texture::{BlockMaterials, BlockTextureId, TextureMap},
+        BlockModelId, Blocks,
+    },
+    chunk::{mesher::ChunkNeedsMeshing, Chunk},
+};
+
+/// The asset directory containing resource packs, each a directory mirroring the asset folder
+pub const PACK_DIRECTORY: &str = "packs";
+
+/// A stack of resource packs overriding the textures and block models in the asset folder.
+///
+/// Block textures and models are registered by their path relative to the asset folder, and are
+/// loaded from the highest priority enabled pack containing that path, falling back to the asset
+/// folder itself. Changing the pack order re-resolves every registered path.
+///
+/// Only packs unpacked into directories under [`PACK_DIRECTORY`] are supported, since their files
+/// are loaded through the asset server like any other asset. Reading zipped packs would need an
+/// asset reader that decompresses archives, which is not implemented.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ResourcePacks {
+    /// The names of the enabled packs, highest priority first
+    order: Vec<String>,
+    textures: BTreeMap<(BlockTextureId, TextureMap), PathBuf>,
+    models: BTreeMap<BlockModelId, PathBuf>,
+}
+
+impl ResourcePacks {
+    /// Get the names of the enabled packs, highest priority first
+    #[inline]
+    pub fn order(&self) -> &[String] {
+        &self.order
+    }
+
+    /// Set the enabled packs, highest priority first
+    #[inline]
+    pub fn set_order(&mut self, order: Vec<String>) {
+        self.order = order;
+    }
+
+    /// Load a block texture's image from `path`, returning its previous path
+    #[inline]
+    pub fn set_texture_path(
+        &mut self,
+        texture: BlockTextureId,
+        path: impl Into<PathBuf>,
+    ) -> Option<PathBuf> {
+        self.set_map_path(texture, TextureMap::BaseColor, path)
+    }
+
+    /// Load one map of a block texture from `path`, returning its previous path
+    #[inline]
+    pub fn set_map_path(
+        &mut self,
+        texture: BlockTextureId,
+        map: TextureMap,
+        path: impl Into<PathBuf>,
+    ) -> Option<PathBuf> {
+        self.textures.insert((texture, map), path.into())
+    }
+
+    /// Load a block model from `path`, returning its previous path
+    #[inline]
+    pub fn set_model_path(
+        &mut self,
+        model: BlockModelId,
+        path: impl Into<PathBuf>,
+    ) -> Option<PathBuf> {
+        self.models.insert(model, path.into())
+    }
+
+    /// Stop loading every map of a removed block texture
+    #[inline]
+    pub fn remove_texture(&mut self, texture: BlockTextureId) {
+        self.textures.retain(|&(t, _), _| t != texture);
+    }
+
+    /// Get the asset path to load `path` from, given the enabled packs
+    pub fn resolve(&self, path: &Path, asset_io: &dyn AssetIo) -> PathBuf {
+        self.order
+            .iter()
+            .map(|pack| Path::new(PACK_DIRECTORY).join(pack).join(path))
+            .find(|candidate| asset_io.is_file(candidate))
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// List the names of the packs unpacked in the asset folder, sorted by name, ignoring any
+    /// other files such as zipped packs
+    pub fn available(asset_io: &dyn AssetIo) -> Vec<String> {
+        let mut packs: Vec<String> = asset_io
+            .read_directory(Path::new(PACK_DIRECTORY))
+            .into_iter()
+            .flatten()
+            .filter(|path| asset_io.is_dir(path))
+            .filter_map(|path| Some(path.file_name()?.to_str()?.to_owned()))
+            .collect();
+        packs.sort();
+        packs
+    }
+}
+
+/// Re-resolve every registered texture and model whenever the resource packs change
+#[allow(clippy::too_many_arguments)]
+pub fn apply_resource_packs(
+    mut commands: Commands,
+    packs: Res<ResourcePacks>,
+    asset_server: Res<AssetServer>,
+    mut block_materials: ResMut<BlockMaterials>,
+    mut blocks: ResMut<Blocks>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    models: Res<Assets<BlockModel>>,
+    chunks: Query<Entity, With<Chunk>>,
+) {
+    if !packs.is_changed() {
+        return;
+    }
+    let asset_io = asset_server.asset_io();
+    for (&(texture, map), path) in &packs.textures {
+        let image = asset_server.load(packs.resolve(path, asset_io));
+        if let Err(err) =
+            block_materials.set_block_map(texture, map, image, &mut images, &mut materials)
+        {
+            error!(
+                "failed to import {:?} of block texture {:?}: {}",
+                map, texture, err
+            );
+        }
+    }
+    let mut remesh = false;
+    for (&model, path) in &packs.models {
+        let asset = asset_server.load(packs.resolve(path, asset_io));
+        // Models which are already loaded take effect immediately, rather than on their next load
+        remesh |= models.contains(&asset);
+        if blocks.set_model_asset(model, asset, &models).is_err() {
+            error!(
+                "cannot load {:?} into unknown block model {:?}",
+                path, model
+            );
+        }
+    }
+    if remesh {
+        for chunk in chunks.iter() {
+            commands.entity(chunk).insert(ChunkNeedsMeshing);
+        }
+    }
+}
@@ -1,12 +1,24 @@
 use std::f32::consts::PI;
 
 use bevy::{
-    input::mouse::MouseMotion,
+    input::mouse::{MouseMotion, MouseWheel},
     prelude::{shape::Cube, *},
     window::{CursorGrabMode, PrimaryWindow, WindowMode},
 };
 use bevy_rapier3d::prelude::*;
 
+use crate::{
+    block::{face::Face, BlockId, Blocks},
+    chunk::{mesher::ChunkNeedsMeshing, BlockBroken, BlockChanged, Chunk, ChunkData},
+    item::{Inventory, ITEM_GROUP},
+};
+
+/// The collision group of the player
+pub const PLAYER_GROUP: Group = Group::GROUP_3;
+
+/// How far away, in blocks, the player can reach
+pub const PLAYER_REACH: f32 = 8.0;
+
 #[derive(Component)]
 pub struct PlayerCamera;
 
@@ -20,9 +32,15 @@ pub fn setup_player(
 ) {
     commands.spawn((
         Camera3dBundle::default(),
-        KinematicCharacterController::default(),
+        FogSettings::default(),
+        KinematicCharacterController {
+            filter_groups: Some(CollisionGroups::new(PLAYER_GROUP, !ITEM_GROUP)),
+            ..default()
+        },
         Collider::capsule_y(1.0, 0.5),
+        CollisionGroups::new(PLAYER_GROUP, Group::ALL),
         PlayerCamera,
+        Inventory::default(),
     ));
 
     commands.spawn((
@@ -41,6 +59,7 @@ pub fn setup_player(
     ));
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn player_control(
     key: Res<Input<KeyCode>>,
     btn: Res<Input<MouseButton>>,
@@ -86,7 +105,13 @@ pub fn player_control(
 
     let mut highlight = highlight.get_single_mut().unwrap();
     let ray_origin = player_position.translation + 0.5 * player_position.forward();
-    match rapier_context.cast_ray(ray_origin, player_position.forward(), 8.0, false, default()) {
+    match rapier_context.cast_ray(
+        ray_origin,
+        player_position.forward(),
+        PLAYER_REACH,
+        false,
+        default(),
+    ) {
         Some((_entity, toi)) if toi > 0.5 => {
             highlight.0.translation = ray_origin + toi * player_position.forward();
             if btn.just_pressed(MouseButton::Left) {
@@ -139,3 +164,170 @@ pub fn player_control(
         }
     }
 }
+
+/// Cast a ray along the player's view to the first chunk within reach
+fn cast_to_chunk(
+    rapier_context: &RapierContext,
+    player_position: &Transform,
+    is_chunk: &impl Fn(Entity) -> bool,
+) -> Option<(Entity, RayIntersection)> {
+    let ray_origin = player_position.translation + 0.5 * player_position.forward();
+    rapier_context.cast_ray_and_get_normal(
+        ray_origin,
+        player_position.forward(),
+        PLAYER_REACH,
+        false,
+        QueryFilter::default().predicate(is_chunk),
+    )
+}
+
+/// Break the block the player is looking at when the left mouse button is clicked
+#[allow(clippy::too_many_arguments)]
+pub fn player_break_blocks(
+    mut commands: Commands,
+    btn: Res<Input<MouseButton>>,
+    rapier_context: Res<RapierContext>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    player: Query<&Transform, With<PlayerCamera>>,
+    mut chunks: Query<(&mut Chunk, &GlobalTransform)>,
+    mut broken: EventWriter<BlockBroken>,
+    mut changed: EventWriter<BlockChanged>,
+) {
+    // Clicking an unlocked window only grabs the cursor
+    let window = window.get_single().unwrap();
+    if !btn.just_pressed(MouseButton::Left) || window.cursor.grab_mode != CursorGrabMode::Locked {
+        return;
+    }
+
+    let player_position = player.get_single().unwrap();
+    let is_chunk = |entity| chunks.contains(entity);
+    let Some((chunk_entity, hit)) = cast_to_chunk(&rapier_context, player_position, &is_chunk)
+    else {
+        return;
+    };
+
+    let (mut chunk, chunk_transform) = chunks.get_mut(chunk_entity).unwrap();
+    let inside = hit.point - 0.5 * hit.normal;
+    let local = chunk_transform.affine().inverse().transform_point3(inside);
+    let Some(index) = ChunkData::block_index(local) else {
+        return;
+    };
+    let block = std::mem::take(&mut chunk.data.blocks[index[0]][index[1]][index[2]]);
+    if block == default() {
+        return;
+    }
+    commands.entity(chunk_entity).insert(ChunkNeedsMeshing);
+    let position = chunk_transform.transform_point(ChunkData::block_center(index));
+    broken.send(BlockBroken {
+        chunk: chunk_entity,
+        index,
+        block,
+        position,
+    });
+    changed.send(BlockChanged {
+        position: position.round().as_ivec3(),
+        old: block,
+        new: default(),
+    });
+}
+
+/// Place the selected inventory block against the block the player is looking at when the right
+/// mouse button is clicked
+#[allow(clippy::too_many_arguments)]
+pub fn player_place_blocks(
+    mut commands: Commands,
+    btn: Res<Input<MouseButton>>,
+    rapier_context: Res<RapierContext>,
+    blocks: Res<Blocks>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut player: Query<(Entity, &Transform, &mut Inventory), With<PlayerCamera>>,
+    mut chunks: Query<(&mut Chunk, &GlobalTransform)>,
+    mut changed: EventWriter<BlockChanged>,
+) {
+    let window = window.get_single().unwrap();
+    if !btn.just_pressed(MouseButton::Right) || window.cursor.grab_mode != CursorGrabMode::Locked {
+        return;
+    }
+
+    let (player_entity, player_position, mut inventory) = player.get_single_mut().unwrap();
+    let Some(block_type) = inventory
+        .selected_block()
+        .and_then(|block| blocks.block_type(block))
+    else {
+        return;
+    };
+    let is_chunk = |entity| chunks.contains(entity);
+    let Some((chunk_entity, hit)) = cast_to_chunk(&rapier_context, player_position, &is_chunk)
+    else {
+        return;
+    };
+
+    let (mut chunk, chunk_transform) = chunks.get_mut(chunk_entity).unwrap();
+    let to_local = chunk_transform.affine().inverse();
+    let outside = hit.point + 0.5 * hit.normal;
+    let Some(index) = ChunkData::block_index(to_local.transform_point3(outside)) else {
+        return;
+    };
+    let target = &mut chunk.data.blocks[index[0]][index[1]][index[2]];
+    if *target != BlockId::default() {
+        return;
+    }
+
+    // Don't place blocks inside the player
+    let center = chunk_transform.transform_point(ChunkData::block_center(index));
+    let is_player = |entity| entity == player_entity;
+    if rapier_context
+        .intersection_with_shape(
+            center,
+            Quat::IDENTITY,
+            &Collider::cuboid(0.5, 0.5, 0.5),
+            QueryFilter::default().predicate(&is_player),
+        )
+        .is_some()
+    {
+        return;
+    }
+
+    let clicked = Face::from_direction(to_local.transform_vector3(hit.normal));
+    *target = blocks.placement_state(block_type, clicked, player_position.forward());
+    inventory.take_selected();
+    commands.entity(chunk_entity).insert(ChunkNeedsMeshing);
+    changed.send(BlockChanged {
+        position: center.round().as_ivec3(),
+        old: BlockId::default(),
+        new: *target,
+    });
+}
+
+/// Select inventory stacks with the number keys and the mouse wheel
+pub fn player_select_item(
+    key: Res<Input<KeyCode>>,
+    mut wheel_evr: EventReader<MouseWheel>,
+    mut player: Query<&mut Inventory, With<PlayerCamera>>,
+) {
+    const SLOT_KEYS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let mut inventory = player.get_single_mut().unwrap();
+    for (slot, slot_key) in SLOT_KEYS.iter().enumerate() {
+        if key.just_pressed(*slot_key) {
+            inventory.selected = slot;
+        }
+    }
+    let stacks = inventory.items.len().max(1);
+    for ev in wheel_evr.iter() {
+        if ev.y > 0.0 {
+            inventory.selected = (inventory.selected + stacks - 1) % stacks;
+        } else if ev.y < 0.0 {
+            inventory.selected = (inventory.selected + 1) % stacks;
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use std::f32::consts::TAU;
+
+use bevy::{
+    pbr::{NotShadowCaster, NotShadowReceiver},
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+};
+use fxhash::FxHashMap;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    block::{
+        texture::{BlockMaterials, SheetId},
+        Blocks,
+    },
+    chunk::{map::ChunkMap, mesher::MeshLight, Chunk, ViewDistance, CHUNK_SIZE},
+    player::PlayerCamera,
+};
+
+/// The illuminance, in lux, of the sun at noon
+pub const SUN_ILLUMINANCE: f32 = 10000.0;
+/// The illuminance, in lux, of the moon at midnight
+pub const MOON_ILLUMINANCE: f32 = 200.0;
+/// The voxel sky light at midnight, relative to noon
+pub const NIGHT_SKY_LIGHT: f32 = 0.25;
+/// The brightness of blocks hidden from the sky, whatever the time of day
+pub const CAVE_LIGHT: f32 = 0.1;
+/// How far, in radians, the sun's path is tilted from passing directly overhead
+const SUN_TILT: f32 = 0.4;
+
+/// The radius of the sky dome, which must lie within the camera's far plane
+pub const SKY_RADIUS: f32 = 500.0;
+/// The distance of the sun and moon discs, in front of the stars
+const DISC_DISTANCE: f32 = 0.8 * SKY_RADIUS;
+/// The distance of the stars, in front of the dome
+const STAR_DISTANCE: f32 = 0.9 * SKY_RADIUS;
+/// The angular radius, in radians, of the sun and moon discs
+const DISC_RADIUS: f32 = 0.05;
+/// The number of stars in the star field
+const STAR_COUNT: usize = 800;
+
+/// The fraction of the view distance at which fog starts to hide the world
+const FOG_START: f32 = 0.6;
+/// The fraction of the view distance the world can be seen at midnight
+const NIGHT_VISIBILITY: f32 = 0.6;
+/// How far, in blocks, the camera can see inside a fluid
+const FLUID_VISIBILITY: f32 = 12.0;
+
+/// The time of day, advancing in a cycle of days and nights
+#[derive(Resource, Debug, Copy, Clone, PartialEq)]
+pub struct TimeOfDay {
+    /// The fraction of the current day elapsed, where `0.0` is midnight, `0.25` is sunrise and
+    /// `0.5` is noon
+    pub time: f32,
+    /// The length of a day, in seconds
+    pub day_length: f32,
+    /// How many times faster than normal the day advances, pausing the cycle if zero
+    pub speed: f32,
+}
+
+impl Default for TimeOfDay {
+    #[inline]
+    fn default() -> Self {
+        TimeOfDay {
+            time: 0.3,
+            day_length: 600.0,
+            speed: 1.0,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Get the unit vector pointing from the world towards the sun
+    #[inline]
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sky_rotation() * Vec3::X
+    }
+
+    /// Get the rotation of the celestial sphere, carrying the sun, moon and stars across the sky
+    #[inline]
+    pub fn sky_rotation(&self) -> Quat {
+        Quat::from_rotation_x(SUN_TILT) * Quat::from_rotation_z((self.time - 0.25) * TAU)
+    }
+
+    /// Get the unit vector pointing from the world towards the moon, opposite the sun
+    #[inline]
+    pub fn moon_direction(&self) -> Vec3 {
+        -self.sun_direction()
+    }
+
+    /// Get how much of the day's light is shining, from `0.0` at night to `1.0` once the sun is
+    /// well above the horizon, fading through twilight
+    #[inline]
+    pub fn daylight(&self) -> f32 {
+        let elevation = self.sun_direction().y;
+        ((elevation + 0.1) / 0.3).clamp(0.0, 1.0)
+    }
+
+    /// Get how strongly the sky is colored by a sunrise or sunset, peaking as the sun crosses the
+    /// horizon
+    #[inline]
+    pub fn twilight(&self) -> f32 {
+        let elevation = self.sun_direction().y;
+        (1.0 - elevation.abs() / 0.25).max(0.0)
+    }
+}
+
+/// The colors of the sky and its ambient light through the day
+#[derive(Resource, Debug, Copy, Clone, PartialEq)]
+pub struct SkyColors {
+    /// The color of the sky at the horizon at noon, which is also the clear color
+    pub day: Color,
+    /// The color of the sky at the horizon at midnight
+    pub night: Color,
+    /// The color of the sky overhead at noon
+    pub day_zenith: Color,
+    /// The color of the sky overhead at midnight
+    pub night_zenith: Color,
+    /// The color blended into the sky at sunrise and sunset, strongest around the sun
+    pub twilight: Color,
+    pub sun: Color,
+    pub moon: Color,
+    pub stars: Color,
+    pub day_ambient: Color,
+    pub night_ambient: Color,
+    /// The brightness of the ambient light at noon
+    pub day_ambient_brightness: f32,
+    /// The brightness of the ambient light at midnight
+    pub night_ambient_brightness: f32,
+}
+
+impl Default for SkyColors {
+    #[inline]
+    fn default() -> Self {
+        SkyColors {
+            day: Color::ALICE_BLUE,
+            night: Color::rgb(0.01, 0.01, 0.04),
+            day_zenith: Color::rgb(0.3, 0.55, 0.95),
+            night_zenith: Color::rgb(0.0, 0.0, 0.01),
+            twilight: Color::rgb(0.95, 0.5, 0.25),
+            sun: Color::rgb(1.0, 0.95, 0.8),
+            moon: Color::rgb(0.85, 0.9, 1.0),
+            stars: Color::WHITE,
+            day_ambient: Color::WHITE,
+            night_ambient: Color::rgb(0.5, 0.6, 1.0),
+            day_ambient_brightness: 0.05,
+            night_ambient_brightness: 0.01,
+        }
+    }
+}
+
+impl SkyColors {
+    /// Get the color of the sky at the horizon at a time of day
+    pub fn sky_color(&self, time: &TimeOfDay) -> Color {
+        let sky = lerp_color(self.night, self.day, time.daylight());
+        lerp_color(sky, self.twilight, 0.5 * time.twilight())
+    }
+
+    /// Get the color of the sky in a direction at a time of day, fading from the horizon to the
+    /// zenith and glowing around the sun at sunrise and sunset
+    pub fn sky_color_towards(&self, time: &TimeOfDay, direction: Vec3) -> Color {
+        let horizon = self.sky_color(time);
+        let zenith = lerp_color(self.night_zenith, self.day_zenith, time.daylight());
+        let sky = lerp_color(horizon, zenith, direction.y.max(0.0).sqrt());
+        let glow = time.twilight() * direction.dot(time.sun_direction()).max(0.0).powi(8);
+        lerp_color(sky, self.twilight, glow)
+    }
+}
+
+/// Linearly interpolate between two colors in linear space
+#[inline]
+pub fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let a = Vec4::from(a.as_linear_rgba_f32());
+    let b = Vec4::from(b.as_linear_rgba_f32());
+    let [r, g, b, a] = a.lerp(b, t).to_array();
+    Color::rgba_linear(r, g, b, a)
+}
+
+/// The brightness of blocks lit by the sky, from `0.0` to `1.0`, which chunks are shaded by
+/// through their [`ChunkMaterials`]
+#[derive(Resource, Debug, Copy, Clone, PartialEq)]
+pub struct SkyLight(pub f32);
+
+impl Default for SkyLight {
+    #[inline]
+    fn default() -> Self {
+        SkyLight(1.0)
+    }
+}
+
+/// The materials chunk meshes are drawn with: a copy of each texture sheet's material for each
+/// [`MeshLight`], its base color scaled by the brightness of that light
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ChunkMaterials {
+    materials: FxHashMap<(SheetId, MeshLight), Handle<StandardMaterial>>,
+}
+
+impl ChunkMaterials {
+    /// Get the material of a sheet under a light, creating it if it does not exist
+    pub fn get_or_insert(
+        &mut self,
+        sheet: SheetId,
+        light: MeshLight,
+        materials: &BlockMaterials,
+        standard_materials: &mut Assets<StandardMaterial>,
+        sky_light: &SkyLight,
+    ) -> Handle<StandardMaterial> {
+        self.materials
+            .entry((sheet, light))
+            .or_insert_with(|| {
+                let template = materials.get_sheet_material(sheet);
+                let material = lit_material(standard_materials.get(&template), light, sky_light);
+                standard_materials.add(material)
+            })
+            .clone()
+    }
+}
+
+/// Get the brightness of a light
+#[inline]
+fn light_level(light: MeshLight, sky_light: &SkyLight) -> f32 {
+    match light {
+        MeshLight::Sky => sky_light.0.max(CAVE_LIGHT),
+        MeshLight::Cave => CAVE_LIGHT,
+    }
+}
+
+/// Copy a sheet's material, scaling its base color by the brightness of a light
+fn lit_material(
+    template: Option<&StandardMaterial>,
+    light: MeshLight,
+    sky_light: &SkyLight,
+) -> StandardMaterial {
+    let mut material = template.cloned().unwrap_or_default();
+    let [r, g, b, a] = material.base_color.as_linear_rgba_f32();
+    let level = light_level(light, sky_light);
+    material.base_color = Color::rgba_linear(r * level, g * level, b * level, a);
+    material
+}
+
+/// Rescale the sky-lit chunk materials whenever the sky light changes, and copy sheet materials
+/// into the chunk materials again when they change
+pub fn update_chunk_materials(
+    sky_light: Res<SkyLight>,
+    materials: Res<BlockMaterials>,
+    chunk_materials: Res<ChunkMaterials>,
+    mut standard_materials: ResMut<Assets<StandardMaterial>>,
+    mut events: EventReader<AssetEvent<StandardMaterial>>,
+) {
+    let mut changed = Vec::new();
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            changed.push(handle.id());
+        }
+    }
+    for (&(sheet, light), handle) in &chunk_materials.materials {
+        let template = materials.get_sheet_material(sheet);
+        if changed.contains(&template.id()) {
+            let material = lit_material(standard_materials.get(&template), light, &sky_light);
+            standard_materials.set_untracked(handle, material);
+        } else if light == MeshLight::Sky && sky_light.is_changed() {
+            let level = light_level(light, &sky_light);
+            let base_color = standard_materials
+                .get(&template)
+                .map_or(Color::WHITE, |template| template.base_color);
+            let [r, g, b, a] = base_color.as_linear_rgba_f32();
+            if let Some(material) = standard_materials.get_mut(handle) {
+                material.base_color = Color::rgba_linear(r * level, g * level, b * level, a);
+            }
+        }
+    }
+}
+
+/// The directional light cast by the sun
+#[derive(Component)]
+pub struct Sun;
+
+/// The directional light cast by the moon
+#[derive(Component)]
+pub struct Moon;
+
+/// The sky dome, sun and moon discs and stars, which follow the camera
+#[derive(Component)]
+pub struct Sky;
+
+/// The gradient dome behind everything else in the sky
+#[derive(Component)]
+pub struct SkyDome;
+
+/// The star field, which rotates with the sky
+#[derive(Component)]
+pub struct Stars;
+
+/// A disc showing the sun or moon in the direction of its light
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SkyDisc {
+    Sun,
+    Moon,
+}
+
+/// Spawn the sun and moon lights, and the sky rendered behind the world
+pub fn setup_sky(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        Sun,
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: SUN_ILLUMINANCE,
+                shadows_enabled: true,
+                ..default()
+            },
+            ..default()
+        },
+    ));
+    commands.spawn((
+        Moon,
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: 0.0,
+                color: Color::rgb(0.7, 0.8, 1.0),
+                ..default()
+            },
+            ..default()
+        },
+    ));
+
+    let sky_material = |materials: &mut Assets<StandardMaterial>, alpha_mode| {
+        materials.add(StandardMaterial {
+            unlit: true,
+            fog_enabled: false,
+            cull_mode: None,
+            alpha_mode,
+            ..default()
+        })
+    };
+    let mut dome = Mesh::from(shape::UVSphere {
+        radius: SKY_RADIUS,
+        sectors: 32,
+        stacks: 16,
+    });
+    let vertices = dome.count_vertices();
+    dome.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1.0; 4]; vertices]);
+    let disc = meshes.add(
+        shape::Circle {
+            radius: DISC_RADIUS * DISC_DISTANCE,
+            vertices: 32,
+        }
+        .into(),
+    );
+    commands
+        .spawn((Sky, SpatialBundle::default()))
+        .with_children(|sky| {
+            sky.spawn((
+                SkyDome,
+                PbrBundle {
+                    mesh: meshes.add(dome),
+                    material: sky_material(&mut materials, AlphaMode::Opaque),
+                    ..default()
+                },
+                NotShadowCaster,
+                NotShadowReceiver,
+            ));
+            sky.spawn((
+                Stars,
+                PbrBundle {
+                    mesh: meshes.add(star_mesh(STAR_COUNT)),
+                    material: sky_material(&mut materials, AlphaMode::Add),
+                    ..default()
+                },
+                NotShadowCaster,
+                NotShadowReceiver,
+            ));
+            for disc_kind in [SkyDisc::Sun, SkyDisc::Moon] {
+                sky.spawn((
+                    disc_kind,
+                    PbrBundle {
+                        mesh: disc.clone(),
+                        material: sky_material(&mut materials, AlphaMode::Opaque),
+                        ..default()
+                    },
+                    NotShadowCaster,
+                    NotShadowReceiver,
+                ));
+            }
+        });
+}
+
+/// Build a mesh of small quads scattered over a sphere, each facing its center, with randomly
+/// dimmed vertex colors
+fn star_mesh(count: usize) -> Mesh {
+    // A fixed seed keeps the constellations the same every time
+    let mut rng = StdRng::seed_from_u64(0x57A25);
+    let mut vertices = Vec::with_capacity(4 * count);
+    let mut colors = Vec::with_capacity(4 * count);
+    let mut triangles = Vec::with_capacity(6 * count);
+    for _ in 0..count {
+        let direction = loop {
+            let v = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            );
+            let length = v.length();
+            if length > 0.01 && length <= 1.0 {
+                break v / length;
+            }
+        };
+        let size = rng.gen_range(0.5..1.5);
+        let right = direction.any_orthonormal_vector() * size;
+        let up = direction.cross(right);
+        let center = direction * STAR_DISTANCE;
+        let v = vertices.len() as u16;
+        vertices.extend([
+            center - right - up,
+            center + right - up,
+            center + right + up,
+            center - right + up,
+        ]);
+        let brightness = rng.gen_range(0.3..1.0);
+        colors.extend([[brightness, brightness, brightness, 1.0]; 4]);
+        triangles.extend([0, 1, 2, 0, 2, 3].map(|t| v + t));
+    }
+    let normals: Vec<_> = vertices
+        .iter()
+        .map(|v| (-v.normalize()).to_array())
+        .collect();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vertices.iter().map(|v| v.to_array()).collect::<Vec<_>>(),
+    );
+    mesh.set_indices(Some(Indices::U16(triangles)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0f32; 2]; vertices.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+/// Advance the time of day
+pub fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.speed == 0.0 || time_of_day.day_length <= 0.0 {
+        return;
+    }
+    let delta = time.delta_seconds() * time_of_day.speed / time_of_day.day_length;
+    time_of_day.time = (time_of_day.time + delta).rem_euclid(1.0);
+}
+
+/// Move the sun and moon, and color the sky, ambient light and voxel sky light
+#[allow(clippy::type_complexity)]
+pub fn update_sky_lighting(
+    time: Res<TimeOfDay>,
+    colors: Res<SkyColors>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient: ResMut<AmbientLight>,
+    mut sky_light: ResMut<SkyLight>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), (With<Sun>, Without<Moon>)>,
+    mut moon: Query<(&mut Transform, &mut DirectionalLight), (With<Moon>, Without<Sun>)>,
+) {
+    if !time.is_changed() && !colors.is_changed() {
+        return;
+    }
+    let daylight = time.daylight();
+    for (mut transform, mut light) in sun.iter_mut() {
+        *transform = Transform::IDENTITY.looking_to(-time.sun_direction(), Vec3::Z);
+        light.illuminance = SUN_ILLUMINANCE * daylight;
+    }
+    for (mut transform, mut light) in moon.iter_mut() {
+        *transform = Transform::IDENTITY.looking_to(-time.moon_direction(), Vec3::Z);
+        light.illuminance = MOON_ILLUMINANCE * (1.0 - daylight);
+    }
+
+    clear_color.0 = colors.sky_color(&time);
+    ambient.color = lerp_color(colors.night_ambient, colors.day_ambient, daylight);
+    ambient.brightness = colors.night_ambient_brightness
+        + (colors.day_ambient_brightness - colors.night_ambient_brightness) * daylight;
+
+    let level = NIGHT_SKY_LIGHT + (1.0 - NIGHT_SKY_LIGHT) * daylight;
+    sky_light.set_if_neq(SkyLight(level));
+}
+
+/// Keep the sky centered on the camera, so that it appears infinitely far away
+pub fn follow_camera_with_sky(
+    camera: Query<&Transform, (With<PlayerCamera>, Without<Sky>)>,
+    mut sky: Query<&mut Transform, With<Sky>>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    for mut transform in sky.iter_mut() {
+        transform.translation = camera.translation;
+    }
+}
+
+/// Recolor the sky dome, and move the sun, moon and stars, as the time of day changes
+#[allow(clippy::type_complexity)]
+pub fn update_sky_dome(
+    time: Res<TimeOfDay>,
+    colors: Res<SkyColors>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    dome: Query<&Handle<Mesh>, With<SkyDome>>,
+    mut stars: Query<(&mut Transform, &Handle<StandardMaterial>), (With<Stars>, Without<SkyDisc>)>,
+    mut discs: Query<
+        (
+            &SkyDisc,
+            &mut Transform,
+            &mut Visibility,
+            &Handle<StandardMaterial>,
+        ),
+        Without<Stars>,
+    >,
+) {
+    if !time.is_changed() && !colors.is_changed() {
+        return;
+    }
+    for dome in dome.iter() {
+        let Some(mesh) = meshes.get_mut(dome) else {
+            continue;
+        };
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            continue;
+        };
+        let sky_colors: Vec<_> = positions
+            .iter()
+            .map(|&position| {
+                let direction = Vec3::from(position).normalize_or_zero();
+                colors
+                    .sky_color_towards(&time, direction)
+                    .as_linear_rgba_f32()
+            })
+            .collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, sky_colors);
+    }
+
+    // Stars fade in as the sky darkens
+    let night = 1.0 - time.daylight();
+    for (mut transform, material) in stars.iter_mut() {
+        transform.rotation = time.sky_rotation();
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color = lerp_color(Color::BLACK, colors.stars, night);
+        }
+    }
+
+    for (disc, mut transform, mut visibility, material) in discs.iter_mut() {
+        let (direction, color) = match disc {
+            SkyDisc::Sun => (time.sun_direction(), colors.sun),
+            SkyDisc::Moon => (time.moon_direction(), colors.moon),
+        };
+        *transform =
+            Transform::from_translation(direction * DISC_DISTANCE).looking_to(direction, Vec3::Z);
+        // Hide discs well below the horizon, where the world may not cover them
+        *visibility = if direction.y < -DISC_RADIUS {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color = color;
+        }
+    }
+}
+
+/// Fade the world into the sky at the edge of the view distance, or into the color of the fluid the
+/// camera is inside.
+///
+/// The fog is only rewritten when the fluid around the camera, the time of day, the sky's colors or
+/// the view distance change.
+#[allow(clippy::too_many_arguments)]
+pub fn update_fog(
+    time: Res<TimeOfDay>,
+    colors: Res<SkyColors>,
+    view_distance: Res<ViewDistance>,
+    blocks: Res<Blocks>,
+    map: Res<ChunkMap>,
+    chunks: Query<&Chunk>,
+    mut camera: Query<(&Transform, &mut FogSettings), With<PlayerCamera>>,
+    mut last_fluid_fog: Local<Option<Option<[u8; 3]>>>,
+) {
+    let Ok((camera, mut fog)) = camera.get_single_mut() else {
+        return;
+    };
+    let (coords, [x, y, z]) = ChunkMap::chunk_coords(camera.translation.round().as_ivec3());
+    let fluid_fog = map
+        .get(coords)
+        .and_then(|chunk| chunks.get(chunk).ok())
+        .and_then(|chunk| blocks.get_meshing_data(chunk.data.blocks[x][y][z]).fog);
+    if *last_fluid_fog == Some(fluid_fog)
+        && !time.is_changed()
+        && !colors.is_changed()
+        && !view_distance.is_changed()
+    {
+        return;
+    }
+    *last_fluid_fog = Some(fluid_fog);
+
+    let daylight = time.daylight();
+    *fog = match fluid_fog {
+        Some([r, g, b]) => FogSettings {
+            // Fluids are darker at night, but never pitch black
+            color: lerp_color(Color::BLACK, Color::rgb_u8(r, g, b), daylight.max(0.2)),
+            directional_light_color: Color::NONE,
+            falloff: FogFalloff::from_visibility(FLUID_VISIBILITY),
+            ..default()
+        },
+        None => {
+            let visibility = NIGHT_VISIBILITY + (1.0 - NIGHT_VISIBILITY) * daylight;
+            let end = (view_distance.0 as i32 * CHUNK_SIZE) as f32 * visibility;
+            FogSettings {
+                color: colors.sky_color(&time),
+                directional_light_color: colors.sun.with_a(0.5 * daylight),
+                directional_light_exponent: 30.0,
+                falloff: FogFalloff::Linear {
+                    start: FOG_START * end,
+                    end,
+                },
+            }
+        }
+    };
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeSet;
+
+use bevy::prelude::*;
+use fxhash::FxHashMap;
+use rand::{rngs::ThreadRng, Rng};
+
+use crate::{
+    block::{BlockId, BlockTypeId, Blocks},
+    chunk::{
+        map::{ChunkMap, WorldBlocks},
+        CHUNK_SIZE,
+    },
+};
+
+/// The number of world ticks per second
+pub const TICKS_PER_SECOND: f32 = 20.0;
+
+/// The number of ticks since the world started
+#[derive(Resource, Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct WorldTick(pub u64);
+
+/// The number of random blocks in each loaded chunk which are ticked every world tick
+#[derive(Resource, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct RandomTickSpeed(pub u32);
+
+impl Default for RandomTickSpeed {
+    #[inline]
+    fn default() -> Self {
+        RandomTickSpeed(3)
+    }
+}
+
+/// The length of each update saved by [`ScheduledTicks::to_bytes`]: its delay and block index
+const SAVED_TICK_LEN: usize = 11;
+
+/// The block updates scheduled in a chunk, which are saved and loaded along with its blocks
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct ScheduledTicks {
+    /// The tick each update is due on, and the index of the block to update
+    ticks: BTreeSet<(u64, [u8; 3])>,
+}
+
+impl ScheduledTicks {
+    /// Update the block at `index` on the given world tick
+    #[inline]
+    pub fn schedule(&mut self, tick: u64, index: [usize; 3]) {
+        self.ticks.insert((tick, index.map(|i| i as u8)));
+    }
+
+    /// Get whether no updates are scheduled
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    /// Remove and return the indices of the blocks due to be updated by the given world tick
+    pub fn take_due(&mut self, tick: u64) -> Vec<[usize; 3]> {
+        let mut due = Vec::new();
+        while let Some(&(due_tick, index)) = self.ticks.first() {
+            if due_tick > tick {
+                break;
+            }
+            self.ticks.pop_first();
+            due.push(index.map(|i| i as usize));
+        }
+        due
+    }
+
+    /// Encode the scheduled updates to be saved with their chunk, storing how many ticks after
+    /// `tick` each is due, so that they keep their timing when loaded at another world tick
+    pub fn to_bytes(&self, tick: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ticks.len() * SAVED_TICK_LEN);
+        for &(due_tick, index) in &self.ticks {
+            bytes.extend_from_slice(&due_tick.saturating_sub(tick).to_le_bytes());
+            bytes.extend_from_slice(&index);
+        }
+        bytes
+    }
+
+    /// Decode updates saved by [`ScheduledTicks::to_bytes`], now due relative to `tick`, or
+    /// `None` if the bytes are malformed
+    pub fn from_bytes(bytes: &[u8], tick: u64) -> Option<ScheduledTicks> {
+        let saved = bytes.chunks_exact(SAVED_TICK_LEN);
+        if !saved.remainder().is_empty() {
+            return None;
+        }
+        let mut ticks = ScheduledTicks::default();
+        for saved in saved {
+            let (delay, index) = saved.split_at(8);
+            let delay = u64::from_le_bytes(delay.try_into().ok()?);
+            let index: [u8; 3] = index.try_into().ok()?;
+            if index.iter().any(|&i| i as i32 >= CHUNK_SIZE) {
+                return None;
+            }
+            ticks.ticks.insert((tick.saturating_add(delay), index));
+        }
+        Some(ticks)
+    }
+}
+
+/// The world access given to block tick and update handlers
+pub struct BlockTickContext<'a, 'w, 's> {
+    pub world: &'a mut WorldBlocks<'w, 's>,
+    pub blocks: &'a Blocks,
+    pub rng: &'a mut ThreadRng,
+}
+
+/// Updates a block, given its position and current state
+pub type BlockTickHandler = Box<dyn Fn(&mut BlockTickContext, IVec3, BlockId) + Send + Sync>;
+
+/// The handlers run when blocks of each type are randomly ticked, or their scheduled updates are
+/// due
+#[derive(Resource, Default)]
+pub struct BlockTickHandlers {
+    random: FxHashMap<BlockTypeId, BlockTickHandler>,
+    scheduled: FxHashMap<BlockTypeId, BlockTickHandler>,
+}
+
+impl BlockTickHandlers {
+    /// Set the handler run when a block of the given type is picked by a random tick
+    #[inline]
+    pub fn on_random_tick(
+        &mut self,
+        block_type: BlockTypeId,
+        handler: impl Fn(&mut BlockTickContext, IVec3, BlockId) + Send + Sync + 'static,
+    ) {
+        self.random.insert(block_type, Box::new(handler));
+    }
+
+    /// Set the handler run when an update scheduled for a block of the given type is due
+    #[inline]
+    pub fn on_scheduled_tick(
+        &mut self,
+        block_type: BlockTypeId,
+        handler: impl Fn(&mut BlockTickContext, IVec3, BlockId) + Send + Sync + 'static,
+    ) {
+        self.scheduled.insert(block_type, Box::new(handler));
+    }
+
+    /// Forget the handlers of a block type which is being unregistered
+    #[inline]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) {
+        self.random.remove(&block_type);
+        self.scheduled.remove(&block_type);
+    }
+}
+
+/// Advance the world tick
+pub fn advance_world_tick(mut tick: ResMut<WorldTick>) {
+    tick.0 += 1;
+}
+
+/// Run the handlers of the blocks whose scheduled updates are due, then of [`RandomTickSpeed`]
+/// random blocks in every loaded chunk
+pub fn tick_blocks(
+    blocks: Res<Blocks>,
+    handlers: Res<BlockTickHandlers>,
+    speed: Res<RandomTickSpeed>,
+    mut world: WorldBlocks,
+) {
+    let mut rng = rand::thread_rng();
+    let tick = world.tick();
+    let mut scheduled = Vec::new();
+    let mut random = Vec::new();
+    for coords in world.loaded_chunks() {
+        let origin = ChunkMap::chunk_origin(coords);
+        let Some(mut chunk) = world.chunk_mut(coords) else {
+            continue;
+        };
+        if !chunk.ticks.is_empty() {
+            for [x, y, z] in chunk.ticks.take_due(tick) {
+                scheduled.push(origin + IVec3::new(x as i32, y as i32, z as i32));
+            }
+        }
+        for _ in 0..speed.0 {
+            random.push(origin + IVec3::from_array([(); 3].map(|_| rng.gen_range(0..CHUNK_SIZE))));
+        }
+    }
+
+    let mut context = BlockTickContext {
+        world: &mut world,
+        blocks: &blocks,
+        rng: &mut rng,
+    };
+    for (positions, handlers) in [(scheduled, &handlers.scheduled), (random, &handlers.random)] {
+        for position in positions {
+            let Some(block) = context.world.get(position) else {
+                continue;
+            };
+            let handler = blocks
+                .block_type(block)
+                .and_then(|block_type| handlers.get(&block_type));
+            if let Some(handler) = handler {
+                handler(&mut context, position, block);
+            }
+        }
+    }
+}
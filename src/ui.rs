@@ -7,18 +7,30 @@ use bevy_egui::{
     EguiContexts,
 };
 
-use crate::player::PlayerCamera;
+use crate::{
+    biome::BiomeMap,
+    block::{
+        texture::{BlockMaterials, BlockTextureId, TextureMap, SHEET_HEIGHT},
+        Blocks,
+    },
+    item::Inventory,
+    pack::ResourcePacks,
+    player::PlayerCamera,
+    sky::TimeOfDay,
+};
 
 pub fn ui_system(
     mut contexts: EguiContexts,
     diagnostics: Res<DiagnosticsStore>,
-    query: Query<&Transform, With<PlayerCamera>>,
+    query: Query<(&Transform, &Inventory), With<PlayerCamera>>,
+    biomes: Res<BiomeMap>,
+    mut time_of_day: ResMut<TimeOfDay>,
 ) {
     let ctx = contexts.ctx_mut();
     egui::Window::new("Graphics")
         .default_pos((0.0, 0.0))
-        .show(&*ctx, |ui: &mut egui::Ui| {
-            let player = query.get_single().unwrap();
+        .show(ctx, |ui: &mut egui::Ui| {
+            let (player, inventory) = query.get_single().unwrap();
             ui.label(format!(
                 "pos = ({:.1}, {:.1}, {:.1})",
                 player.translation.x, player.translation.y, player.translation.z
@@ -27,8 +39,270 @@ pub fn ui_system(
                 "rotation = ({:.1}, {:.1}, {:.1}, {:.1})",
                 player.rotation.x, player.rotation.y, player.rotation.z, player.rotation.w
             ));
+            if let Some(biome) = biomes.biome(player.translation.round().as_ivec3()) {
+                ui.label(format!("biome = {}", biomes.get(biome).name));
+            }
             if let Some(fps) = diagnostics.get_measurement(FrameTimeDiagnosticsPlugin::FPS) {
                 ui.label(format!("FPS = {:.1}", fps.value));
             }
+            ui.label(format!("items = {}", inventory.items.values().sum::<u32>()));
+            if let Some((block, count)) = inventory.items.get_index(inventory.selected) {
+                ui.label(format!("selected = {:?} x {}", block, count));
+            }
+            // Only touch the time of day mutably when a slider is dragged, so that the sky is not
+            // relit every frame
+            let mut time = time_of_day.time;
+            if ui
+                .add(egui::Slider::new(&mut time, 0.0..=1.0).text("time of day"))
+                .changed()
+            {
+                time_of_day.time = time;
+            }
+            let mut speed = time_of_day.speed;
+            if ui
+                .add(
+                    egui::Slider::new(&mut speed, 0.0..=100.0)
+                        .logarithmic(true)
+                        .text("day speed"),
+                )
+                .changed()
+            {
+                time_of_day.speed = speed;
+            }
+        });
+}
+
+/// Lists the installed resource packs, allowing them to be enabled and reordered
+pub fn resource_pack_ui(
+    mut contexts: EguiContexts,
+    mut packs: ResMut<ResourcePacks>,
+    asset_server: Res<AssetServer>,
+    mut available: Local<Option<Vec<String>>>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Resource packs")
+        .default_pos((0.0, 200.0))
+        .show(ctx, |ui: &mut egui::Ui| {
+            if ui.button("Refresh").clicked() {
+                *available = None;
+            }
+            let available =
+                available.get_or_insert_with(|| ResourcePacks::available(asset_server.asset_io()));
+            // Only touch the packs mutably when the order actually changes, so that textures are
+            // not needlessly re-resolved
+            let mut order = packs.order().to_vec();
+            let mut changed = false;
+            ui.label("Enabled, highest priority first:");
+            for ix in 0..order.len() {
+                ui.horizontal(|ui| {
+                    ui.label(&order[ix]);
+                    if ui.small_button("^").clicked() && ix > 0 {
+                        order.swap(ix, ix - 1);
+                        changed = true;
+                    }
+                    if ui.small_button("v").clicked() && ix + 1 < order.len() {
+                        order.swap(ix, ix + 1);
+                        changed = true;
+                    }
+                    if ui.small_button("Disable").clicked() {
+                        order[ix].clear();
+                        changed = true;
+                    }
+                });
+            }
+            order.retain(|pack| !pack.is_empty());
+            ui.separator();
+            for pack in available.iter() {
+                if order.contains(pack) {
+                    continue;
+                }
+                ui.horizontal(|ui| {
+                    ui.label(pack);
+                    if ui.small_button("Enable").clicked() {
+                        order.insert(0, pack.clone());
+                        changed = true;
+                    }
+                });
+            }
+            if changed {
+                packs.set_order(order);
+            }
+        });
+}
+
+/// The state of the texture atlas inspector
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtlasInspector {
+    /// The index of the shown sheet
+    sheet: usize,
+    map: TextureMap,
+    zoom: f32,
+    selected: Option<BlockTextureId>,
+}
+
+impl Default for AtlasInspector {
+    fn default() -> Self {
+        AtlasInspector {
+            sheet: 0,
+            map: TextureMap::BaseColor,
+            zoom: 1.0,
+            selected: None,
+        }
+    }
+}
+
+/// Shows the block texture sheets, the images and blocks using each tile, and flags tiles which
+/// were allocated but never had an image blitted into them
+pub fn texture_atlas_ui(
+    mut contexts: EguiContexts,
+    block_materials: Res<BlockMaterials>,
+    blocks: Res<Blocks>,
+    materials: Res<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    mut inspector: Local<AtlasInspector>,
+) {
+    let sheets: Vec<_> = block_materials.sheets().collect();
+    let sheet = sheets.get(inspector.sheet).copied();
+    // Register the sheet with egui before borrowing its context, without keeping the sheet alive
+    let sheet_image = sheet
+        .and_then(|sheet| block_materials.get_sheet_image(sheet, inspector.map, &materials))
+        .map(|image| contexts.add_image(image.clone_weak()));
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Texture atlas")
+        .default_pos((0.0, 400.0))
+        .default_open(false)
+        .show(ctx, |ui: &mut egui::Ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("Sheet")
+                    .selected_text(format!("{}", inspector.sheet))
+                    .show_ui(ui, |ui| {
+                        for (ix, &sheet) in sheets.iter().enumerate() {
+                            let resolution = block_materials.get_sheet_settings(sheet).resolution;
+                            ui.selectable_value(
+                                &mut inspector.sheet,
+                                ix,
+                                format!("{} ({}px)", ix, resolution),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Map")
+                    .selected_text(format!("{:?}", inspector.map))
+                    .show_ui(ui, |ui| {
+                        for map in TextureMap::ALL {
+                            ui.selectable_value(&mut inspector.map, map, format!("{:?}", map));
+                        }
+                    });
+            });
+            ui.add(
+                egui::Slider::new(&mut inspector.zoom, 0.25..=8.0)
+                    .logarithmic(true)
+                    .text("Zoom"),
+            );
+            let Some(sheet) = sheet else {
+                ui.label("No texture sheets");
+                return;
+            };
+
+            let textures: Vec<_> = block_materials.sheet_textures(sheet).collect();
+            // A tile which never received a base color shows whatever the sheet was filled with
+            let unblitted = |texture: BlockTextureId| {
+                !block_materials.is_blitted(texture, TextureMap::BaseColor)
+            };
+            ui.label(format!(
+                "{} textures, {} never blitted",
+                textures.len(),
+                textures
+                    .iter()
+                    .filter(|&&texture| unblitted(texture))
+                    .count()
+            ));
+
+            let tile = block_materials.get_sheet_settings(sheet).resolution as f32 * inspector.zoom;
+            let rows = block_materials.sheet_rows(sheet);
+            egui::ScrollArea::both()
+                .id_source("sheet")
+                .max_height(300.0)
+                .show(ui, |ui| {
+                    let size = egui::vec2(SHEET_HEIGHT as f32, rows as f32) * tile;
+                    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+                    let painter = ui.painter_at(rect);
+                    if let Some(sheet_image) = sheet_image {
+                        let uv = egui::Rect::from_min_max(
+                            egui::pos2(0.0, 0.0),
+                            egui::pos2(1.0, rows as f32 / SHEET_HEIGHT as f32),
+                        );
+                        painter.image(sheet_image, rect, uv, egui::Color32::WHITE);
+                    } else {
+                        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(32));
+                    }
+                    let tile_rect = |texture: BlockTextureId| {
+                        let coords = texture.coords();
+                        let min = rect.min
+                            + egui::vec2(coords.x_ix() as f32, coords.y_ix() as f32) * tile;
+                        egui::Rect::from_min_size(min, egui::vec2(tile, tile))
+                    };
+                    for &texture in &textures {
+                        if unblitted(texture) {
+                            painter.rect_stroke(tile_rect(texture), 0.0, (1.0, egui::Color32::RED));
+                        }
+                    }
+                    if let Some(selected) = inspector.selected.filter(|s| s.sheet() == sheet) {
+                        painter.rect_stroke(tile_rect(selected), 0.0, (2.0, egui::Color32::YELLOW));
+                    }
+                    if let Some(pointer) = response.interact_pointer_pos() {
+                        if response.clicked() {
+                            let offset = (pointer - rect.min) / tile;
+                            inspector.selected = textures.iter().copied().find(|texture| {
+                                let coords = texture.coords();
+                                coords.x_ix() as usize == offset.x as usize
+                                    && coords.y_ix() as usize == offset.y as usize
+                            });
+                        }
+                    }
+                });
+
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .id_source("textures")
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for &texture in &textures {
+                        let mut label = format!("{:?}", texture);
+                        if unblitted(texture) {
+                            label.push_str(" (never blitted)");
+                        }
+                        ui.selectable_value(&mut inspector.selected, Some(texture), label);
+                    }
+                });
+
+            let Some(selected) = inspector.selected else {
+                return;
+            };
+            ui.separator();
+            ui.label(format!(
+                "{:?} at ({}, {})",
+                selected,
+                selected.coords().x_ix(),
+                selected.coords().y_ix()
+            ));
+            for map in TextureMap::ALL {
+                let image = match block_materials.get_block_map(selected, map) {
+                    Some(image) => match asset_server.get_handle_path(image) {
+                        Some(path) => format!("{:?}", path.path()),
+                        None => format!("{:?}", image.id()),
+                    },
+                    None => "none".to_owned(),
+                };
+                let status = if block_materials.is_blitted(selected, map) {
+                    "blitted"
+                } else {
+                    "never blitted"
+                };
+                ui.label(format!("{:?}: {} ({})", map, image, status));
+            }
+            ui.label(format!(
+                "used by {:?}",
+                blocks.blocks_with_texture(selected)
+            ));
         });
 }
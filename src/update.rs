@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use fxhash::{FxHashMap, FxHashSet};
+
+use crate::{
+    block::{face::Face, BlockId, BlockTypeId, Blocks},
+    chunk::{map::WorldBlocks, BlockChanged},
+    tick::BlockTickContext,
+};
+
+/// Updates a block after it or one of its neighbours changed, given its position, its current
+/// state and the position of the block which changed
+pub type BlockUpdateHandler =
+    Box<dyn Fn(&mut BlockTickContext, IVec3, BlockId, IVec3) + Send + Sync>;
+
+/// The handlers run when blocks of each type, or their neighbours, change
+#[derive(Resource, Default)]
+pub struct BlockUpdateHandlers {
+    handlers: FxHashMap<BlockTypeId, BlockUpdateHandler>,
+}
+
+impl BlockUpdateHandlers {
+    /// Set the handler run when a block of the given type, or one of its neighbours, changes
+    #[inline]
+    pub fn on_update(
+        &mut self,
+        block_type: BlockTypeId,
+        handler: impl Fn(&mut BlockTickContext, IVec3, BlockId, IVec3) + Send + Sync + 'static,
+    ) {
+        self.handlers.insert(block_type, Box::new(handler));
+    }
+
+    /// Forget the handler of a block type which is being unregistered
+    #[inline]
+    pub fn remove_block_type(&mut self, block_type: BlockTypeId) {
+        self.handlers.remove(&block_type);
+    }
+}
+
+/// The block updates waiting to be run.
+///
+/// Each world tick runs at most `budget` updates, leaving the rest for later ticks, and updates
+/// caused by handlers only run on the next tick, so that a cascade of updates which never settles
+/// spreads over many ticks instead of hanging the game.
+#[derive(Resource, Debug, Clone)]
+pub struct BlockUpdates {
+    /// The most updates run each world tick
+    pub budget: usize,
+    /// The position of each block to update, and the position of the block which changed
+    queue: VecDeque<(IVec3, IVec3)>,
+    queued: FxHashSet<(IVec3, IVec3)>,
+}
+
+impl Default for BlockUpdates {
+    #[inline]
+    fn default() -> Self {
+        BlockUpdates {
+            budget: 4096,
+            queue: VecDeque::new(),
+            queued: FxHashSet::default(),
+        }
+    }
+}
+
+impl BlockUpdates {
+    /// Queue updates for a changed block and its six neighbours
+    pub fn notify(&mut self, position: IVec3) {
+        let targets = [IVec3::ZERO]
+            .into_iter()
+            .chain(Face::ALL.map(Face::offset))
+            .map(|offset| position + offset);
+        for target in targets {
+            if self.queued.insert((target, position)) {
+                self.queue.push_back((target, position));
+            }
+        }
+    }
+
+    /// Get the number of updates waiting to be run
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Get whether no updates are waiting to be run
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Remove and return the next update to run
+    #[inline]
+    fn pop(&mut self) -> Option<(IVec3, IVec3)> {
+        let update = self.queue.pop_front()?;
+        self.queued.remove(&update);
+        Some(update)
+    }
+}
+
+/// Queue updates around every block which changed
+pub fn queue_block_updates(
+    mut changed: EventReader<BlockChanged>,
+    mut updates: ResMut<BlockUpdates>,
+) {
+    for event in changed.iter() {
+        updates.notify(event.position);
+    }
+}
+
+/// Run the handlers of up to [`BlockUpdates::budget`] queued updates
+pub fn run_block_updates(
+    blocks: Res<Blocks>,
+    handlers: Res<BlockUpdateHandlers>,
+    mut updates: ResMut<BlockUpdates>,
+    mut world: WorldBlocks,
+) {
+    let mut rng = rand::thread_rng();
+    let mut context = BlockTickContext {
+        world: &mut world,
+        blocks: &blocks,
+        rng: &mut rng,
+    };
+    for _ in 0..updates.budget {
+        let Some((position, source)) = updates.pop() else {
+            break;
+        };
+        let Some(block) = context.world.get(position) else {
+            continue;
+        };
+        let handler = blocks
+            .block_type(block)
+            .and_then(|block_type| handlers.handlers.get(&block_type));
+        if let Some(handler) = handler {
+            handler(&mut context, position, block, source);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    biome::BiomeMap,
+    block::{variant::position_hash, BlockId},
+    chunk::{map::ChunkMap, ChunkData, CHUNK_SIZE},
+    noise::{fractal_noise, fractal_noise_3d},
+};
+
+const HEIGHT_SALT: u32 = 0x7E44_A1B0;
+const CHEESE_SALT: u32 = 0xC4EE_5E00;
+const SPAGHETTI_SALTS: [u32; 2] = [0x5BA6_E771, 0x5BA6_E772];
+const ORE_SALT: u32 = 0x0BE5_0000;
+/// The furthest the random walk tracing a vein can move in one step: its direction, plus the
+/// largest jitter along a diagonal
+const MAX_VEIN_STEP: f32 = 0.5 + 0.8 * 1.7321;
+
+/// The shape of the terrain before caves are carved into it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainSettings {
+    /// The average height of the surface, where the world has no biomes
+    pub base_height: f32,
+    /// How far the surface rises and falls from its average height, where the world has no biomes
+    pub amplitude: f32,
+    /// The approximate width, in blocks, of hills
+    pub scale: f32,
+    /// The number of layers of subsurface blocks below the surface
+    pub dirt_depth: i32,
+}
+
+impl Default for TerrainSettings {
+    #[inline]
+    fn default() -> Self {
+        TerrainSettings {
+            base_height: -8.0,
+            amplitude: 6.0,
+            scale: 48.0,
+            dirt_depth: 2,
+        }
+    }
+}
+
+/// The caves carved out of the terrain: open "cheese" caverns wherever one 3D noise is high, and
+/// winding "spaghetti" tunnels wherever two 3D noises are both close to their midpoint
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CaveSettings {
+    /// The approximate size, in blocks, of caverns
+    pub cheese_scale: f32,
+    /// The noise above which caverns are carved, from `0.0` to `1.0`, with fewer caverns the
+    /// higher it is
+    pub cheese_threshold: f32,
+    /// How far below the surface caverns begin, so that they don't swallow the surface
+    pub cheese_depth: i32,
+    /// The approximate length, in blocks, of each bend in a tunnel
+    pub spaghetti_scale: f32,
+    /// How far from their midpoint both noises may be for a tunnel to be carved, which sets the
+    /// width of tunnels
+    pub spaghetti_width: f32,
+}
+
+impl Default for CaveSettings {
+    #[inline]
+    fn default() -> Self {
+        CaveSettings {
+            cheese_scale: 24.0,
+            cheese_threshold: 0.68,
+            cheese_depth: 6,
+            spaghetti_scale: 32.0,
+            spaghetti_width: 0.035,
+        }
+    }
+}
+
+/// A kind of ore, placed in veins which replace stone
+#[derive(Debug, Clone, PartialEq)]
+pub struct OreVein {
+    pub block: BlockId,
+    /// The number of steps of the random walk tracing each vein, roughly its number of blocks
+    pub size: u32,
+    /// The heights veins may start at
+    pub heights: Range<i32>,
+    /// The average number of veins started in each chunk lying within `heights`
+    pub frequency: f32,
+}
+
+/// Generates the blocks of chunks in passes, each deterministic from the world seed and the
+/// chunk's coordinates: the base terrain, shaped by the biomes of the world, then caves carved out
+/// of it, then veins of ore, which may wander in from neighbouring chunks
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct WorldGenerator {
+    pub seed: u32,
+    pub stone: BlockId,
+    /// The subsurface block, where the world has no biomes
+    pub dirt: BlockId,
+    /// The surface block, where the world has no biomes
+    pub grass: BlockId,
+    pub terrain: TerrainSettings,
+    pub caves: CaveSettings,
+    ores: Vec<OreVein>,
+}
+
+impl WorldGenerator {
+    /// Create a generator building terrain from the given blocks, with default settings and no
+    /// ores
+    #[inline]
+    pub fn new(seed: u32, stone: BlockId, dirt: BlockId, grass: BlockId) -> WorldGenerator {
+        WorldGenerator {
+            seed,
+            stone,
+            dirt,
+            grass,
+            terrain: default(),
+            caves: default(),
+            ores: Vec::new(),
+        }
+    }
+
+    /// Add a kind of ore, failing if its frequency is negative or not finite
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn add_ore(&mut self, ore: OreVein) -> Result<(), ()> {
+        if !ore.frequency.is_finite() || ore.frequency < 0.0 {
+            return Err(());
+        }
+        self.ores.push(ore);
+        Ok(())
+    }
+
+    /// Get the height of the surface block at the top of a column of terrain, before caves are
+    /// carved, with the height and roughness of the terrain blended between biomes
+    pub fn surface_height(&self, biomes: &BiomeMap, x: i32, z: i32) -> i32 {
+        let column = IVec3::new(x, 0, z);
+        let base_height = biomes
+            .blend(column, |biome| biome.base_height)
+            .unwrap_or(self.terrain.base_height);
+        let amplitude = biomes
+            .blend(column, |biome| biome.amplitude)
+            .unwrap_or(self.terrain.amplitude);
+        let point = Vec2::new(x as f32, z as f32) / self.terrain.scale;
+        let noise = fractal_noise(self.seed ^ HEIGHT_SALT, point, 4);
+        (base_height + amplitude * (2.0 * noise - 1.0)).round() as i32
+    }
+
+    /// Generate the blocks of the chunk at the given chunk coordinates
+    pub fn generate(&self, biomes: &BiomeMap, coords: IVec3) -> ChunkData {
+        let origin = ChunkMap::chunk_origin(coords);
+        let mut chunk = ChunkData::default();
+        let mut surface = [[0; 16]; 16];
+        for (x, column) in surface.iter_mut().enumerate() {
+            for (z, height) in column.iter_mut().enumerate() {
+                *height = self.surface_height(biomes, origin.x + x as i32, origin.z + z as i32);
+            }
+        }
+        self.place_terrain(&mut chunk, biomes, origin, &surface);
+        self.carve_caves(&mut chunk, origin, &surface);
+        self.place_ores(&mut chunk, coords);
+        chunk
+    }
+
+    /// Fill each column with stone, then its biome's subsurface blocks, up to its biome's surface
+    /// block
+    fn place_terrain(
+        &self,
+        chunk: &mut ChunkData,
+        biomes: &BiomeMap,
+        origin: IVec3,
+        surface: &[[i32; 16]; 16],
+    ) {
+        for (x, column) in surface.iter().enumerate() {
+            for (z, &height) in column.iter().enumerate() {
+                let position = origin + IVec3::new(x as i32, 0, z as i32);
+                let (top, below) = match biomes.biome(position) {
+                    Some(biome) => {
+                        let biome = biomes.get(biome);
+                        (biome.surface, biome.subsurface)
+                    }
+                    None => (self.grass, self.dirt),
+                };
+                for y in 0..16 {
+                    let world_y = origin.y + y as i32;
+                    if world_y > height {
+                        break;
+                    }
+                    chunk.blocks[x][y][z] = if world_y == height {
+                        top
+                    } else if world_y >= height - self.terrain.dirt_depth {
+                        below
+                    } else {
+                        self.stone
+                    };
+                }
+            }
+        }
+    }
+
+    /// Get whether a block below the surface of its column is carved out by a cave
+    pub fn is_cave(&self, position: IVec3, surface: i32) -> bool {
+        let caves = &self.caves;
+        let point = position.as_vec3();
+        if position.y < surface - caves.cheese_depth {
+            let cheese = fractal_noise_3d(self.seed ^ CHEESE_SALT, point / caves.cheese_scale, 2);
+            if cheese > caves.cheese_threshold {
+                return true;
+            }
+        }
+        // Tunnels are squashed vertically, so that they wind mostly sideways
+        let point = point * Vec3::new(1.0, 2.0, 1.0) / caves.spaghetti_scale;
+        SPAGHETTI_SALTS.iter().all(|&salt| {
+            let noise = fractal_noise_3d(self.seed ^ salt, point, 2);
+            (noise - 0.5).abs() < caves.spaghetti_width
+        })
+    }
+
+    /// Replace the blocks of the terrain lying inside caves with air
+    fn carve_caves(&self, chunk: &mut ChunkData, origin: IVec3, surface: &[[i32; 16]; 16]) {
+        for (x, column) in surface.iter().enumerate() {
+            for (z, &height) in column.iter().enumerate() {
+                for y in 0..16 {
+                    if chunk.blocks[x][y][z] == BlockId::default() {
+                        continue;
+                    }
+                    let position = origin + IVec3::new(x as i32, y as i32, z as i32);
+                    if self.is_cave(position, height) {
+                        chunk.blocks[x][y][z] = BlockId::default();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Trace veins of each ore as random walks, replacing the stone they pass through in the chunk.
+    ///
+    /// Veins are seeded by the chunk they start in, so the veins of every chunk close enough to
+    /// reach this one are traced, and each is written identically into every chunk it crosses.
+    fn place_ores(&self, chunk: &mut ChunkData, coords: IVec3) {
+        let origin = ChunkMap::chunk_origin(coords);
+        for (ix, ore) in self.ores.iter().enumerate() {
+            let reach = (ore.size as f32 * MAX_VEIN_STEP).ceil() as i32;
+            let radius = (reach + CHUNK_SIZE - 1) / CHUNK_SIZE;
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    for dz in -radius..=radius {
+                        let start = coords + IVec3::new(dx, dy, dz);
+                        self.trace_veins(ix, ore, start, |position| {
+                            let cell = position - origin;
+                            if cell.cmpge(IVec3::ZERO).all()
+                                && cell.cmplt(IVec3::splat(CHUNK_SIZE)).all()
+                            {
+                                let [x, y, z] = cell.to_array().map(|i| i as usize);
+                                let block = &mut chunk.blocks[x][y][z];
+                                if *block == self.stone {
+                                    *block = ore.block;
+                                }
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Trace the veins of an ore starting in the chunk at the given chunk coordinates, calling
+    /// `place` with the world position of each block they pass through
+    fn trace_veins(&self, ix: usize, ore: &OreVein, coords: IVec3, mut place: impl FnMut(IVec3)) {
+        let origin = ChunkMap::chunk_origin(coords);
+        let seed = position_hash(coords, self.seed ^ ORE_SALT) as u64;
+        let mut rng = StdRng::seed_from_u64(seed << 32 | ix as u64);
+        let whole = ore.frequency.floor() as u32;
+        let veins = whole + rng.gen_bool(ore.frequency.fract() as f64) as u32;
+        for _ in 0..veins {
+            let start = IVec3::from_array([(); 3].map(|_| rng.gen_range(0..CHUNK_SIZE)));
+            if !ore.heights.contains(&(origin.y + start.y)) {
+                continue;
+            }
+            let mut point = (origin + start).as_vec3();
+            let direction = Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-0.5..0.5),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize_or_zero()
+                * 0.5;
+            for _ in 0..ore.size {
+                place(point.round().as_ivec3());
+                let jitter = Vec3::from_array([(); 3].map(|_| rng.gen_range(-0.8..0.8)));
+                point += direction + jitter;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{state::StateValues, Blocks, MeshingData};
+
+    fn solid(_: StateValues) -> MeshingData {
+        MeshingData {
+            opacity: u32::MAX,
+            solid: true,
+            ..default()
+        }
+    }
+
+    /// Create a generator, and the block to place its ores with
+    fn generator() -> (WorldGenerator, BlockId) {
+        let mut blocks = Blocks::default();
+        let [stone, dirt, grass, ore] = [(); 4].map(|_| {
+            let block_type = blocks.new_block_type(&[], solid).unwrap();
+            blocks.default_state(block_type)
+        });
+        (WorldGenerator::new(7, stone, dirt, grass), ore)
+    }
+
+    /// Create a generator with an ore, over a world with no biomes
+    fn generator_with_ore(seed: u32) -> WorldGenerator {
+        let (mut generator, block) = generator();
+        generator.seed = seed;
+        let ore = OreVein {
+            block,
+            size: 12,
+            heights: -64..0,
+            frequency: 2.5,
+        };
+        generator.add_ore(ore).unwrap();
+        generator
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let biomes = BiomeMap::default();
+        let coords = [
+            IVec3::new(0, -1, 0),
+            IVec3::new(-3, -2, 5),
+            IVec3::new(7, 0, -4),
+        ];
+        for seed in [0, 42] {
+            let (a, b) = (generator_with_ore(seed), generator_with_ore(seed));
+            for coords in coords {
+                assert_eq!(a.generate(&biomes, coords), b.generate(&biomes, coords));
+                // Generating other chunks in between changes nothing
+                a.generate(&biomes, coords + IVec3::X);
+                assert_eq!(a.generate(&biomes, coords), b.generate(&biomes, coords));
+            }
+        }
+    }
+
+    #[test]
+    fn generation_depends_on_seed_and_coordinates() {
+        let biomes = BiomeMap::default();
+        let (a, b) = (generator_with_ore(0), generator_with_ore(42));
+        let coords = IVec3::new(0, -1, 0);
+        assert_ne!(a.generate(&biomes, coords), b.generate(&biomes, coords));
+        assert_ne!(
+            a.generate(&biomes, coords),
+            a.generate(&biomes, coords + IVec3::X)
+        );
+    }
+
+    #[test]
+    fn add_ore_rejects_bad_frequencies() {
+        let (mut generator, block) = generator();
+        for frequency in [-1.0, f32::NAN, f32::INFINITY] {
+            let ore = OreVein {
+                block,
+                size: 8,
+                heights: -64..64,
+                frequency,
+            };
+            assert!(generator.add_ore(ore).is_err());
+        }
+        assert!(generator.ores.is_empty());
+    }
+
+    #[test]
+    fn ore_veins_cross_chunk_borders() {
+        let (mut generator, block) = generator();
+        let ore = OreVein {
+            block,
+            size: 24,
+            heights: -64..64,
+            frequency: 3.0,
+        };
+        generator.add_ore(ore.clone()).unwrap();
+        let mut veins = Vec::new();
+        generator.trace_veins(0, &ore, IVec3::ZERO, |position| veins.push(position));
+        assert!(veins
+            .iter()
+            .any(|&position| ChunkMap::chunk_coords(position).0 != IVec3::ZERO));
+
+        for position in veins {
+            let (coords, [x, y, z]) = ChunkMap::chunk_coords(position);
+            let mut chunk = ChunkData {
+                blocks: [[[generator.stone; 16]; 16]; 16],
+            };
+            generator.place_ores(&mut chunk, coords);
+            assert_eq!(chunk.blocks[x][y][z], ore.block);
+        }
+    }
+}